@@ -0,0 +1,588 @@
+//! Map with a limited capacity that evicts entries when it is full.
+//!
+//! All entries are ordered by a rank that depends on the [EvictionPolicy].
+//! When the map is full, the entry with the lowest rank is evicted.
+//!
+//! - [EvictionPolicy::Lru] ranks by the time of the last access
+//! - [EvictionPolicy::Lfu] ranks by the number of accesses, entries with the
+//!   same number are ranked by the time of the last access
+//! - [EvictionPolicy::Random] ranks by a random number that is set on insert
+
+use crate::{Del, Get2, Key, Parse, Set2};
+
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Mutex;
+
+/// Limit of a [BoundedMap]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capacity {
+    /// Maximum number of entries
+    Entries(usize),
+    /// Maximum number of bytes, the size of an entry is estimated with the
+    /// weigher of the map, see [BoundedMap::weigher]
+    Bytes(usize),
+}
+
+/// Decides which entry is evicted when a [BoundedMap] is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evicts the least recently used entry
+    Lru,
+    /// Evicts the least frequently used entry
+    Lfu,
+    /// Evicts a random entry
+    Random,
+}
+
+/// Estimates the size of an entry in bytes
+pub type Weigher<K, V> = fn(&K, &V) -> usize;
+
+/// Map with a limited capacity, see the module documentation.
+///
+/// Implements [Get2], [Set2], [Del] and [Key], so it can be used as storage
+/// inside a [crate::Cache] implementation.
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let map = BoundedMap::new(Capacity::Entries(2), EvictionPolicy::Lru);
+/// map.set(1u32, 10u32).await;
+/// map.set(2u32, 20u32).await;
+///
+/// // 1 is now used more recently than 2
+/// assert_eq!(map.get(1u32).await, Some(10u32));
+///
+/// map.set(3u32, 30u32).await;
+/// assert_eq!(map.get(2u32).await, None);
+/// assert_eq!(map.evictions(), 1u64);
+/// # }
+/// ```
+pub struct BoundedMap<K, V> {
+    /// Limit of the map
+    capacity: Capacity,
+    /// Decides which entry is evicted
+    policy:   EvictionPolicy,
+    /// Estimates the size of an entry
+    weigher:  Weigher<K, V>,
+    /// Entries and their order
+    inner:    Mutex<Inner<K, V>>,
+}
+
+/// Entries of a [BoundedMap] and their eviction order
+struct Inner<K, V> {
+    /// All entries
+    entries:   HashMap<K, Entry<V>>,
+    /// Keys ordered by their rank, the first key is evicted first
+    order:     BTreeMap<Rank, K>,
+    /// Sum of the weight of all entries
+    weight:    usize,
+    /// Increased on every access, used for ordering by time
+    tick:      u64,
+    /// Number of entries that were evicted
+    evictions: u64,
+    /// State of the random number generator
+    rng:       u64,
+}
+
+/// Position of an entry in the eviction order, the lowest is evicted first
+type Rank = (u64, u64);
+
+/// Single entry of a [BoundedMap]
+struct Entry<V> {
+    /// Stored value
+    value:  V,
+    /// Estimated size of the entry
+    weight: usize,
+    /// Position in the eviction order
+    rank:   Rank,
+    /// Number of accesses
+    hits:   u64,
+}
+
+impl<K, V> BoundedMap<K, V>
+where
+    K: Clone + Eq + Hash {
+
+    /// Creates a new empty map
+    ///
+    /// # Params
+    ///
+    /// * `capacity` - Limit of the map
+    /// * `policy`   - Decides which entry is evicted
+    ///
+    pub fn new(capacity: Capacity, policy: EvictionPolicy) -> Self {
+        let mut seed = RandomState::new().build_hasher();
+        seed.write_u8(0u8);
+
+        Self {
+            capacity,
+            policy,
+            weigher: |_, _| std::mem::size_of::<K>() + std::mem::size_of::<V>(),
+            inner:   Mutex::new(Inner {
+                entries:   HashMap::new(),
+                order:     BTreeMap::new(),
+                weight:    0usize,
+                tick:      0u64,
+                evictions: 0u64,
+                // xorshift must not start with 0
+                rng:       seed.finish() | 1,
+            }),
+        }
+    }
+
+    /// Sets the function that estimates the size of an entry, only used with
+    /// [Capacity::Bytes]. Defaults to the stack size of the key and value.
+    ///
+    /// # Params
+    ///
+    /// * `weigher` - Estimates the size of an entry in bytes
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use cachem::*;
+    /// let map = BoundedMap::<u32, String>::new(Capacity::Bytes(1024), EvictionPolicy::Lfu)
+    ///     .weigher(|_, v| 4 + v.len());
+    /// ```
+    ///
+    pub fn weigher(mut self, weigher: Weigher<K, V>) -> Self {
+        self.weigher = weigher;
+        self
+    }
+
+    /// Inserts a new entry, evicting other entries until it fits.
+    ///
+    /// Entries that are larger than the capacity are not inserted and count
+    /// as evicted.
+    ///
+    /// # Params
+    ///
+    /// * `key`   - Key of the entry
+    /// * `value` - Value of the entry
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    /// # Returns
+    ///
+    /// The previous value of the key
+    ///
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let weight = self.weight(&key, &value);
+        let mut inner = self.inner.lock().unwrap();
+
+        let previous = inner.remove(&key);
+        if weight > self.limit() {
+            inner.evictions += 1;
+            return previous.map(|x| x.value);
+        }
+
+        while inner.weight + weight > self.limit() {
+            if !inner.evict() {
+                break;
+            }
+        }
+
+        let rank = inner.rank(self.policy, 1);
+        inner.order.insert(rank, key.clone());
+        inner.weight += weight;
+        inner.entries.insert(key, Entry {
+            value,
+            weight,
+            rank,
+            hits: 1,
+        });
+
+        previous.map(|x| x.value)
+    }
+
+    /// Removes an entry
+    ///
+    /// # Params
+    ///
+    /// * `key` - Key of the entry
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    /// # Returns
+    ///
+    /// The removed value
+    ///
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.inner
+            .lock()
+            .unwrap()
+            .remove(key)
+            .map(|x| x.value)
+    }
+
+    /// Checks if the key exists, without counting it as access
+    ///
+    /// # Params
+    ///
+    /// * `key` - Key of the entry
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .contains_key(key)
+    }
+
+    /// # Returns
+    ///
+    /// Number of entries that were evicted since the map was created
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    pub fn evictions(&self) -> u64 {
+        self.inner.lock().unwrap().evictions
+    }
+
+    /// # Returns
+    ///
+    /// Number of entries
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// # Returns
+    ///
+    /// `true` if there are no entries
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// # Returns
+    ///
+    /// Estimated size of all entries in bytes
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    pub fn size(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        match self.capacity {
+            Capacity::Entries(_) => inner.entries
+                .iter()
+                .map(|(k, v)| (self.weigher)(k, &v.value))
+                .sum(),
+            Capacity::Bytes(_)   => inner.weight,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// All keys, without counting them as access
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    pub fn keys(&self) -> Vec<K> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    }
+
+    /// Gets the weight of an entry, the weight is compared with the limit of
+    /// the capacity
+    fn weight(&self, key: &K, value: &V) -> usize {
+        match self.capacity {
+            Capacity::Entries(_) => 1,
+            Capacity::Bytes(_)   => (self.weigher)(key, value),
+        }
+    }
+
+    /// Gets the limit of the capacity
+    fn limit(&self) -> usize {
+        match self.capacity {
+            Capacity::Entries(x) |
+            Capacity::Bytes(x)   => x,
+        }
+    }
+}
+
+impl<K, V> BoundedMap<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone {
+
+    /// Gets the value of a key and counts it as access
+    ///
+    /// # Params
+    ///
+    /// * `key` - Key of the entry
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it accessed the map
+    ///
+    /// # Returns
+    ///
+    /// Copy of the value, `None` if the key does not exist
+    ///
+    pub fn get_cloned(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.touch(key, self.policy);
+        inner.entries.get(key).map(|x| x.value.clone())
+    }
+}
+
+impl<K, V> Inner<K, V>
+where
+    K: Clone + Eq + Hash {
+
+    /// Creates a new rank
+    ///
+    /// # Params
+    ///
+    /// * `policy` - Policy of the map
+    /// * `hits`   - Number of accesses of the entry
+    ///
+    fn rank(&mut self, policy: EvictionPolicy, hits: u64) -> Rank {
+        self.tick += 1;
+        match policy {
+            EvictionPolicy::Lru    => (0, self.tick),
+            EvictionPolicy::Lfu    => (hits, self.tick),
+            EvictionPolicy::Random => {
+                // xorshift64
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                (self.rng, self.tick)
+            },
+        }
+    }
+
+    /// Counts an access of the given key and moves it in the eviction order
+    fn touch(&mut self, key: &K, policy: EvictionPolicy) {
+        if policy == EvictionPolicy::Random {
+            return;
+        }
+
+        let (old, hits) = match self.entries.get(key) {
+            Some(x) => (x.rank, x.hits.saturating_add(1)),
+            None    => return,
+        };
+        let rank = self.rank(policy, hits);
+
+        if let Some(key) = self.order.remove(&old) {
+            self.order.insert(rank, key);
+        }
+        if let Some(x) = self.entries.get_mut(key) {
+            x.rank = rank;
+            x.hits = hits;
+        }
+    }
+
+    /// Removes an entry
+    fn remove(&mut self, key: &K) -> Option<Entry<V>> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.rank);
+        self.weight -= entry.weight;
+        Some(entry)
+    }
+
+    /// Evicts the entry with the lowest rank
+    ///
+    /// # Returns
+    ///
+    /// `false` if there was no entry to evict
+    ///
+    fn evict(&mut self) -> bool {
+        let key = match self.order.values().next() {
+            Some(x) => x.clone(),
+            None    => return false,
+        };
+
+        self.remove(&key);
+        self.evictions += 1;
+        true
+    }
+}
+
+#[async_trait]
+impl<K, V> Get2<K, V> for BoundedMap<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    async fn get(&self, id: K) -> Option<V> {
+        self.get_cloned(&id)
+    }
+}
+
+#[async_trait]
+impl<K, V> Set2<K, V> for BoundedMap<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Parse + Send + Sync + 'static {
+
+    async fn set(&self, id: K, val: V) {
+        self.insert(id, val);
+    }
+}
+
+#[async_trait]
+impl<K, V> Del for BoundedMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync,
+    V: Send {
+
+    type Id = K;
+
+    async fn del(&self, id: K) {
+        self.remove(&id);
+    }
+}
+
+#[async_trait]
+impl<K, V> Key for BoundedMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync,
+    V: Send {
+
+    type Id = K;
+
+    async fn keys(&self) -> Vec<K> {
+        BoundedMap::keys(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction_order() {
+        let map = BoundedMap::new(Capacity::Entries(3), EvictionPolicy::Lru);
+        map.insert(1u32, 1u32);
+        map.insert(2u32, 2u32);
+        map.insert(3u32, 3u32);
+
+        // order from least to most recently used: 2, 3, 1
+        assert_eq!(map.get_cloned(&1u32), Some(1u32));
+        // does not count as access
+        assert!(map.contains_key(&2u32));
+
+        map.insert(4u32, 4u32);
+        assert!(!map.contains_key(&2u32));
+        map.insert(5u32, 5u32);
+        assert!(!map.contains_key(&3u32));
+        map.insert(6u32, 6u32);
+        assert!(!map.contains_key(&1u32));
+
+        assert_eq!(map.evictions(), 3u64);
+        let mut keys = map.keys();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![4u32, 5u32, 6u32]);
+    }
+
+    #[test]
+    fn test_lfu_eviction_order() {
+        let map = BoundedMap::new(Capacity::Entries(3), EvictionPolicy::Lfu);
+        map.insert(1u32, 1u32);
+        map.insert(2u32, 2u32);
+        map.insert(3u32, 3u32);
+
+        for _ in 0..3 {
+            map.get_cloned(&1u32);
+        }
+        map.get_cloned(&2u32);
+        map.get_cloned(&3u32);
+        map.get_cloned(&3u32);
+
+        // 2 has fewer accesses than 3 and 1
+        map.insert(4u32, 4u32);
+        assert!(!map.contains_key(&2u32));
+
+        // 4 was only inserted
+        map.insert(5u32, 5u32);
+        assert!(!map.contains_key(&4u32));
+        for _ in 0..5 {
+            map.get_cloned(&5u32);
+        }
+        map.insert(6u32, 6u32);
+        assert!(!map.contains_key(&3u32));
+        assert!(map.contains_key(&1u32));
+        assert!(map.contains_key(&5u32));
+    }
+
+    #[test]
+    fn test_lfu_ties_are_ordered_by_time() {
+        let map = BoundedMap::new(Capacity::Entries(2), EvictionPolicy::Lfu);
+        map.insert(1u32, 1u32);
+        map.insert(2u32, 2u32);
+        map.get_cloned(&2u32);
+        map.get_cloned(&1u32);
+
+        // both have two accesses, 2 was used before 1
+        map.insert(3u32, 3u32);
+        assert!(map.contains_key(&1u32));
+        assert!(!map.contains_key(&2u32));
+    }
+
+    #[test]
+    fn test_random_keeps_capacity() {
+        let map = BoundedMap::new(Capacity::Entries(10), EvictionPolicy::Random);
+        for i in 0..100u32 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.evictions(), 90u64);
+    }
+
+    #[test]
+    fn test_bytes_capacity() {
+        let map = BoundedMap::<u32, String>::new(Capacity::Bytes(10), EvictionPolicy::Lru)
+            .weigher(|_, v| v.len());
+        map.insert(1u32, "aaaa".into());
+        map.insert(2u32, "bbbb".into());
+        assert_eq!(map.size(), 8usize);
+
+        // both older entries have to be evicted
+        map.insert(3u32, "cccccccc".into());
+        assert_eq!(map.keys(), vec![3u32]);
+        assert_eq!(map.size(), 8usize);
+
+        // larger than the capacity
+        assert_eq!(map.insert(4u32, "d".repeat(11)), None);
+        assert!(!map.contains_key(&4u32));
+        assert_eq!(map.evictions(), 3u64);
+
+        // replacing an entry frees its weight first
+        assert_eq!(map.insert(3u32, "ee".into()), Some("cccccccc".into()));
+        assert_eq!(map.size(), 2usize);
+        assert_eq!(map.remove(&3u32), Some("ee".into()));
+        assert!(map.is_empty());
+        assert_eq!(map.size(), 0usize);
+    }
+}
@@ -0,0 +1,214 @@
+//! Encodings for fields that should not use their [crate::Parse]
+//! implementation.
+//!
+//! A codec is a module with the functions `read` and `write`. It is used with
+//! `#[cachem(with = "module")]` on a field of a struct that derives `Parse`.
+//!
+//! ```
+//! # use cachem::*;
+//! /// Stores a timestamp in milliseconds as seconds
+//! mod as_secs {
+//!     use cachem::{CachemError, Parse};
+//!     use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+//!
+//!     pub async fn read<B>(buf: &mut B) -> Result<u64, CachemError>
+//!     where
+//!         B: AsyncBufRead + AsyncRead + Send + Unpin {
+//!         Ok(u32::read(buf).await? as u64 * 1000)
+//!     }
+//!
+//!     pub async fn write<B>(val: &u64, buf: &mut B) -> Result<(), CachemError>
+//!     where
+//!         B: AsyncWrite + Send + Unpin {
+//!         ((*val / 1000) as u32).write(buf).await
+//!     }
+//! }
+//!
+//! #[derive(Debug, Parse, PartialEq)]
+//! struct Entry {
+//!     #[cachem(with = "cachem::codec::varint")]
+//!     id:      u32,
+//!     #[cachem(with = "as_secs")]
+//!     created: u64,
+//!     #[cachem(with = "cachem::codec::bytes")]
+//!     data:    Vec<u8>,
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let entry = Entry { id: 1u32, created: 5_000u64, data: vec![1u8, 2u8] };
+//! let mut buf = Vec::new();
+//! entry.write(&mut buf).await.unwrap();
+//! assert_eq!(buf, vec![1, 0, 0, 0, 5, 2, 1, 2]);
+//!
+//! let read = Entry::read(&mut std::io::Cursor::new(buf)).await.unwrap();
+//! assert_eq!(read, entry);
+//! # }
+//! ```
+
+/// Unsigned integers as LEB128 varint.
+///
+/// Every byte contains 7 bits of the value, the highest bit is set if
+/// another byte follows. Values below 128 take a single byte, a [u64] takes
+/// at most 10 bytes.
+pub mod varint {
+    use crate::CachemError;
+
+    use std::convert::TryFrom;
+    use std::io::{Error, ErrorKind};
+    use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    /// Maximum number of bytes of a varint
+    const MAX_LEN: usize = 10;
+
+    /// Reads a varint
+    ///
+    /// # Errors
+    ///
+    /// If the varint is longer than 10 bytes or the value does not fit into
+    /// `T`
+    ///
+    pub async fn read<B, T>(buf: &mut B) -> Result<T, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin,
+        T: TryFrom<u64> {
+
+        let mut value = 0u64;
+        for i in 0..MAX_LEN {
+            let byte = buf.read_u8().await?;
+            value |= ((byte & 0x7F) as u64) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return T::try_from(value).map_err(|_| invalid("varint does not fit into the type"));
+            }
+        }
+        Err(invalid("varint is longer than 10 bytes"))
+    }
+
+    /// Writes the value as varint
+    ///
+    /// # Errors
+    ///
+    /// If the buffer cannot be written
+    ///
+    pub async fn write<B, T>(val: &T, buf: &mut B) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin,
+        T: Copy + Into<u64> {
+
+        let mut value: u64 = (*val).into();
+        let mut bytes = Vec::with_capacity(MAX_LEN);
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                bytes.push(byte);
+                break;
+            }
+            bytes.push(byte | 0x80);
+        }
+
+        buf.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    /// Creates the error for invalid data
+    pub(crate) fn invalid(msg: &str) -> CachemError {
+        CachemError::IoError(Error::new(ErrorKind::InvalidData, msg))
+    }
+}
+
+/// Signed integers as zigzag encoded varint.
+///
+/// Zigzag maps small negative and positive numbers to small unsigned
+/// numbers, `0, -1, 1, -2` become `0, 1, 2, 3`, which are then written as
+/// [varint].
+pub mod zigzag {
+    use crate::CachemError;
+
+    use std::convert::TryFrom;
+    use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+    /// Reads a zigzag encoded varint
+    ///
+    /// # Errors
+    ///
+    /// If the varint is invalid or the value does not fit into `T`
+    ///
+    pub async fn read<B, T>(buf: &mut B) -> Result<T, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin,
+        T: TryFrom<i64> {
+
+        let value: u64 = super::varint::read(buf).await?;
+        let value = ((value >> 1) as i64) ^ -((value & 1) as i64);
+        T::try_from(value).map_err(|_| super::varint::invalid("varint does not fit into the type"))
+    }
+
+    /// Writes the value as zigzag encoded varint
+    ///
+    /// # Errors
+    ///
+    /// If the buffer cannot be written
+    ///
+    pub async fn write<B, T>(val: &T, buf: &mut B) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin,
+        T: Copy + Into<i64> {
+
+        let value: i64 = (*val).into();
+        let value = ((value << 1) ^ (value >> 63)) as u64;
+        super::varint::write(&value, buf).await
+    }
+}
+
+/// Bytes prefixed with their length as [varint].
+///
+/// Unlike `Vec<u8>`, which is written as [u32] length and read byte by byte,
+/// the bytes are read with a single call.
+pub mod bytes {
+    use crate::CachemError;
+
+    use std::io::{Error, ErrorKind};
+    use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    /// Reads length prefixed bytes
+    ///
+    /// # Errors
+    ///
+    /// If the length is invalid or the buffer ends before all bytes are read
+    ///
+    pub async fn read<B, T>(buf: &mut B) -> Result<T, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin,
+        T: From<Vec<u8>> {
+
+        let len: u64 = super::varint::read(buf).await?;
+
+        // the length is not trusted, the buffer grows while reading
+        let mut bytes = Vec::new();
+        (&mut *buf).take(len).read_to_end(&mut bytes).await?;
+        if bytes.len() as u64 != len {
+            return Err(CachemError::IoError(Error::from(ErrorKind::UnexpectedEof)));
+        }
+        Ok(T::from(bytes))
+    }
+
+    /// Writes the bytes with their length
+    ///
+    /// # Errors
+    ///
+    /// If the buffer cannot be written
+    ///
+    pub async fn write<B, T>(val: &T, buf: &mut B) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin,
+        T: AsRef<[u8]> {
+
+        let bytes = val.as_ref();
+        super::varint::write(&(bytes.len() as u64), buf).await?;
+        buf.write_all(bytes).await?;
+        Ok(())
+    }
+}
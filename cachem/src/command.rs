@@ -1,5 +1,9 @@
 //! List of all valid commands and a parser from and to u8.
 
+use crate::CachemError;
+
+use std::convert::TryFrom;
+
 /// Contains all valid commands
 ///
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -27,15 +31,47 @@ pub enum Command {
     /// Saves the current cache to disk
     Save,
 
+    /// Sets the time to live of an entry
+    Expire,
+    /// Gets the remaining time to live of an entry
+    Ttl,
+    /// Removes the time to live of an entry
+    Persist,
+    /// Sets a value that expires after the given time
+    SetWithTtl,
+
+    /// Exchanges the protocol version and server information
+    Hello,
     /// Pong from the server
     Pong,
     /// Pings the server
     Ping,
 }
 
-impl From<u8> for Command {
-    fn from(x: u8) -> Self {
-        match x {
+impl Command {
+    /// # Returns
+    ///
+    /// `true` if the command changes the content of a cache
+    ///
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Self::Set     |
+            Self::MSet    |
+            Self::Del     |
+            Self::MDel    |
+            Self::Expire  |
+            Self::Persist |
+            Self::SetWithTtl
+        )
+    }
+}
+
+impl TryFrom<u8> for Command {
+    type Error = CachemError;
+
+    fn try_from(x: u8) -> Result<Self, Self::Error> {
+        let cmd = match x {
             0   => Self::Get,
             1   => Self::MGet,
             2   => Self::Keys,
@@ -49,9 +85,17 @@ impl From<u8> for Command {
 
             9   => Self::Save,
 
+            10  => Self::Expire,
+            11  => Self::Ttl,
+            12  => Self::Persist,
+            13  => Self::SetWithTtl,
+
+            253 => Self::Hello,
             254 => Self::Ping,
-            _   => Self::Pong,
-        }
+            255 => Self::Pong,
+            _   => return Err(CachemError::UnknownCommand(x)),
+        };
+        Ok(cmd)
     }
 }
 
@@ -71,6 +115,12 @@ impl From<Command> for u8 {
 
             Command::Save    => 9,
 
+            Command::Expire  => 10,
+            Command::Ttl     => 11,
+            Command::Persist    => 12,
+            Command::SetWithTtl => 13,
+
+            Command::Hello   => 253,
             Command::Ping    => 254,
             Command::Pong    => 255,
         }
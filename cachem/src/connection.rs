@@ -1,10 +1,12 @@
 use crate::{CachemError, Parse};
-use super::{Command, ConnectionPool};
+use super::{Command, ConnectionPool, Frame, Hello, LengthEncoding, MultiplexedConnection, Pipeline, PROTOCOL_VERSION, Response};
 
 use std::convert::AsMut;
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::io::Cursor;
 use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 use tokio::io::{AsyncWriteExt, BufStream};
 use tokio::net::TcpStream;
 
@@ -12,13 +14,117 @@ use tokio::net::TcpStream;
 /// This is returned when a connection from the [`crate::ConnectionPool`] is requested.
 /// Internally the library should use the underlying buffer for reading and
 /// writing, but externals only should see the wrapper struct.
-pub struct Connection(BufStream<TcpStream>);
+///
+/// When the server answers a request with an error, the methods return
+/// [CachemError::Remote] containing the code and message of the server.
+///
+/// Requests and responses use the [LengthEncoding] that was agreed on during
+/// the handshake.
+pub struct Connection {
+    /// Buffered socket to the server
+    stream:          BufStream<TcpStream>,
+    /// Id that is used for the next request
+    next_id:         u32,
+    /// Encoding of collection lengths
+    length_encoding: LengthEncoding,
+    /// Maximum size of a response frame in bytes
+    max_frame_size:  u32,
+    /// `true` while a request was send whose response was not read yet. If
+    /// the caller is cancelled in between, the socket is out of sync and
+    /// the connection must not be used again.
+    awaiting_reply:  bool,
+}
 
 impl Connection {
     /// Takes the given [`tokio::net::TcpStream`] and wraps it in a
     /// [`tokio::io::BufStream`] and stores it in the struct.
     pub fn new(stream: TcpStream) -> Self {
-        Self(BufStream::new(stream))
+        Self {
+            stream:          BufStream::new(stream),
+            next_id:         0u32,
+            length_encoding: LengthEncoding::Fixed,
+            max_frame_size:  Frame::DEFAULT_MAX_SIZE,
+            awaiting_reply:  false,
+        }
+    }
+
+    /// Sets the maximum size of a response. Larger responses are discarded
+    /// and the request fails with [CachemError::FrameTooLarge].
+    ///
+    /// # Params
+    ///
+    /// * `size` - Maximum size in bytes, defaults to [Frame::DEFAULT_MAX_SIZE]
+    ///
+    pub fn set_max_frame_size(&mut self, size: u32) -> &mut Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// # Returns
+    ///
+    /// Maximum size of a response in bytes
+    ///
+    pub fn max_frame_size(&self) -> u32 {
+        self.max_frame_size
+    }
+
+    /// Sends a HELLO to the server, this must be the first command that is
+    /// send over a new connection.
+    ///
+    /// The connection uses [LengthEncoding::Fixed], see
+    /// [Connection::hello_with].
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::IncompatibleVersion] if the server speaks another
+    /// protocol version
+    ///
+    /// # Returns
+    ///
+    /// Information about the server
+    ///
+    pub async fn hello(&mut self) -> Result<Hello, CachemError> {
+        self.hello_with(LengthEncoding::Fixed).await
+    }
+
+    /// Sends a HELLO to the server and asks for the given [LengthEncoding].
+    /// The server may refuse [LengthEncoding::Compact], the encoding that is
+    /// used is returned in [Hello::length_encoding].
+    ///
+    /// # Params
+    ///
+    /// * `encoding` - Encoding the connection should use
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::IncompatibleVersion] if the server speaks another
+    /// protocol version
+    ///
+    /// # Returns
+    ///
+    /// Information about the server
+    ///
+    pub async fn hello_with(&mut self, encoding: LengthEncoding) -> Result<Hello, CachemError> {
+        let mut req = vec![Command::Hello.into()];
+        PROTOCOL_VERSION.write(&mut req).await?;
+        encoding.write(&mut req).await?;
+
+        // the handshake is always done with the fixed encoding
+        self.length_encoding = LengthEncoding::Fixed;
+        let hello = self.send::<Hello>(req)
+            .await?
+            .validate()?;
+        self.length_encoding = hello.length_encoding;
+        Ok(hello)
+    }
+
+    /// # Returns
+    ///
+    /// Encoding of collection lengths that was agreed on during the
+    /// handshake
+    ///
+    pub fn length_encoding(&self) -> LengthEncoding {
+        self.length_encoding
     }
 
     /// Checkes if the connection is still healthy
@@ -33,6 +139,10 @@ impl Connection {
 
     /// Sends a PING command to the server
     ///
+    /// # Errors
+    ///
+    /// Never fails, a failed request returns `false`
+    ///
     /// # Returns
     ///
     /// * `true` -> The server replied with PONG
@@ -54,10 +164,7 @@ impl Connection {
     /// # }
     /// ```
     pub async fn ping(&mut self) -> Result<bool, CachemError> {
-        self.0.get_mut().write_u8(Command::Ping.into()).await?;
-        self.0.flush().await?;
-
-        if u8::read(&mut self.0).await.is_ok() {
+        if self.send::<u8>(vec![Command::Ping.into()]).await.is_ok() {
             Ok(true)
         } else {
             log::error!("Connection not healthy");
@@ -65,8 +172,45 @@ impl Connection {
         }
     }
 
-    pub async fn save(&mut self) -> Result<(), CachemError> {
-        unimplemented!()
+    /// Creates a new [Pipeline] for sending multiple requests at once
+    ///
+    /// # Returns
+    ///
+    /// New empty pipeline that uses this connection
+    ///
+    pub fn pipeline(&mut self) -> Pipeline<'_> {
+        Pipeline::new(self)
+    }
+
+    /// Turns the connection into a [MultiplexedConnection] that can be used
+    /// by many tasks at the same time
+    ///
+    /// # Returns
+    ///
+    /// New multiplexed connection that owns the socket
+    ///
+    pub fn into_multiplexed(self) -> MultiplexedConnection {
+        MultiplexedConnection::new(self)
+    }
+
+    /// Sends a SAVE command to the server, the cache saves a snapshot of
+    /// itself, see [crate::Cache::snapshot]
+    ///
+    /// # Params
+    ///
+    /// * `cache` -> Target cache for the command
+    ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
+    pub async fn save<C>(&mut self, cache: C) -> Result<(), CachemError>
+    where
+        C: Into<u8> {
+
+        let req = Self::request(Command::Save, cache);
+        self.send::<()>(req).await
     }
 
     /// Sends a GET command to the server
@@ -76,6 +220,11 @@ impl Connection {
     /// * `cache` -> Target cache for the command
     /// * `idx`   -> Id of the entry to get
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Returns
     ///
     /// * `Some(R)` -> The requested id exists in that cache
@@ -106,12 +255,9 @@ impl Connection {
         I: Parse,
         R: Parse + Send + Sync {
 
-        self.0.get_mut().write_u8(Command::Get.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        idx.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
-
-        Ok(Option::<R>::read(&mut self.0).await?)
+        let mut req = Self::request(Command::Get, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<Option<R>>(req).await
     }
 
     /// Sends a MGET command to the server
@@ -121,10 +267,15 @@ impl Connection {
     /// * `cache` -> Target cache for the command
     /// * `ids`   -> List of ids to get
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Returns
     ///
     /// * `Vec<R>` -> List of the requested ids, if an id did not exist, that
-    ///               entry will be ignored
+    ///   entry will be ignored
     ///
     /// # Example
     ///
@@ -151,12 +302,9 @@ impl Connection {
         I: Parse + Send + Sync,
         R: Parse + Send + Sync {
 
-        self.0.get_mut().write_u8(Command::MGet.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        ids.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
-
-        Ok(Vec::<Option<R>>::read(&mut self.0).await?)
+        let mut req = Self::request(Command::MGet, cache);
+        self.length_encoding.encode(&ids, &mut req).await?;
+        self.send::<Vec<Option<R>>>(req).await
     }
 
     /// Sends a KEYS command to the server
@@ -165,6 +313,11 @@ impl Connection {
     ///
     /// * `cache` -> Target cache for the command
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -189,11 +342,8 @@ impl Connection {
         C: Into<u8>,
         R: Parse + Send + Sync {
 
-        self.0.get_mut().write_u8(Command::Keys.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        self.0.flush().await?;
-
-        Ok(Vec::<R>::read(&mut self.0).await?)
+        let req = Self::request(Command::Keys, cache);
+        self.send::<Vec<R>>(req).await
     }
 
     /// Sends a EXISTS command to the server
@@ -203,6 +353,11 @@ impl Connection {
     /// * `cache` -> Target cache for the command
     /// * `idx`   -> Id of the entry to check
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -227,12 +382,9 @@ impl Connection {
         C: Into<u8>,
         I: Parse {
 
-        self.0.get_mut().write_u8(Command::Exists.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        idx.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
-
-        Ok(bool::read(&mut self.0).await?)
+        let mut req = Self::request(Command::Exists, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<bool>(req).await
     }
 
     /// Sends a MEXISTS command to the server
@@ -242,6 +394,11 @@ impl Connection {
     /// * `cache` -> Target cache for the command
     /// * `ids`   -> Ids of the entries to check
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -266,12 +423,9 @@ impl Connection {
         C: Into<u8>,
         I: Parse + Send + Sync {
 
-        self.0.get_mut().write_u8(Command::MExists.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        ids.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
-
-        Ok(Vec::<bool>::read(&mut self.0).await?)
+        let mut req = Self::request(Command::MExists, cache);
+        self.length_encoding.encode(&ids, &mut req).await?;
+        self.send::<Vec<bool>>(req).await
     }
 
     /// Sends a SET command to the server
@@ -282,6 +436,11 @@ impl Connection {
     /// * `id`    -> Id of the new entry
     /// * `data`  -> Date for the entry
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -307,14 +466,10 @@ impl Connection {
         I: Parse,
         D: Parse {
 
-        self.0.get_mut().write_u8(Command::Set.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        idx.write(&mut self.0.get_mut()).await?;
-        data.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
-
-        u8::read(&mut self.0).await?;
-        Ok(())
+        let mut req = Self::request(Command::Set, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.length_encoding.encode(&data, &mut req).await?;
+        self.send::<()>(req).await
     }
 
     /// Sends a MSET command to the server
@@ -324,6 +479,11 @@ impl Connection {
     /// * `cache` -> Target cache for the command
     /// * `data`  -> Map of entries to insert
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -354,13 +514,9 @@ impl Connection {
         I: Parse + Eq + Hash + Send + Sync,
         D: Parse + Send + Sync {
 
-        self.0.get_mut().write_u8(Command::MSet.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        data.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
-
-        u8::read(&mut self.0).await?;
-        Ok(())
+        let mut req = Self::request(Command::MSet, cache);
+        self.length_encoding.encode(&data, &mut req).await?;
+        self.send::<()>(req).await
     }
 
     /// Sends a DEL command to the server
@@ -370,6 +526,11 @@ impl Connection {
     /// * `cache` -> Target cache for the command
     /// * `idx`   -> Id to delete
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -394,13 +555,9 @@ impl Connection {
         C: Into<u8>,
         I: Parse {
 
-        self.0.get_mut().write_u8(Command::Del.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        idx.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
-
-        u8::read(&mut self.0).await?;
-        Ok(())
+        let mut req = Self::request(Command::Del, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<()>(req).await
     }
 
     /// Sends a MDEL command to the server
@@ -410,6 +567,11 @@ impl Connection {
     /// * `cache` -> Target cache for the command
     /// * `ids`   -> Ids to delete
     ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -434,28 +596,313 @@ impl Connection {
         C: Into<u8>,
         I: Parse + Send + Sync {
 
-        self.0.get_mut().write_u8(Command::MDel.into()).await?;
-        self.0.get_mut().write_u8(cache.into()).await?;
-        ids.write(&mut self.0.get_mut()).await?;
-        self.0.flush().await?;
+        let mut req = Self::request(Command::MDel, cache);
+        self.length_encoding.encode(&ids, &mut req).await?;
+        self.send::<()>(req).await
+    }
+
+    /// Sends a EXPIRE command to the server
+    ///
+    /// # Params
+    ///
+    /// * `cache` -> Target cache for the command
+    /// * `idx`   -> Id of the entry
+    /// * `ttl`   -> Time until the entry expires
+    ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
+    /// # Returns
+    ///
+    /// `true` if the entry exists
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// # use std::time::Duration;
+    /// enum CacheName { A }
+    /// impl Into<u8> for CacheName {
+    ///     fn into(self) -> u8 { 0u8 }
+    /// }
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// // creates a new pool with one connection
+    /// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize).await?;
+    /// // get a connection
+    /// let mut conn = pool.acquire().await?;
+    /// conn.expire(CacheName::A, 0u32, Duration::from_secs(60)).await;
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn expire<C, I>(&mut self, cache: C, idx: I, ttl: Duration) -> Result<bool, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
 
-        u8::read(&mut self.0).await?;
-        Ok(())
+        let mut req = Self::request(Command::Expire, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        (ttl.as_millis() as u64).write(&mut req).await?;
+        self.send::<bool>(req).await
+    }
+
+    /// Sends a SET_WITH_TTL command to the server. Sets the value and its
+    /// time to live at once, other requests never see the value without the
+    /// time to live.
+    ///
+    /// # Params
+    ///
+    /// * `cache` -> Target cache for the command
+    /// * `idx`   -> Id of the entry
+    /// * `data`  -> Value of the entry
+    /// * `ttl`   -> Time until the entry expires
+    ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// # use std::time::Duration;
+    /// enum CacheName { A }
+    /// impl Into<u8> for CacheName {
+    ///     fn into(self) -> u8 { 0u8 }
+    /// }
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// // creates a new pool with one connection
+    /// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize).await?;
+    /// // get a connection
+    /// let mut conn = pool.acquire().await?;
+    /// conn.set_with_ttl(CacheName::A, 0u32, 1u32, Duration::from_secs(60)).await;
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_with_ttl<C, I, D>(
+        &mut self,
+        cache: C,
+        idx:   I,
+        data:  D,
+        ttl:   Duration,
+    ) -> Result<(), CachemError>
+    where
+        C: Into<u8>,
+        I: Parse,
+        D: Parse {
+
+        let mut req = Self::request(Command::SetWithTtl, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.length_encoding.encode(&data, &mut req).await?;
+        (ttl.as_millis() as u64).write(&mut req).await?;
+        self.send::<()>(req).await
+    }
+
+    /// Sends a TTL command to the server
+    ///
+    /// # Params
+    ///
+    /// * `cache` -> Target cache for the command
+    /// * `idx`   -> Id of the entry
+    ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
+    /// # Returns
+    ///
+    /// Remaining time to live, `None` if the entry does not exist or does
+    /// not expire
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// enum CacheName { A }
+    /// impl Into<u8> for CacheName {
+    ///     fn into(self) -> u8 { 0u8 }
+    /// }
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// // creates a new pool with one connection
+    /// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize).await?;
+    /// // get a connection
+    /// let mut conn = pool.acquire().await?;
+    /// conn.ttl(CacheName::A, 0u32).await;
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn ttl<C, I>(&mut self, cache: C, idx: I) -> Result<Option<Duration>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Self::request(Command::Ttl, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<Option<u64>>(req)
+            .await
+            .map(|x| x.map(Duration::from_millis))
+    }
+
+    /// Sends a PERSIST command to the server
+    ///
+    /// # Params
+    ///
+    /// * `cache` -> Target cache for the command
+    /// * `idx`   -> Id of the entry
+    ///
+    /// # Errors
+    ///
+    /// If sending the request fails or [CachemError::Remote] when the
+    /// server answered with an error
+    ///
+    /// # Returns
+    ///
+    /// `true` if the entry had a time to live
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// enum CacheName { A }
+    /// impl Into<u8> for CacheName {
+    ///     fn into(self) -> u8 { 0u8 }
+    /// }
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// // creates a new pool with one connection
+    /// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize).await?;
+    /// // get a connection
+    /// let mut conn = pool.acquire().await?;
+    /// conn.persist(CacheName::A, 0u32).await;
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn persist<C, I>(&mut self, cache: C, idx: I) -> Result<bool, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Self::request(Command::Persist, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<bool>(req).await
+    }
+}
+
+impl Connection {
+    /// Creates the body of a new request
+    ///
+    /// # Params
+    ///
+    /// * `cmd`   - Command to execute
+    /// * `cache` - Target cache for the command
+    ///
+    pub(crate) fn request<C: Into<u8>>(cmd: Command, cache: C) -> Vec<u8> {
+        vec![cmd.into(), cache.into()]
+    }
+
+    /// Splits the connection into the raw socket, the id for the next
+    /// request, the encoding of the connection and the maximum size of a
+    /// response
+    pub(crate) fn into_parts(self) -> (TcpStream, u32, LengthEncoding, u32) {
+        (self.stream.into_inner(), self.next_id, self.length_encoding, self.max_frame_size)
+    }
+
+    /// # Returns
+    ///
+    /// `true` if a request was send whose response was not read, the
+    /// connection is then out of sync and cannot be used anymore
+    ///
+    pub(crate) fn is_awaiting_reply(&self) -> bool {
+        self.awaiting_reply
+    }
+
+    /// Marks that a request was send and its response was not read yet
+    ///
+    /// # Params
+    ///
+    /// * `awaiting` - `true` before sending, `false` after the response was
+    ///   read
+    ///
+    pub(crate) fn set_awaiting_reply(&mut self, awaiting: bool) {
+        self.awaiting_reply = awaiting;
+    }
+
+    /// Generates the id for the next request
+    pub(crate) fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    /// Sends the given request as [Frame] to the server and reads the
+    /// response.
+    ///
+    /// # Params
+    ///
+    /// * `req` - Body of the request
+    ///
+    /// # Returns
+    ///
+    /// Parsed payload of the response
+    ///
+    async fn send<R>(&mut self, req: Vec<u8>) -> Result<R, CachemError>
+    where
+        R: Parse {
+
+        let id = self.next_id();
+        // stays set if the caller is cancelled or the socket is out of sync
+        self.awaiting_reply = true;
+        Frame::new(id, req).write(&mut self.stream).await?;
+        self.stream.flush().await?;
+
+        let res = match Frame::read(&mut self.stream, self.max_frame_size).await {
+            Ok(x) => x,
+            // the body was discarded, the connection can still be used
+            Err(CachemError::FrameTooLarge { id: received, size, max }) if received == id => {
+                self.awaiting_reply = false;
+                return Err(CachemError::FrameTooLarge { id, size, max });
+            },
+            Err(e) => return Err(e),
+        };
+        if res.id != id {
+            return Err(CachemError::RequestIdMismatch {
+                expected: id,
+                received: res.id,
+            });
+        }
+        self.awaiting_reply = false;
+        self.length_encoding
+            .scope(Response::read::<R, _>(&mut Cursor::new(res.body)))
+            .await
     }
 }
 
 impl AsMut<BufStream<TcpStream>> for Connection {
     fn as_mut(&mut self) -> &mut BufStream<TcpStream> {
-        &mut self.0
+        &mut self.stream
     }
 }
 
 /// This guard wrapps a connection from the pool.
 ///
 /// When the guard is dropped, the connection is returned to the connectiton
-/// pool and can be used for further usage
+/// pool and can be used for further usage. A connection that still waits for
+/// a response, for example because a request was cancelled, is closed instead
+/// and the pool opens a new one in the background.
 pub struct ConnectionGuard {
+    /// Pool the connection is returned to
     pool:       ConnectionPool,
+    /// Connection that is wrapped, only `None` while it is released
     connection: Option<Connection>,
 }
 
@@ -470,7 +917,13 @@ impl ConnectionGuard {
 
 impl Drop for ConnectionGuard {
     fn drop(&mut self) {
-        self.pool.release(self.connection.take().unwrap());
+        let connection = self.connection.take().unwrap();
+        if connection.is_awaiting_reply() {
+            drop(connection);
+            self.pool.replace_connection();
+        } else {
+            self.pool.release(connection);
+        }
     }
 }
 
@@ -488,3 +941,46 @@ impl DerefMut for ConnectionGuard {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConnectionPool, MapCache, Server};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_cancelled_request_does_not_return_connection() {
+        let (_, mut server) = Server::new("127.0.0.1:47018".into());
+        server.add(0u8, Arc::new(MapCache::<u32, u32>::default()));
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            server.run_until(async { let _ = rx.await; }).await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let pool = ConnectionPool::new("127.0.0.1:47018", 1).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        conn.set(0u8, 1u32, 10u32).await.unwrap();
+
+        // the request is send, but the caller stops waiting for the reply
+        {
+            let mut get = Box::pin(conn.get::<_, _, u32>(0u8, 1u32));
+            assert!(futures::poll!(get.as_mut()).is_pending());
+        }
+        drop(conn);
+
+        // the connection is replaced instead of returned with the reply
+        // still on the socket
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.available_connections(), 1);
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(conn.get::<_, _, u32>(0u8, 1u32).await.unwrap(), Some(10u32));
+        assert_eq!(conn.get::<_, _, u32>(0u8, 2u32).await.unwrap(), None);
+
+        drop(conn);
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+}
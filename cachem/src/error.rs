@@ -1,3 +1,5 @@
+use crate::ErrorCode;
+
 #[derive(Debug)]
 pub enum CachemError {
     Empty,
@@ -5,9 +7,91 @@ pub enum CachemError {
     IoError(std::io::Error),
     StringParseError(std::string::FromUtf8Error),
     ConnectionPoolError(ConnectionPoolError),
+    /// The given byte does not represent a valid [crate::Command]
+    UnknownCommand(u8),
+    /// The read tag does not belong to any variant of the enum
+    InvalidDiscriminant {
+        /// Name of the enum
+        name: String,
+        /// Tag that was read
+        tag:  u8,
+    },
+    /// The protocol versions of the client and the server do not match
+    IncompatibleVersion {
+        /// Protocol version of the client
+        client: u16,
+        /// Protocol version of the server
+        server: u16,
+    },
+    /// The received frame is larger than the allowed maximum
+    FrameTooLarge {
+        /// Id of the request
+        id:   u32,
+        /// Size of the frame body in bytes
+        size: u32,
+        /// Maximum allowed size in bytes
+        max:  u32,
+    },
+    /// The server answered with another request id than expected
+    RequestIdMismatch {
+        /// Id of the request that was send
+        expected: u32,
+        /// Id the server answered with
+        received: u32,
+    },
+    /// A snapshot file is damaged or does not belong to the cache
+    CorruptSnapshot {
+        /// Path of the file
+        file:   String,
+        /// Description of the damage
+        reason: String,
+    },
+    /// The write log is damaged or was written by a newer version
+    CorruptWriteLog {
+        /// Path of the file
+        file:   String,
+        /// Description of the damage
+        reason: String,
+    },
+    /// The cache does not implement [crate::Cache::snapshot]
+    SnapshotNotSupported {
+        /// Name of the cache
+        cache: String,
+    },
+    /// The server answered the request with an error
+    Remote {
+        /// Code that describes the kind of error
+        code:    ErrorCode,
+        /// Human readable message of the server
+        message: String,
+    },
 }
 impl std::error::Error for CachemError {}
 
+impl CachemError {
+    /// Gets the [ErrorCode] that is send to the client when a request fails
+    /// with this error.
+    ///
+    /// # Returns
+    ///
+    /// Errors that originate from reading the request are mapped to
+    /// [ErrorCode::ParseError], all other errors are considered as
+    /// [ErrorCode::HandlerError]
+    ///
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::IoError(_)                 |
+            Self::StringParseError(_)        |
+            Self::InvalidDiscriminant { .. } => ErrorCode::ParseError,
+            Self::UnknownCommand(_)          => ErrorCode::UnknownCommand,
+            Self::IncompatibleVersion { .. } => ErrorCode::IncompatibleVersion,
+            Self::FrameTooLarge { .. }       => ErrorCode::FrameTooLarge,
+            Self::Remote { code, .. }        => *code,
+            _                                => ErrorCode::HandlerError,
+        }
+    }
+}
+
 impl std::fmt::Display for CachemError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self) 
@@ -0,0 +1,271 @@
+//! Keeps track of entries that expire.
+//!
+//! Caches that support expiration keep an [Expirations] next to their
+//! entries. On every read the cache checks [Expirations::is_expired] and
+//! removes the entry if it expired. The server calls [crate::Cache::sweep]
+//! in an interval, where the cache removes all entries returned by
+//! [Expirations::expired].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Time at which entries expire
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # use std::time::Duration;
+/// let mut expirations = Expirations::default();
+/// expirations.expire(1u32, Duration::from_secs(60));
+/// expirations.expire(2u32, Duration::from_secs(0));
+///
+/// assert!(!expirations.is_expired(&1u32));
+/// assert!(expirations.is_expired(&2u32));
+/// assert!(expirations.ttl(&1u32).is_some());
+/// assert!(expirations.ttl(&3u32).is_none());
+///
+/// assert_eq!(expirations.expired(), vec![2u32]);
+/// assert!(expirations.persist(&1u32));
+/// assert!(expirations.is_empty());
+/// ```
+#[derive(Debug)]
+pub struct Expirations<K> {
+    /// Time at which a key expires
+    deadlines: HashMap<K, Instant>,
+    /// All keys ordered by the time they expire
+    queue:     BTreeMap<Instant, HashSet<K>>,
+}
+
+impl<K> Expirations<K>
+where
+    K: Clone + Eq + Hash {
+
+    /// Sets the time to live of a key, replacing the previous one
+    ///
+    /// # Params
+    ///
+    /// * `key` - Key that should expire
+    /// * `ttl` - Time until the key expires
+    ///
+    pub fn expire(&mut self, key: K, ttl: Duration) {
+        self.persist(&key);
+
+        let deadline = Instant::now() + ttl;
+        self.queue
+            .entry(deadline)
+            .or_default()
+            .insert(key.clone());
+        self.deadlines.insert(key, deadline);
+    }
+
+    /// Sets the wall-clock time at which a key expires, replacing the
+    /// previous one. Used to restore deadlines that were saved with
+    /// [Expirations::deadlines]. A deadline in the past lets the key expire
+    /// immediately.
+    ///
+    /// # Params
+    ///
+    /// * `key`      - Key that should expire
+    /// * `deadline` - Time at which the key expires
+    ///
+    pub fn expire_at(&mut self, key: K, deadline: SystemTime) {
+        let ttl = deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        self.expire(key, ttl);
+    }
+
+    /// Gets the wall-clock time at which every key expires, so that they
+    /// can be saved and restored after a restart
+    ///
+    /// # Returns
+    ///
+    /// All keys with a time to live and the time they expire
+    ///
+    pub fn deadlines(&self) -> Vec<(K, SystemTime)> {
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        self.deadlines
+            .iter()
+            .map(|(key, x)| (key.clone(), wall + x.saturating_duration_since(now)))
+            .collect::<Vec<_>>()
+    }
+
+    /// Removes the time to live of a key
+    ///
+    /// # Params
+    ///
+    /// * `key` - Key that should not expire anymore
+    ///
+    /// # Returns
+    ///
+    /// `true` if the key had a time to live
+    ///
+    pub fn persist(&mut self, key: &K) -> bool {
+        let deadline = if let Some(x) = self.deadlines.remove(key) {
+            x
+        } else {
+            return false;
+        };
+
+        if let Some(keys) = self.queue.get_mut(&deadline) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.queue.remove(&deadline);
+            }
+        }
+        true
+    }
+
+    /// Gets the remaining time to live of a key
+    ///
+    /// # Params
+    ///
+    /// * `key` - Key to get the time to live for
+    ///
+    /// # Returns
+    ///
+    /// `None` if the key does not expire, otherwise the remaining time
+    ///
+    pub fn ttl(&self, key: &K) -> Option<Duration> {
+        self.deadlines
+            .get(key)
+            .map(|x| x.saturating_duration_since(Instant::now()))
+    }
+
+    /// Checks if the key expired
+    ///
+    /// # Params
+    ///
+    /// * `key` - Key to check
+    ///
+    /// # Returns
+    ///
+    /// `true` if the key has a time to live that passed
+    ///
+    pub fn is_expired(&self, key: &K) -> bool {
+        self.deadlines
+            .get(key)
+            .map(|x| *x <= Instant::now())
+            .unwrap_or_default()
+    }
+
+    /// Removes all keys that expired
+    ///
+    /// # Returns
+    ///
+    /// All keys that expired, the caller should remove their entries
+    ///
+    pub fn expired(&mut self) -> Vec<K> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+
+        while let Some(deadline) = self.queue.keys().next().copied() {
+            if deadline > now {
+                break;
+            }
+
+            if let Some(keys) = self.queue.remove(&deadline) {
+                for key in keys {
+                    self.deadlines.remove(&key);
+                    expired.push(key);
+                }
+            }
+        }
+
+        expired
+    }
+
+    /// # Returns
+    ///
+    /// Number of keys that have a time to live
+    ///
+    pub fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    /// # Returns
+    ///
+    /// `true` if no key has a time to live
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+}
+
+impl<K> Default for Expirations<K> {
+    fn default() -> Self {
+        Self {
+            deadlines: HashMap::new(),
+            queue:     BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired_in_order() {
+        let mut expirations = Expirations::default();
+        expirations.expire(1u32, Duration::from_millis(20));
+        expirations.expire(2u32, Duration::from_millis(0));
+        expirations.expire(3u32, Duration::from_secs(60));
+        assert_eq!(expirations.len(), 3);
+
+        assert_eq!(expirations.expired(), vec![2u32]);
+        assert!(!expirations.is_expired(&1u32));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(expirations.is_expired(&1u32));
+        assert_eq!(expirations.expired(), vec![1u32]);
+        assert_eq!(expirations.expired(), Vec::<u32>::new());
+        assert_eq!(expirations.len(), 1);
+    }
+
+    #[test]
+    fn test_expire_replaces_ttl() {
+        let mut expirations = Expirations::default();
+        expirations.expire(1u32, Duration::from_millis(0));
+        expirations.expire(1u32, Duration::from_secs(60));
+
+        assert!(!expirations.is_expired(&1u32));
+        assert!(expirations.expired().is_empty());
+        assert!(expirations.ttl(&1u32).unwrap() > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn test_deadlines_round_trip() {
+        let mut expirations = Expirations::default();
+        expirations.expire(1u32, Duration::from_secs(60));
+        expirations.expire(2u32, Duration::from_millis(0));
+
+        let mut restored = Expirations::default();
+        for (key, deadline) in expirations.deadlines() {
+            restored.expire_at(key, deadline);
+        }
+
+        let ttl = restored.ttl(&1u32).unwrap();
+        assert!(ttl > Duration::from_secs(59) && ttl <= Duration::from_secs(60));
+        assert!(restored.is_expired(&2u32));
+
+        restored.expire_at(3u32, SystemTime::now() - Duration::from_secs(1));
+        assert!(restored.is_expired(&3u32));
+    }
+
+    #[test]
+    fn test_persist() {
+        let mut expirations = Expirations::default();
+        expirations.expire(1u32, Duration::from_millis(0));
+
+        assert!(expirations.persist(&1u32));
+        assert!(!expirations.persist(&1u32));
+        assert!(!expirations.is_expired(&1u32));
+        assert!(expirations.ttl(&1u32).is_none());
+        assert!(expirations.expired().is_empty());
+        assert!(expirations.is_empty());
+    }
+}
@@ -1,52 +1,53 @@
-use crate::{CachemError, Parse};
+use crate::{CachemError, Parse, SnapshotFile};
 
-use tokio::fs::OpenOptions;
-use tokio::io::{AsyncWriteExt, BufStream};
+use std::io::Cursor;
 
-/// Wraps [`tokio::fs::File`] type for easier testability
+/// Saves a list of entries as [SnapshotFile] and loads it again.
 ///
-/// When compiled as a test build, all filesystem based implementations
-/// are replaced with non filesystem implementations, while mainining
-/// the same interface.
-///
-/// When calling [`FileUtils::write`] it will not write directly in the file, instead
-/// it will write in an internal buffer. Only on when [`FileUtils::save`] is
-/// called the whole buffer will be written and afterwards cleared
+/// The path of the file is used as name of the cache in the header.
 pub struct FileUtils;
 
 impl FileUtils {
     /// Loads the file and parses it into the given model
     ///
-    /// If the file does not exist, it will be created
+    /// If the file does not exist, an empty list is returned
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::CorruptSnapshot] if the file is damaged or does not
+    /// contain the number of entries noted in the header
     pub async fn open<R>(
         path: &str
     ) -> Result<Vec<R>, CachemError>
-    where 
+    where
         R: Parse {
 
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(path)
-            .await?;
-
-        let file_size = std::fs::metadata(path)?.len();
-        let mut buf = BufStream::new(file);
+        let (header, payload) = match SnapshotFile::read(path).await? {
+            Some(x) => x,
+            None    => return Ok(Vec::new()),
+        };
 
-        if file_size > 0 {
-            let length = u32::read(&mut buf).await?;
-            let mut result = Vec::with_capacity(length as usize);
-            for _ in 0..length {
-                result.push(R::read(&mut buf).await?)
-            }
-            Ok(result)
-        } else {
-            Ok(Vec::new())
+        let mut buf = Cursor::new(payload);
+        let mut result = Vec::with_capacity(header.entries as usize);
+        for _ in 0..header.entries {
+            let entry = header.length_encoding
+                .scope(R::read(&mut buf))
+                .await
+                .map_err(|_| CachemError::CorruptSnapshot {
+                    file:   path.into(),
+                    reason: "payload does not match the entry count".into(),
+                })?;
+            result.push(entry);
         }
+        Ok(result)
     }
 
-    /// Writes the internal buffer to the file, clears the buffer and flushes
+    /// Writes all entries to the file, replacing the previous file atomically
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be written, the previous file is untouched
+    ///
     pub async fn save<T>(
         path: &str,
         entries: Vec<T>,
@@ -54,18 +55,10 @@ impl FileUtils {
     where
         T: Parse {
 
-        let file = OpenOptions::new()
-            .write(true)
-            .open(path)
-            .await?;
-
-        let mut buf = BufStream::new(file);
-
-        u32::from(entries.len() as u32).write(&mut buf).await?;
-        for entry in entries {
-            entry.write(&mut buf).await?;
+        let mut payload = Vec::new();
+        for entry in entries.iter() {
+            entry.write(&mut payload).await?;
         }
-        buf.flush().await?;
-        Ok(())
+        SnapshotFile::write(path, path, entries.len() as u32, &payload).await
     }
 }
@@ -0,0 +1,161 @@
+//! Every request and response is send as a frame.
+//!
+//! A frame starts with the length of the body as `u32`, followed by the id of
+//! the request as `u32` and the body.
+//! The body of a request contains the command, the cache and the payload of
+//! the request. The body of a response contains the [crate::Response].
+//! The server answers every request with the id of the request, that way
+//! a client can send multiple requests before reading the responses.
+//!
+//! Because the length is known before the body is read, a request that could
+//! not be parsed only affects its own frame and the connection can be used
+//! for further requests.
+
+use crate::{CachemError, Parse};
+
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// A single length prefixed frame
+#[derive(Debug, Default)]
+pub struct Frame {
+    /// Id of the request, the response uses the same id
+    pub id:   u32,
+    /// Body of the frame, without the length and id
+    pub body: Vec<u8>,
+}
+
+impl Frame {
+    /// Default for the maximum size of a frame body in bytes, 16 MiB
+    pub const DEFAULT_MAX_SIZE: u32 = 16 * 1024 * 1024;
+
+    /// Creates a new frame
+    ///
+    /// # Params
+    ///
+    /// * `id`   - Id of the request
+    /// * `body` - Body of the frame
+    ///
+    pub fn new(id: u32, body: Vec<u8>) -> Self {
+        Self { id, body }
+    }
+
+    /// Reads a single frame from the given buffer.
+    ///
+    /// # Params
+    ///
+    /// * `buf`      - Buffer to read the frame from
+    /// * `max_size` - Maximum size of the body in bytes
+    ///
+    /// # Errors
+    ///
+    /// If the body is larger than `max_size`, the body is read and discarded
+    /// so that the next frame can be read and [CachemError::FrameTooLarge] is
+    /// returned.
+    ///
+    /// # Returns
+    ///
+    /// The frame that was read
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use cachem::*;
+    /// # use std::io::Cursor;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut buf = Vec::new();
+    /// Frame::new(0u32, vec![0u8; 8]).write(&mut buf).await?;
+    /// Frame::new(1u32, vec![1u8; 2]).write(&mut buf).await?;
+    ///
+    /// let mut buf = Cursor::new(buf);
+    /// assert!(Frame::read(&mut buf, 4u32).await.is_err());
+    ///
+    /// let frame = Frame::read(&mut buf, 4u32).await?;
+    /// assert_eq!(frame.id, 1u32);
+    /// assert_eq!(frame.body, vec![1u8; 2]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn read<B>(
+        buf:      &mut B,
+        max_size: u32,
+    ) -> Result<Self, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        let size = u32::read(buf).await?;
+        let id = u32::read(buf).await?;
+        if size > max_size {
+            tokio::io::copy(&mut (&mut *buf).take(size as u64), &mut tokio::io::sink()).await?;
+            return Err(CachemError::FrameTooLarge { id, size, max: max_size });
+        }
+
+        let mut body = vec![0u8; size as usize];
+        buf.read_exact(&mut body).await?;
+        Ok(Self { id, body })
+    }
+
+    /// Writes the frame into the given buffer
+    ///
+    /// # Params
+    ///
+    /// * `buf` - Buffer to write the frame into
+    ///
+    /// # Errors
+    ///
+    /// If the body is larger than [u32::MAX] bytes or writing into the
+    /// buffer fails
+    ///
+    pub async fn write<B>(
+        &self,
+        buf: &mut B,
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        let size = u32::try_from(self.body.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "frame body is larger than u32::MAX bytes"))?;
+        buf.write_u32(size).await?;
+        buf.write_u32(self.id).await?;
+        buf.write_all(&self.body).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn test_frame_rw() {
+        let mut buf = Vec::new();
+        Frame::new(7u32, vec![1u8, 2u8]).write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![0, 0, 0, 2, 0, 0, 0, 7, 1, 2]);
+
+        let is = Frame::read(&mut Cursor::new(buf), Frame::DEFAULT_MAX_SIZE).await.unwrap();
+        assert_eq!(is.id, 7u32);
+        assert_eq!(is.body, vec![1u8, 2u8]);
+    }
+
+    #[tokio::test]
+    async fn test_frame_too_large_skipped() {
+        let mut buf = Vec::new();
+        Frame::new(0u32, vec![0u8; 16]).write(&mut buf).await.unwrap();
+        Frame::new(1u32, vec![1u8]).write(&mut buf).await.unwrap();
+
+        let mut buf = Cursor::new(buf);
+        let is = Frame::read(&mut buf, 8u32).await;
+        assert!(matches!(
+            is,
+            Err(CachemError::FrameTooLarge { id: 0, size: 16, max: 8 })
+        ));
+
+        let is = Frame::read(&mut buf, 8u32).await.unwrap();
+        assert_eq!(is.id, 1u32);
+    }
+}
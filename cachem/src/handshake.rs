@@ -0,0 +1,112 @@
+//! HELLO exchange that is done when a connection is opened.
+//!
+//! The client sends [Command::Hello](crate::Command::Hello) followed by its
+//! protocol version and the [LengthEncoding] it wants to use. The server
+//! answers with a [Hello] containing its own protocol version, its name, the
+//! ids of all registered caches and the [LengthEncoding] of the connection.
+//! If the versions do not match, the connection is closed after the answer.
+//!
+//! The handshake itself always uses [LengthEncoding::Fixed].
+
+use crate::{CachemError, LengthEncoding, Parse};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+/// Version of the wire protocol. Must be increased every time the format of
+/// a request or response changes.
+///
+/// * `1` - Requests and responses with a status envelope
+/// * `2` - Length prefixed frames with request ids
+/// * `3` - Length encoding that is agreed on in the handshake
+pub const PROTOCOL_VERSION: u16 = 3u16;
+
+/// Answer of the server to a HELLO
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Hello {
+    /// Protocol version of the server
+    pub version:         u16,
+    /// Name of the server
+    pub name:            String,
+    /// Ids of all caches that are registered
+    pub caches:          Vec<u8>,
+    /// Encoding of collection lengths for all following requests and
+    /// responses
+    pub length_encoding: LengthEncoding,
+}
+
+impl Hello {
+    /// Checks if the given protocol version can talk to this side
+    ///
+    /// # Params
+    ///
+    /// * `version` - Protocol version of the other side
+    ///
+    /// # Returns
+    ///
+    /// `true` if both versions are the same
+    ///
+    pub fn is_compatible(version: u16) -> bool {
+        version == PROTOCOL_VERSION
+    }
+
+    /// Validates the version of the server
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::IncompatibleVersion] when the server speaks another
+    /// protocol version
+    ///
+    pub fn validate(self) -> Result<Self, CachemError> {
+        if Self::is_compatible(self.version) {
+            Ok(self)
+        } else {
+            Err(CachemError::IncompatibleVersion {
+                client: PROTOCOL_VERSION,
+                server: self.version,
+            })
+        }
+    }
+}
+
+#[async_trait]
+impl Parse for Hello {
+    async fn read<B>(
+        buf: &mut B
+    ) -> Result<Self, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        let version = u16::read(buf).await?;
+        let name = String::read(buf).await?;
+        let caches = Vec::<u8>::read(buf).await?;
+        // servers with another version may not send the encoding, they are
+        // refused by `validate` afterwards
+        let length_encoding = if Self::is_compatible(version) {
+            LengthEncoding::read(buf).await?
+        } else {
+            LengthEncoding::default()
+        };
+
+        Ok(Self {
+            version,
+            name,
+            caches,
+            length_encoding,
+        })
+    }
+
+    async fn write<B>(
+        &self,
+        buf: &mut B
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        self.version.write(buf).await?;
+        self.name.write(buf).await?;
+        self.caches.write(buf).await?;
+        self.length_encoding.write(buf).await?;
+        Ok(())
+    }
+}
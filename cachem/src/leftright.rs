@@ -1,67 +1,220 @@
-use std::cell::UnsafeCell;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+//! Concurrent datastructure with wait-free reads.
+//!
+//! The data is stored twice. Readers use the active side, writers change the
+//! other side. A commit swaps the sides, waits until all readers left the
+//! previous side and applies the same changes to it again.
+//!
+//! Changes are operations that are recorded in a log, see [Absorb]. A commit
+//! only replays the operations since the last commit, so its cost depends on
+//! the number of changes and not on the size of the data.
+//!
+//! The synchronisation is checked with [loom](https://docs.rs/loom), see
+//! `tests/loom_leftright.rs`.
+
+use self::sync::{AtomicBool, AtomicUsize, Condvar, Mutex, MutexGuard, Ordering, Side, SideRef};
+
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::ops::Deref;
+use std::time::Duration;
+
+// Readers get shared access to the read side from many threads, so `T` must
+// be `Sync`. Writers change the write side and move operations from any
+// thread, so `T` and `O` must be `Send`. The write side is only accessed
+// while holding the writer lock and never while it has readers, see
+// [LeftRight::arrive] and [Commit].
+unsafe impl<T, O> Sync for LeftRight<T, O>
+where
+    T: Absorb<O> + Send + Sync,
+    O: Send {}
+
+/// Applies operations of type `O` to a datastructure that is used in a
+/// [LeftRight].
+///
+/// Every operation is applied twice, once to each side. Both applications
+/// must have the same result, otherwise the sides drift apart.
+///
+/// # Example
+///
+/// ```
+/// use cachem::{Absorb, LeftRight};
+///
+/// enum Counter {
+///     Add(u32),
+///     Reset,
+/// }
+///
+/// #[derive(Clone, Default)]
+/// struct Total(u32);
+///
+/// impl Absorb<Counter> for Total {
+///     fn absorb_first(&mut self, op: &Counter) {
+///         match op {
+///             Counter::Add(x) => self.0 += x,
+///             Counter::Reset  => self.0 = 0,
+///         }
+///     }
+/// }
+///
+/// let total = LeftRight::<Total, Counter>::default();
+/// total.write(Counter::Add(5));
+/// total.blocking_commit();
+/// total.write(Counter::Add(2));
+/// total.blocking_commit();
+/// assert_eq!(total.read(|x| x.0), 7);
+/// ```
+pub trait Absorb<O> {
+    /// Applies the operation to the write side. The operation is applied
+    /// again to the other side with [Absorb::absorb_second] after the next
+    /// commit.
+    ///
+    /// # Params
+    ///
+    /// * `op` - Operation to apply
+    ///
+    fn absorb_first(&mut self, op: &O);
+
+    /// Applies the operation to the second side, the operation is dropped
+    /// afterwards. Defaults to [Absorb::absorb_first].
+    ///
+    /// # Params
+    ///
+    /// * `op` - Operation to apply
+    ///
+    fn absorb_second(&mut self, op: O) {
+        self.absorb_first(&op)
+    }
+}
+
+/// Operations on a [HashMap] in a [LeftRight]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapOp<K, V> {
+    /// Inserts or replaces an entry
+    Insert(K, V),
+    /// Removes an entry
+    Remove(K),
+    /// Removes all entries
+    Clear,
+}
+
+impl<K, V> Absorb<MapOp<K, V>> for HashMap<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone {
 
-unsafe impl<T> Sync for LeftRight<T> where T: Clone + Default + Send {}
+    fn absorb_first(&mut self, op: &MapOp<K, V>) {
+        match op {
+            MapOp::Insert(k, v) => { self.insert(k.clone(), v.clone()); },
+            MapOp::Remove(k)    => { self.remove(k); },
+            MapOp::Clear        => self.clear(),
+        }
+    }
+
+    fn absorb_second(&mut self, op: MapOp<K, V>) {
+        match op {
+            MapOp::Insert(k, v) => { self.insert(k, v); },
+            MapOp::Remove(k)    => { self.remove(&k); },
+            MapOp::Clear        => self.clear(),
+        }
+    }
+}
 
+/// Datastructure with wait-free reads, see the module documentation.
+///
+/// `T` is the stored datastructure, `O` the operations that change it.
+///
+/// Readers never wait and writers only wait for other writers. Commits wait
+/// until all readers left the previous side, with [LeftRight::commit] the
+/// task yields to the runtime while waiting.
 #[derive(Debug)]
-pub struct LeftRight<T> {
+pub struct LeftRight<T, O> {
     /// Contains the data for the left side
-    left:  UnsafeCell<T>,
+    left:  Side<T>,
     /// Contains the data for the right side
-    right: UnsafeCell<T>,
+    right: Side<T>,
 
+    /// Operations since the last commit and if a commit is running, every
+    /// access to the write side is done while holding the lock
+    writer:          Mutex<Writer<O>>,
+    /// Notified when a commit finished
+    commit_done:     Condvar,
     /// Contains the currently active read side
     active_side:     Active,
-    /// If the value is `true` the requesting writer has exclusive access
-    /// to the datastructure. If the value is `false` there is another thread
-    /// with exclusive write
-    exclusive_write: AtomicBool,
     /// Counts the number of readers on the left side
     readers_left:    AtomicUsize,
     /// Counts the number of readers on the right side
     readers_right:   AtomicUsize,
 }
 
-impl<T> LeftRight<T> where T: Clone + Default + Send {
+/// State of the writers of a [LeftRight]
+#[derive(Debug)]
+struct Writer<O> {
+    /// Operations that were applied to the write side since the last commit
+    oplog:      Vec<O>,
+    /// Operations that were written while a commit was running, they are
+    /// applied when the commit finished
+    pending:    Vec<O>,
+    /// `true` while a commit waits for the readers of the previous side
+    committing: bool,
+}
+
+impl<O> Default for Writer<O> {
+    fn default() -> Self {
+        Self {
+            oplog:      Vec::new(),
+            pending:    Vec::new(),
+            committing: false,
+        }
+    }
+}
+
+impl<T, O> LeftRight<T, O> where T: Absorb<O> + Clone {
+    /// Creates a new instance, both sides start with the given data
+    ///
+    /// # Params
+    ///
+    /// * `data` - Initial data
+    ///
     pub fn new(data: T) -> Self {
         Self {
-            left:  UnsafeCell::new(data.clone()),
-            right: UnsafeCell::new(data),
+            left:  Side::new(data.clone()),
+            right: Side::new(data),
 
+            writer:          Mutex::new(Writer::default()),
+            commit_done:     Condvar::new(),
             active_side:     Active::default(),
-            exclusive_write: AtomicBool::new(true),
-            readers_left:    AtomicUsize::default(),
-            readers_right:   AtomicUsize::default(),
+            readers_left:    AtomicUsize::new(0),
+            readers_right:   AtomicUsize::new(0),
         }
     }
 }
 
-impl<T> Default for LeftRight<T> where T: Clone + Default + Send {
+impl<T, O> Default for LeftRight<T, O> where T: Absorb<O> + Default {
     fn default() -> Self {
         Self {
-            left:  UnsafeCell::default(),
-            right: UnsafeCell::default(),
+            left:  Side::new(T::default()),
+            right: Side::new(T::default()),
 
+            writer:          Mutex::new(Writer::default()),
+            commit_done:     Condvar::new(),
             active_side:     Active::default(),
-            exclusive_write: AtomicBool::new(true),
-            readers_left:    AtomicUsize::default(),
-            readers_right:   AtomicUsize::default(),
+            readers_left:    AtomicUsize::new(0),
+            readers_right:   AtomicUsize::new(0),
         }
     }
 }
 
-impl<T> LeftRight<T> where T: Clone + Default + Send {
+impl<T, O> LeftRight<T, O> where T: Absorb<O> {
     /// Takes a function and injects the current read side into the function
     ///
     /// # Example:
     /// ``` rust
-    /// use cachem::LeftRight;
+    /// use cachem::{LeftRight, MapOp};
     /// use std::collections::HashMap;
     /// 
     /// // Creates a new [LeftRight] instance, the [HashMap] can be replace
-    /// // with any other type
-    /// let left_right = LeftRight::<HashMap<u32, u32>>::default();
+    /// // with any other type that implements [Absorb]
+    /// let left_right = LeftRight::<HashMap<u32, u32>, MapOp<u32, u32>>::default();
     /// // x contains the HashMap or any other configured type
     /// left_right.read(|x| {
     ///     let entry = x.get(&5);
@@ -69,122 +222,344 @@ impl<T> LeftRight<T> where T: Clone + Default + Send {
     /// })
     /// ```
     pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.read_guard())
+    }
+
+    /// Gives access to the current read side until the guard is dropped.
+    ///
+    /// Commits wait until the guard is dropped, so it should not be held
+    /// for long.
+    ///
+    /// # Returns
+    ///
+    /// Guard that derefs to the current read side
+    ///
+    /// # Example:
+    /// ``` rust
+    /// use cachem::{LeftRight, MapOp};
+    /// use std::collections::HashMap;
+    ///
+    /// let left_right = LeftRight::<HashMap<u32, u32>, MapOp<u32, u32>>::default();
+    /// left_right.write(MapOp::Insert(5, 1));
+    /// left_right.blocking_commit();
+    ///
+    /// let entries = left_right.read_guard();
+    /// assert_eq!(entries.get(&5), Some(&1));
+    /// ```
+    pub fn read_guard(&self) -> ReadGuard<'_, T> {
         let active = self.arrive();
 
         // depending on which side is active, give it the reader
-        let result = if active == ActiveSide::Left {
-            unsafe { f(& (*self.left.get())) }
+        // the side is not changed until the reader departs
+        let data = if active == ActiveSide::Left {
+            unsafe { self.left.get() }
         } else {
-            unsafe { f(& (*self.right.get())) }
+            unsafe { self.right.get() }
         };
 
-        self.depart(active);
-        result
+        ReadGuard {
+            data,
+            readers: self.readers(active),
+        }
     }
 
-    /// Takes a function and injects the current write side into the function
+    /// Applies the operation to the write side and records it for the other
+    /// side. Operations that are written while a commit is running are
+    /// applied when the commit finished.
+    ///
+    /// The caller MUST call [LeftRight::commit] or
+    /// [LeftRight::blocking_commit] when the changes should be commited.
+    ///
+    /// # Params
     ///
-    /// The caller MUST call the [LeftRight::commit] function when the changes
-    /// should be commited.
+    /// * `op` - Operation to apply, see [Absorb]
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it held the writer lock
     ///
     /// # Example:
     /// ``` rust
-    /// use cachem::LeftRight;
+    /// use cachem::{LeftRight, MapOp};
     /// use std::collections::HashMap;
     /// 
-    /// // Creates a new [LeftRight] instance, the [HashMap] can be replace
-    /// // with any other type
-    /// let left_right = LeftRight::<HashMap<u32, u32>>::default();
-    /// // x contains the HashMap or any other configured type
-    /// left_right.write(|x|
-    ///     { x.entry(5).and_modify(|x| *x += 1).or_insert(0); }
-    /// );
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let left_right = LeftRight::<HashMap<u32, u32>, MapOp<u32, u32>>::default();
+    /// left_right.write(MapOp::Insert(5, 1));
+    /// // readers see the change after the commit
+    /// assert_eq!(left_right.read(|x| x.get(&5).copied()), None);
+    ///
+    /// left_right.commit().await;
+    /// assert_eq!(left_right.read(|x| x.get(&5).copied()), Some(1));
+    ///
+    /// // the operation was also applied to the other side
+    /// left_right.write(MapOp::Insert(6, 2));
+    /// left_right.commit().await;
+    /// assert_eq!(left_right.read(|x| x.len()), 2);
+    /// # }
     /// ```
     ///
-    pub fn write(&self, f: impl FnOnce(&mut T)) {
-        // Make sure that we are the only ones that manipulate the structure
-        while self
-                .exclusive_write
-                .compare_exchange_weak(
-                    true, false,
-                    Ordering::SeqCst, Ordering::SeqCst
-                )
-                .is_err() {}
+    pub fn write(&self, op: O) {
+        let mut writer = self.writer.lock().unwrap();
+        // the write side may still have readers
+        if writer.committing {
+            writer.pending.push(op);
+            return;
+        }
 
         // When the left side is currently active, inject the right side.
         // When the right side is currently active, inject the left side.
+        // no commit is running, so the write side has no readers
         if self.active_side.get() == ActiveSide::Right {
-            unsafe { f(&mut (*self.left.get())) }
+            unsafe { self.left.with_mut(|x| x.absorb_first(&op)) }
         } else {
-            unsafe { f(&mut (*self.right.get())) }
+            unsafe { self.right.with_mut(|x| x.absorb_first(&op)) }
         }
-
-        // Unlock datastructure
-        self.exclusive_write.store(true, Ordering::SeqCst);
+        writer.oplog.push(op);
     }
 
-    /// Commits all changes, switches sides and then updates the now unactive
-    /// side
-    pub fn commit(&self) {
-        // Make sure that we are the only ones that manipulate the structure
-        while self
-                .exclusive_write
-                .compare_exchange_weak(
-                    true, false,
-                    Ordering::SeqCst, Ordering::SeqCst
-                )
-                .is_err() {}
-
-        let active_side = self.active_side.get();
-
-        // switch the reader from left to right or from right to left
-        self.active_side.swap(active_side);
+    /// Commits all changes, switches sides and then applies all operations
+    /// since the last commit to the now unactive side.
+    ///
+    /// While waiting for other commits and for the readers of the previous
+    /// side, the task yields to the runtime. If the future is dropped before
+    /// it completed, the commit is finished blocking.
+    pub async fn commit(&self) {
+        let mut backoff = Backoff::default();
+        let commit = loop {
+            if let Some(x) = self.try_start_commit() {
+                break x;
+            }
+            backoff.snooze().await;
+        };
 
         // Wait until all readers switched to the new side
-        if active_side == ActiveSide::Left {
-            while self.readers_left.load(Ordering::SeqCst) > 0 {  }
-        } else {
-            while self.readers_right.load(Ordering::SeqCst) > 0 {  }
+        let mut backoff = Backoff::default();
+        while self.has_readers(commit.previous) {
+            backoff.snooze().await;
         }
+        drop(commit);
+    }
 
-        // Finally copy the new data over to the other side
-        if active_side == ActiveSide::Left {
-            unsafe { *(self.left.get() as *mut _) = &mut *self.right.get() };
+    /// Same as [LeftRight::commit], but blocks the thread while waiting.
+    /// In async code [LeftRight::commit] should be used.
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it held the writer lock
+    ///
+    pub fn blocking_commit(&self) {
+        let mut writer = self.writer.lock().unwrap();
+        while writer.committing {
+            writer = self.commit_done.wait(writer).unwrap();
+        }
+        let commit = self.start_commit(writer);
+
+        // dropping the commit waits until all readers switched to the new
+        // side
+        drop(commit);
+    }
+
+    /// Starts a commit if no other commit is running
+    ///
+    /// # Returns
+    ///
+    /// The running commit, `None` if another commit is running
+    ///
+    fn try_start_commit(&self) -> Option<Commit<'_, T, O>> {
+        let writer = self.writer.lock().unwrap();
+        if writer.committing {
+            None
         } else {
-            unsafe { *(self.right.get() as *mut _) = &mut *self.left.get() };
+            Some(self.start_commit(writer))
         }
+    }
 
-        // Unlock datastructure
-        self.exclusive_write.store(true, Ordering::SeqCst);
+    /// Switches the sides, writes are queued until the returned commit is
+    /// dropped
+    ///
+    /// # Params
+    ///
+    /// * `writer` - Locked writer state, no commit may be running
+    ///
+    fn start_commit(&self, mut writer: MutexGuard<'_, Writer<O>>) -> Commit<'_, T, O> {
+        writer.committing = true;
+
+        // switch the reader from left to right or from right to left
+        let previous = self.active_side.get();
+        self.active_side.swap(previous);
+
+        Commit {
+            left_right: self,
+            previous,
+        }
     }
 
     /// Depending on the active side, it increments the counter.
     ///
+    /// The side is checked again after incrementing the counter. A commit
+    /// switches the side before it checks the counter, so either the commit
+    /// sees the reader and waits, or the reader sees the new side and tries
+    /// again. Checking the side only once would let a reader that was
+    /// descheduled in between enter the previous side while the commit
+    /// changes it.
+    ///
     /// Returns the used active side.
     fn arrive(&self) -> ActiveSide {
-        if self.active_side.get() == ActiveSide::Left {
-            self.readers_left.fetch_add(1, Ordering::SeqCst);
-            ActiveSide::Left
+        loop {
+            let active = self.active_side.get();
+            let readers = self.readers(active);
+            readers.fetch_add(1, Ordering::SeqCst);
+
+            if self.active_side.get() == active {
+                return active;
+            }
+
+            // a commit switched the sides in the meantime
+            readers.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Checks if readers use the given side.
+    ///
+    /// The counter is read with a read-modify-write, so the check is
+    /// ordered with the increment in [LeftRight::arrive]. If the increment
+    /// comes later, the reader is guaranteed to see the switched side. A
+    /// plain load would need sequential consistency between the swap and the
+    /// load to give the same guarantee.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the side has readers
+    ///
+    fn has_readers(&self, side: ActiveSide) -> bool {
+        self.readers(side).fetch_add(0, Ordering::SeqCst) > 0
+    }
+
+    /// # Returns
+    ///
+    /// Reader counter of the given side
+    ///
+    fn readers(&self, side: ActiveSide) -> &AtomicUsize {
+        if side == ActiveSide::Left {
+            &self.readers_left
         } else {
-            self.readers_right.fetch_add(1, Ordering::SeqCst);
-            ActiveSide::Right
+            &self.readers_right
         }
     }
+}
+
+/// Gives access to the read side of a [LeftRight], see
+/// [LeftRight::read_guard]
+#[derive(Debug)]
+pub struct ReadGuard<'a, T> {
+    /// Read side
+    data:    SideRef<'a, T>,
+    /// Reader counter of the side, decremented on drop
+    readers: &'a AtomicUsize,
+}
+
+impl<'a, T> Deref for ReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<'a, T> Drop for ReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-    /// Decrements the counter for a side depending on the given active side.
-    fn depart(&self, active: ActiveSide) {
-        if active == ActiveSide::Left {
-            self.readers_left.fetch_sub(1, Ordering::SeqCst);
+/// Running commit of a [LeftRight].
+///
+/// When dropped, it waits until all readers left the previous side, applies
+/// the operation log and all queued writes to it.
+struct Commit<'a, T, O> where T: Absorb<O> {
+    /// Instance that is commited
+    left_right: &'a LeftRight<T, O>,
+    /// Side that was active before the commit
+    previous:   ActiveSide,
+}
+
+impl<'a, T, O> Drop for Commit<'a, T, O> where T: Absorb<O> {
+    fn drop(&mut self) {
+        let left_right = self.left_right;
+
+        // Only waits if the async commit was cancelled
+        while left_right.has_readers(self.previous) {
+            sync::yield_now();
+        }
+
+        let mut writer = left_right.writer.lock().unwrap();
+
+        // Finally replay the operations on the other side
+        let stale = if self.previous == ActiveSide::Left {
+            &left_right.left
         } else {
-            self.readers_right.fetch_sub(1, Ordering::SeqCst);
+            &left_right.right
+        };
+        let writer = &mut *writer;
+
+        // all readers left the side and new readers use the other side
+        unsafe {
+            stale.with_mut(|x| {
+                for op in std::mem::take(&mut writer.oplog) {
+                    x.absorb_second(op);
+                }
+
+                // the previous side is the new write side, apply the writes
+                // that were queued during the commit
+                for op in std::mem::take(&mut writer.pending) {
+                    x.absorb_first(&op);
+                    writer.oplog.push(op);
+                }
+            });
         }
+
+        writer.committing = false;
+        left_right.commit_done.notify_all();
     }
 }
 
-/// Wrapper for atomic bool that represents the current active side.
+/// Waits between two checks in async code, first yields to the runtime and
+/// then sleeps with a growing duration
 #[derive(Debug, Default)]
+struct Backoff(u32);
+
+impl Backoff {
+    /// Number of yields before sleeping
+    const YIELDS: u32 = 8u32;
+    /// Maximum time to sleep, in microseconds
+    const MAX_SLEEP_USEC: u64 = 1000u64;
+
+    /// Waits before the next check
+    async fn snooze(&mut self) {
+        let attempt = self.0;
+        self.0 = self.0.saturating_add(1);
+
+        if attempt < Self::YIELDS {
+            tokio::task::yield_now().await
+        } else {
+            let usec = (1u64 << (attempt - Self::YIELDS).min(10)).min(Self::MAX_SLEEP_USEC);
+            tokio::time::sleep(Duration::from_micros(usec)).await
+        }
+    }
+}
+
+/// Wrapper for atomic bool that represents the current active side.
+#[derive(Debug)]
 struct Active(AtomicBool);
 
+impl Default for Active {
+    fn default() -> Self {
+        Self(AtomicBool::new(false))
+    }
+}
+
 impl Active {
     /// Gets the current active side
     pub fn get(&self) -> ActiveSide {
@@ -201,7 +576,9 @@ impl Active {
 /// Represents the active side
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum ActiveSide {
+    /// Readers use the left side, writes go to the right side
     Left,
+    /// Readers use the right side, writes go to the left side
     Right,
 }
 
@@ -224,3 +601,117 @@ impl Deref for ActiveSide {
         }
     }
 }
+
+/// Synchronisation primitives, replaced by the ones of loom when compiled
+/// with `--cfg cachem_loom`
+#[cfg(not(cachem_loom))]
+mod sync {
+    pub(super) use std::sync::{Condvar, Mutex, MutexGuard};
+    pub(super) use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    pub(super) use std::thread::yield_now;
+
+    use std::cell::UnsafeCell;
+    use std::ops::Deref;
+
+    /// Shared access to the data of a side
+    #[derive(Debug)]
+    pub(super) struct SideRef<'a, T>(&'a T);
+
+    impl<'a, T> Deref for SideRef<'a, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            self.0
+        }
+    }
+
+    /// Data of one side
+    #[derive(Debug)]
+    pub(super) struct Side<T>(UnsafeCell<T>);
+
+    impl<T> Side<T> {
+        /// Creates a new side with the given data
+        pub(super) fn new(data: T) -> Self {
+            Self(UnsafeCell::new(data))
+        }
+
+        /// # Safety
+        ///
+        /// The side must not be changed while the reference exists
+        ///
+        pub(super) unsafe fn get(&self) -> SideRef<'_, T> {
+            SideRef(&*self.0.get())
+        }
+
+        /// # Safety
+        ///
+        /// There must be no other access to the side at the same time
+        ///
+        pub(super) unsafe fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+            f(&mut *self.0.get())
+        }
+    }
+}
+
+/// Synchronisation primitives of loom, that check every possible
+/// interleaving of the threads
+#[cfg(cachem_loom)]
+mod sync {
+    pub(super) use loom::sync::{Condvar, Mutex, MutexGuard};
+    pub(super) use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    pub(super) use loom::thread::yield_now;
+
+    use loom::cell::{ConstPtr, UnsafeCell};
+    use std::marker::PhantomData;
+    use std::ops::Deref;
+
+    /// Shared access to the data of a side, loom reports changes to the
+    /// side while it exists
+    pub(super) struct SideRef<'a, T>(ConstPtr<T>, PhantomData<&'a T>);
+
+    impl<'a, T> Deref for SideRef<'a, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            unsafe { self.0.deref() }
+        }
+    }
+
+    impl<'a, T> std::fmt::Debug for SideRef<'a, T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("SideRef")
+        }
+    }
+
+    /// Data of one side
+    pub(super) struct Side<T>(UnsafeCell<T>);
+
+    impl<T> Side<T> {
+        /// Creates a new side with the given data
+        pub(super) fn new(data: T) -> Self {
+            Self(UnsafeCell::new(data))
+        }
+
+        /// # Safety
+        ///
+        /// The side must not be changed while the reference exists
+        ///
+        pub(super) unsafe fn get(&self) -> SideRef<'_, T> {
+            SideRef(self.0.get(), PhantomData)
+        }
+
+        /// # Safety
+        ///
+        /// There must be no other access to the side at the same time
+        ///
+        pub(super) unsafe fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+            self.0.with_mut(|x| f(&mut *x))
+        }
+    }
+
+    impl<T> std::fmt::Debug for Side<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("Side")
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Ready-made cache with wait-free reads, based on [LeftRight].
+
+use crate::{CommitPolicy, Del, Get2, Key, LeftRight, MapOp, Parse, Set2, WriteHandle};
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Map with wait-free reads.
+///
+/// The entries are stored in a [LeftRight], reads never wait for writers.
+/// Writes are committed according to the [CommitPolicy], until then readers
+/// see the previous entries. By default every write is committed.
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let map = LeftRightMap::<u32, String>::default();
+/// map.set(1u32, "a".into()).await;
+/// assert_eq!(Get2::get(&map, 1u32).await, Some("a".into()));
+///
+/// // writes are only visible after a flush
+/// let map = LeftRightMap::<u32, String>::new(CommitPolicy::default());
+/// map.set(1u32, "a".into()).await;
+/// assert_eq!(Get2::get(&map, 1u32).await, None);
+///
+/// map.flush().await;
+/// assert_eq!(Get2::get(&map, 1u32).await, Some("a".into()));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct LeftRightMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static {
+
+    /// Handle for writing the entries
+    handle: WriteHandle<HashMap<K, V>, MapOp<K, V>>,
+}
+
+impl<K, V> LeftRightMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static {
+
+    /// Creates a new empty map
+    ///
+    /// # Params
+    ///
+    /// * `policy` - When writes are visible to readers
+    ///
+    /// # Panics
+    ///
+    /// If the policy has an interval and the function is not called within
+    /// a tokio runtime
+    ///
+    pub fn new(policy: CommitPolicy) -> Self {
+        let left_right = Arc::new(LeftRight::new(HashMap::new()));
+        Self {
+            handle: WriteHandle::new(left_right, policy),
+        }
+    }
+
+    /// Makes all writes visible to readers, regardless of the policy
+    pub async fn flush(&self) {
+        self.handle.flush().await
+    }
+
+    /// # Returns
+    ///
+    /// Number of entries
+    ///
+    pub fn len(&self) -> usize {
+        self.handle.left_right().read(|x| x.len())
+    }
+
+    /// # Returns
+    ///
+    /// `true` if there are no entries
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> Default for LeftRightMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static {
+
+    fn default() -> Self {
+        Self::new(CommitPolicy::default().after_writes(1))
+    }
+}
+
+#[async_trait]
+impl<K, V> Get2<K, V> for LeftRightMap<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    async fn get(&self, id: K) -> Option<V> {
+        self.handle.left_right().read(|x| x.get(&id).cloned())
+    }
+
+    async fn mget(&self, ids: Vec<K>) -> Vec<Option<V>> {
+        self.handle.left_right().read(|x| {
+            ids
+                .iter()
+                .map(|id| x.get(id).cloned())
+                .collect::<Vec<_>>()
+        })
+    }
+}
+
+#[async_trait]
+impl<K, V> Set2<K, V> for LeftRightMap<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    async fn set(&self, id: K, val: V) {
+        self.handle.write(MapOp::Insert(id, val)).await
+    }
+
+    async fn mset(&self, entries: HashMap<K, V>) {
+        self.handle
+            .write_all(entries.into_iter().map(|(id, val)| MapOp::Insert(id, val)))
+            .await
+    }
+}
+
+#[async_trait]
+impl<K, V> Del for LeftRightMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static {
+
+    type Id = K;
+
+    async fn del(&self, id: K) {
+        self.handle.write(MapOp::Remove(id)).await
+    }
+
+    async fn mdel(&self, ids: Vec<K>) {
+        self.handle
+            .write_all(ids.into_iter().map(MapOp::Remove))
+            .await
+    }
+}
+
+#[async_trait]
+impl<K, V> Key for LeftRightMap<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static {
+
+    type Id = K;
+
+    async fn keys(&self) -> Vec<K> {
+        self.handle.left_right().read(|x| x.keys().cloned().collect::<Vec<_>>())
+    }
+
+    async fn count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_set_get_del() {
+        let map = LeftRightMap::<u32, String>::default();
+        assert!(map.is_empty());
+
+        map.set(1u32, "a".into()).await;
+        let mut entries = HashMap::new();
+        entries.insert(2u32, String::from("b"));
+        entries.insert(3u32, String::from("c"));
+        map.mset(entries).await;
+
+        assert_eq!(Get2::get(&map, 1u32).await, Some("a".into()));
+        assert_eq!(
+            Get2::mget(&map, vec![3u32, 4u32]).await,
+            vec![Some("c".into()), None]
+        );
+        assert_eq!(map.count().await, 3u64);
+
+        map.del(1u32).await;
+        map.mdel(vec![2u32, 4u32]).await;
+        assert_eq!(map.keys().await, vec![3u32]);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_flush_only() {
+        let map = LeftRightMap::<u32, u32>::new(CommitPolicy::default());
+        map.set(1u32, 1u32).await;
+        map.del(2u32).await;
+        assert!(map.is_empty());
+        assert_eq!(Get2::get(&map, 1u32).await, None);
+
+        map.flush().await;
+        assert_eq!(Get2::get(&map, 1u32).await, Some(1u32));
+
+        // clones write to the same map
+        let clone = map.clone();
+        clone.set(2u32, 2u32).await;
+        clone.flush().await;
+        assert_eq!(map.len(), 2);
+    }
+}
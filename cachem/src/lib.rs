@@ -1,32 +1,81 @@
+/// Contains a map with a limited capacity
+mod bounded;
+/// Contains encodings for fields, used with `#[cachem(with = "..")]`
+pub mod codec;
 #[deny(missing_docs)]
-
 /// Contains all structs and enums for the cnc network
 mod command;
 /// Contains the structs for a connection
 mod connection;
 /// Contains all errors
 mod error;
+/// Contains the length prefixed frames requests and responses are send in
+mod frame;
+/// Contains the helper for entries that expire
+mod expire;
+/// Contains helpers for saving lists of entries to file
+mod file;
+/// Contains the handshake that is done when a connection is opened
+mod handshake;
 /// Alternative implementation for RwLock and Mutex
 mod leftright;
+/// Contains a ready-made cache with wait-free reads
+mod leftrightmap;
+/// Contains a ready-made cache based on a HashMap
+mod map;
+/// Contains a connection that can be shared between tasks
+mod multiplex;
+/// Contains the pipeline for sending multiple requests at once
+mod pipeline;
 /// Contains the code for the connection pool
 mod pool;
 /// Handlers for the protocol
 mod protocol;
+/// Contains the envelope for all responses
+mod response;
+/// Contains the scheduler for background snapshots
+mod scheduler;
 /// Contains all needed structs for starting the cache server
 mod server;
+/// Contains the file format of snapshots
+mod snapshot;
+/// Contains the trait for saving caches to file
+mod storage;
 /// Contains all traits for interacting with the cache
 mod traits;
+/// Contains integers with a variable length and the encoding of lengths
+mod varint;
 /// Contains wrapper for most basic datatypes
 mod wrapper;
+/// Contains the handle that commits writes to a LeftRight automatically
+mod writehandle;
+/// Contains the append-only log of all writes
+mod writelog;
 
+pub use self::bounded::*;
 pub use self::command::*;
 pub use self::connection::*;
 pub use self::error::*;
+pub use self::expire::*;
+pub use self::file::*;
+pub use self::frame::*;
+pub use self::handshake::*;
 pub use self::leftright::*;
+pub use self::leftrightmap::*;
+pub use self::map::*;
+pub use self::multiplex::*;
+pub use self::pipeline::*;
 pub use self::pool::*;
 pub use self::protocol::*;
+pub use self::response::*;
+pub use self::scheduler::*;
 pub use self::server::*;
+pub use self::snapshot::*;
+pub use self::storage::*;
 pub use self::traits::*;
+pub use self::varint::*;
 pub use self::wrapper::*;
+pub use self::writehandle::*;
+pub use self::writelog::*;
 
 pub use cachem_derive::*;
@@ -0,0 +1,692 @@
+//! Ready-made cache that stores all entries in a [HashMap].
+
+use crate::{Cache, CachemError, Command, Del, Expirations, Expire, Get2, Key, Parse, Set2, SnapshotFile};
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Cursor;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+/// Cache that stores all entries in a [HashMap] and handles every
+/// [Command].
+///
+/// Entries can expire, see [Expire]. If a file is set, the cache is saved
+/// as [SnapshotFile] when [Cache::snapshot] is called, for example on
+/// shutdown or with a SAVE command. The snapshot contains the time at which
+/// the entries expire, so they also expire after a restart. Without a file
+/// the cache does not support snapshots.
+///
+/// # Example
+///
+/// ```no_run
+/// # use cachem::*;
+/// # use std::sync::Arc;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let cache = MapCache::<u32, String>::default()
+///     .with_name("names")
+///     .with_file("./names.cachem");
+/// cache.load_snapshot().await?;
+///
+/// let (_, mut server) = Server::new("0.0.0.0:55555".into());
+/// server.add(0u8, Arc::new(cache));
+/// server.listen_tcp().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MapCache<K, V> {
+    /// Name of the cache
+    name:  String,
+    /// Path of the snapshot file, if the cache should be saved
+    file:  Option<String>,
+    /// Entries and their expiration times
+    inner: RwLock<Inner<K, V>>,
+}
+
+/// Entries of a [MapCache]
+struct Inner<K, V> {
+    /// All entries
+    entries:     HashMap<K, V>,
+    /// Time at which entries expire
+    expirations: Expirations<K>,
+}
+
+impl<K, V> Inner<K, V>
+where
+    K: Clone + Eq + Hash {
+
+    /// Gets an entry, entries that expired are treated as missing
+    fn get(&self, id: &K) -> Option<&V> {
+        if self.expirations.is_expired(id) {
+            None
+        } else {
+            self.entries.get(id)
+        }
+    }
+
+    /// Removes an entry and its expiration time
+    fn remove(&mut self, id: &K) -> Option<V> {
+        self.expirations.persist(id);
+        self.entries.remove(id)
+    }
+
+    /// Removes all entries that expired
+    fn sweep(&mut self) {
+        for id in self.expirations.expired() {
+            self.entries.remove(&id);
+        }
+    }
+}
+
+impl<K, V> MapCache<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    /// Sets the name of the cache, defaults to `MapCache`
+    ///
+    /// # Params
+    ///
+    /// * `name` - Name of the cache
+    ///
+    pub fn with_name<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the file the cache is saved to
+    ///
+    /// # Params
+    ///
+    /// * `file` - Path of the snapshot file
+    ///
+    pub fn with_file<T: Into<String>>(mut self, file: T) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Loads all entries and the time they expire from the snapshot file,
+    /// replacing the current entries. Entries whose time passed while the
+    /// cache was stopped expire immediately. Does nothing if no file is set
+    /// or the file does not exist.
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::CorruptSnapshot] if the file is damaged or belongs to
+    /// another cache
+    ///
+    pub async fn load_snapshot(&self) -> Result<(), CachemError> {
+        let file = match self.file.as_ref() {
+            Some(x) => x,
+            None    => return Ok(()),
+        };
+        let (header, payload) = match SnapshotFile::read(file).await? {
+            Some(x) => x,
+            None    => return Ok(()),
+        };
+
+        if header.name != self.name {
+            return Err(CachemError::CorruptSnapshot {
+                file:   file.clone(),
+                reason: format!("snapshot belongs to cache {}", header.name),
+            });
+        }
+
+        let mut buf = Cursor::new(payload.as_slice());
+        let (entries, deadlines) = header.length_encoding
+            .scope(async {
+                let entries = HashMap::<K, V>::read(&mut buf).await?;
+                // snapshots of older versions end after the entries
+                let deadlines = if (buf.position() as usize) < payload.len() {
+                    HashMap::<K, u64>::read(&mut buf).await?
+                } else {
+                    HashMap::new()
+                };
+                Ok::<_, CachemError>((entries, deadlines))
+            })
+            .await?;
+
+        let mut expirations = Expirations::default();
+        for (id, deadline) in deadlines {
+            if entries.contains_key(&id) {
+                expirations.expire_at(id, UNIX_EPOCH + Duration::from_millis(deadline));
+            }
+        }
+
+        let mut inner = self.inner.write().await;
+        inner.entries = entries;
+        inner.expirations = expirations;
+        Ok(())
+    }
+
+    /// # Returns
+    ///
+    /// Number of entries, including entries that expired but were not
+    /// removed yet
+    ///
+    pub async fn len(&self) -> usize {
+        self.inner.read().await.entries.len()
+    }
+
+    /// # Returns
+    ///
+    /// `true` if there are no entries
+    ///
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+impl<K, V> Default for MapCache<K, V> {
+    fn default() -> Self {
+        Self {
+            name:  "MapCache".into(),
+            file:  None,
+            inner: RwLock::new(Inner {
+                entries:     HashMap::new(),
+                expirations: Expirations::default(),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl<K, V> Cache for MapCache<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn handle(
+        &self,
+        cmd: Command,
+        req: &mut Cursor<Vec<u8>>,
+        res: &mut Vec<u8>,
+    ) -> Result<(), CachemError> {
+        match cmd {
+            Command::Get => {
+                let id = K::read(req).await?;
+                Get2::get(self, id).await.write(res).await
+            },
+            Command::MGet => {
+                let ids = Vec::<K>::read(req).await?;
+                Get2::mget(self, ids).await.write(res).await
+            },
+            Command::Keys => {
+                Key::keys(self).await.write(res).await
+            },
+            Command::Exists => {
+                let id = K::read(req).await?;
+                self.inner.read().await.get(&id).is_some().write(res).await
+            },
+            Command::MExists => {
+                let ids = Vec::<K>::read(req).await?;
+                let inner = self.inner.read().await;
+                ids
+                    .iter()
+                    .map(|x| inner.get(x).is_some())
+                    .collect::<Vec<_>>()
+                    .write(res)
+                    .await
+            },
+            Command::Set => {
+                let id = K::read(req).await?;
+                let val = V::read(req).await?;
+                Set2::set(self, id, val).await;
+                Ok(())
+            },
+            Command::MSet => {
+                let entries = HashMap::<K, V>::read(req).await?;
+                Set2::mset(self, entries).await;
+                Ok(())
+            },
+            Command::Del => {
+                let id = K::read(req).await?;
+                Del::del(self, id).await;
+                Ok(())
+            },
+            Command::MDel => {
+                let ids = Vec::<K>::read(req).await?;
+                Del::mdel(self, ids).await;
+                Ok(())
+            },
+            Command::Save => {
+                self.snapshot().await
+            },
+            Command::Expire => {
+                let id = K::read(req).await?;
+                let ttl = Duration::from_millis(u64::read(req).await?);
+                Expire::expire(self, id, ttl).await.write(res).await
+            },
+            Command::Ttl => {
+                let id = K::read(req).await?;
+                Expire::ttl(self, id)
+                    .await
+                    .map(|x| x.as_millis() as u64)
+                    .write(res)
+                    .await
+            },
+            Command::Persist => {
+                let id = K::read(req).await?;
+                Expire::persist(self, id).await.write(res).await
+            },
+            Command::SetWithTtl => {
+                let id = K::read(req).await?;
+                let val = V::read(req).await?;
+                let ttl = Duration::from_millis(u64::read(req).await?);
+                Expire::set_with_ttl(self, id, val, ttl).await;
+                Ok(())
+            },
+            Command::Hello |
+            Command::Ping  |
+            Command::Pong  => Err(CachemError::UnknownCommand(cmd.into())),
+        }
+    }
+
+    async fn cnc_listener(&self) {}
+
+    async fn snapshot(&self) -> Result<(), CachemError> {
+        let file = match self.file.as_ref() {
+            Some(x) => x,
+            None    => return Err(CachemError::SnapshotNotSupported {
+                cache: self.name.clone(),
+            }),
+        };
+
+        // a read lock, so that the cache can still be read while the entries
+        // are serialized, expired entries are removed again on load
+        let mut payload = Vec::new();
+        let entries = {
+            let inner = self.inner.read().await;
+            let deadlines = inner.expirations
+                .deadlines()
+                .into_iter()
+                .map(|(id, x)| {
+                    let deadline = x
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64;
+                    (id, deadline)
+                })
+                .collect::<HashMap<_, _>>();
+            inner.entries.write(&mut payload).await?;
+            deadlines.write(&mut payload).await?;
+            inner.entries.len() as u32
+        };
+        SnapshotFile::write(file, &self.name, entries, &payload).await
+    }
+
+    fn supports_snapshot(&self) -> bool {
+        self.file.is_some()
+    }
+
+    async fn sweep(&self) {
+        self.inner.write().await.sweep();
+    }
+}
+
+#[async_trait]
+impl<K, V> Get2<K, V> for MapCache<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    async fn get(&self, id: K) -> Option<V> {
+        self.inner.read().await.get(&id).cloned()
+    }
+
+    async fn mget(&self, ids: Vec<K>) -> Vec<Option<V>> {
+        let inner = self.inner.read().await;
+        ids
+            .iter()
+            .map(|x| inner.get(x).cloned())
+            .collect::<Vec<_>>()
+    }
+}
+
+#[async_trait]
+impl<K, V> Set2<K, V> for MapCache<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    async fn set(&self, id: K, val: V) {
+        let mut inner = self.inner.write().await;
+        inner.expirations.persist(&id);
+        inner.entries.insert(id, val);
+    }
+
+    async fn mset(&self, entries: HashMap<K, V>) {
+        let mut inner = self.inner.write().await;
+        for (id, val) in entries {
+            inner.expirations.persist(&id);
+            inner.entries.insert(id, val);
+        }
+    }
+}
+
+#[async_trait]
+impl<K, V> Del for MapCache<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    type Id = K;
+
+    async fn del(&self, id: K) {
+        self.inner.write().await.remove(&id);
+    }
+
+    async fn mdel(&self, ids: Vec<K>) {
+        let mut inner = self.inner.write().await;
+        for id in ids {
+            inner.remove(&id);
+        }
+    }
+}
+
+#[async_trait]
+impl<K, V> Key for MapCache<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    type Id = K;
+
+    async fn keys(&self) -> Vec<K> {
+        let inner = self.inner.read().await;
+        inner.entries
+            .keys()
+            .filter(|x| !inner.expirations.is_expired(x))
+            .cloned()
+            .collect::<Vec<_>>()
+    }
+}
+
+#[async_trait]
+impl<K, V> Expire<K, V> for MapCache<K, V>
+where
+    K: Clone + Eq + Hash + Parse + Send + Sync + 'static,
+    V: Clone + Parse + Send + Sync + 'static {
+
+    async fn set_with_ttl(&self, id: K, val: V, ttl: Duration) {
+        let mut inner = self.inner.write().await;
+        inner.expirations.expire(id.clone(), ttl);
+        inner.entries.insert(id, val);
+    }
+
+    async fn expire(&self, id: K, ttl: Duration) -> bool {
+        let mut inner = self.inner.write().await;
+        if inner.get(&id).is_none() {
+            return false;
+        }
+        inner.expirations.expire(id, ttl);
+        true
+    }
+
+    async fn ttl(&self, id: K) -> Option<Duration> {
+        let inner = self.inner.read().await;
+        inner.get(&id)?;
+        inner.expirations.ttl(&id)
+    }
+
+    async fn persist(&self, id: K) -> bool {
+        let mut inner = self.inner.write().await;
+        if inner.get(&id).is_none() {
+            return false;
+        }
+        inner.expirations.persist(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path of a new snapshot in the temp directory
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("cachem_map_{}", name));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().into()
+    }
+
+    /// Cache with the given snapshot file
+    fn cache(file: &str) -> MapCache<u32, String> {
+        MapCache::default()
+            .with_name("test")
+            .with_file(file)
+    }
+
+    /// Calls [Cache::handle] with the given request
+    async fn handle(
+        cache: &MapCache<u32, String>,
+        cmd:   Command,
+        req:   Vec<u8>,
+    ) -> Result<Vec<u8>, CachemError> {
+        let mut res = Vec::new();
+        cache.handle(cmd, &mut Cursor::new(req), &mut res).await?;
+        Ok(res)
+    }
+
+    /// Encodes a single value as request
+    async fn request<T: Parse>(value: T) -> Vec<u8> {
+        let mut req = Vec::new();
+        value.write(&mut req).await.unwrap();
+        req
+    }
+
+    /// Parses the response of [Cache::handle]
+    async fn response<T: Parse>(res: Vec<u8>) -> T {
+        T::read(&mut Cursor::new(res)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_and_write_commands() {
+        let cache = MapCache::<u32, String>::default();
+
+        let mut req = request(1u32).await;
+        String::from("a").write(&mut req).await.unwrap();
+        let res = handle(&cache, Command::Set, req).await.unwrap();
+        assert!(res.is_empty());
+
+        let res = handle(&cache, Command::Get, request(1u32).await).await.unwrap();
+        assert_eq!(response::<Option<String>>(res).await, Some("a".into()));
+        let res = handle(&cache, Command::Get, request(2u32).await).await.unwrap();
+        assert_eq!(response::<Option<String>>(res).await, None);
+
+        let mut entries = HashMap::new();
+        entries.insert(2u32, String::from("b"));
+        entries.insert(3u32, String::from("c"));
+        let res = handle(&cache, Command::MSet, request(entries).await).await.unwrap();
+        assert!(res.is_empty());
+
+        let res = handle(&cache, Command::MGet, request(vec![1u32, 2, 4]).await).await.unwrap();
+        assert_eq!(
+            response::<Vec<Option<String>>>(res).await,
+            vec![Some("a".into()), Some("b".into()), None]
+        );
+
+        let res = handle(&cache, Command::Keys, Vec::new()).await.unwrap();
+        let mut keys = response::<Vec<u32>>(res).await;
+        keys.sort_unstable();
+        assert_eq!(keys, vec![1u32, 2, 3]);
+
+        let res = handle(&cache, Command::Exists, request(1u32).await).await.unwrap();
+        assert!(response::<bool>(res).await);
+        let res = handle(&cache, Command::Exists, request(4u32).await).await.unwrap();
+        assert!(!response::<bool>(res).await);
+        let res = handle(&cache, Command::MExists, request(vec![1u32, 4]).await).await.unwrap();
+        assert_eq!(response::<Vec<bool>>(res).await, vec![true, false]);
+
+        let res = handle(&cache, Command::Del, request(1u32).await).await.unwrap();
+        assert!(res.is_empty());
+        let res = handle(&cache, Command::Exists, request(1u32).await).await.unwrap();
+        assert!(!response::<bool>(res).await);
+
+        let res = handle(&cache, Command::MDel, request(vec![2u32, 3]).await).await.unwrap();
+        assert!(res.is_empty());
+        let res = handle(&cache, Command::Keys, Vec::new()).await.unwrap();
+        assert!(response::<Vec<u32>>(res).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expire_commands() {
+        let cache = MapCache::<u32, String>::default();
+        Set2::set(&cache, 1u32, "a".into()).await;
+
+        let mut req = request(1u32).await;
+        60_000u64.write(&mut req).await.unwrap();
+        let res = handle(&cache, Command::Expire, req).await.unwrap();
+        assert!(response::<bool>(res).await);
+
+        // missing entries cannot expire
+        let mut req = request(2u32).await;
+        60_000u64.write(&mut req).await.unwrap();
+        let res = handle(&cache, Command::Expire, req).await.unwrap();
+        assert!(!response::<bool>(res).await);
+
+        let res = handle(&cache, Command::Ttl, request(1u32).await).await.unwrap();
+        let ttl = response::<Option<u64>>(res).await.unwrap();
+        assert!(ttl > 59_000u64 && ttl <= 60_000u64);
+        let res = handle(&cache, Command::Ttl, request(2u32).await).await.unwrap();
+        assert_eq!(response::<Option<u64>>(res).await, None);
+
+        let res = handle(&cache, Command::Persist, request(1u32).await).await.unwrap();
+        assert!(response::<bool>(res).await);
+        let res = handle(&cache, Command::Ttl, request(1u32).await).await.unwrap();
+        assert_eq!(response::<Option<u64>>(res).await, None);
+        let res = handle(&cache, Command::Persist, request(1u32).await).await.unwrap();
+        assert!(!response::<bool>(res).await);
+
+        // the entry is kept after persist
+        let res = handle(&cache, Command::Get, request(1u32).await).await.unwrap();
+        assert_eq!(response::<Option<String>>(res).await, Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_command() {
+        let cache = MapCache::<u32, String>::default();
+        assert!(matches!(
+            handle(&cache, Command::Ping, Vec::new()).await,
+            Err(CachemError::UnknownCommand(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_set_with_ttl_command() {
+        let cache = MapCache::<u32, String>::default();
+
+        let mut req = Vec::new();
+        1u32.write(&mut req).await.unwrap();
+        String::from("a").write(&mut req).await.unwrap();
+        60_000u64.write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache
+            .handle(Command::SetWithTtl, &mut Cursor::new(req), &mut res)
+            .await
+            .unwrap();
+        assert!(res.is_empty());
+
+        assert_eq!(Get2::get(&cache, 1u32).await, Some("a".into()));
+        let ttl = Expire::ttl(&cache, 1u32).await.unwrap();
+        assert!(ttl > Duration::from_secs(59));
+
+        // a set removes the time to live again
+        Set2::set(&cache, 1u32, "b".into()).await;
+        assert_eq!(Expire::ttl(&cache, 1u32).await, None);
+
+        Expire::set_with_ttl(&cache, 2u32, "c".into(), Duration::from_millis(0)).await;
+        assert_eq!(Get2::get(&cache, 2u32).await, None);
+        assert_eq!(Key::keys(&cache).await, vec![1u32]);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_keeps_expirations() {
+        let path = path("expirations");
+        let cache = cache(&path);
+        Set2::set(&cache, 1u32, "a".into()).await;
+        Expire::set_with_ttl(&cache, 2u32, "b".into(), Duration::from_secs(60)).await;
+        Expire::set_with_ttl(&cache, 3u32, "c".into(), Duration::from_millis(20)).await;
+        cache.snapshot().await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let loaded = self::cache(&path);
+        loaded.load_snapshot().await.unwrap();
+
+        assert_eq!(Get2::get(&loaded, 1u32).await, Some("a".into()));
+        assert_eq!(Expire::ttl(&loaded, 1u32).await, None);
+        assert_eq!(Get2::get(&loaded, 2u32).await, Some("b".into()));
+        let ttl = Expire::ttl(&loaded, 2u32).await.unwrap();
+        assert!(ttl > Duration::from_secs(58) && ttl <= Duration::from_secs(60));
+        // expired while the cache was stopped
+        assert_eq!(Get2::get(&loaded, 3u32).await, None);
+
+        loaded.sweep().await;
+        assert_eq!(loaded.len().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_load_snapshot_without_expirations() {
+        let path = path("without_expirations");
+
+        let mut payload = Vec::new();
+        let mut entries = HashMap::new();
+        entries.insert(1u32, String::from("a"));
+        entries.write(&mut payload).await.unwrap();
+        SnapshotFile::write(&path, "test", 1u32, &payload).await.unwrap();
+
+        let cache = cache(&path);
+        cache.load_snapshot().await.unwrap();
+        assert_eq!(Get2::get(&cache, 1u32).await, Some("a".into()));
+        assert_eq!(Expire::ttl(&cache, 1u32).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_load_snapshot_of_other_cache() {
+        let path = path("other_cache");
+        cache(&path).snapshot().await.unwrap();
+
+        let other = MapCache::<u32, String>::default()
+            .with_name("other")
+            .with_file(&path);
+        assert!(matches!(
+            other.load_snapshot().await,
+            Err(CachemError::CorruptSnapshot { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_while_reading() {
+        let path = path("while_reading");
+        let cache = cache(&path);
+        Set2::set(&cache, 1u32, "a".into()).await;
+
+        // the snapshot only needs a read lock
+        let guard = cache.inner.read().await;
+        tokio::time::timeout(Duration::from_secs(5), cache.snapshot())
+            .await
+            .unwrap()
+            .unwrap();
+        drop(guard);
+
+        let loaded = self::cache(&path);
+        loaded.load_snapshot().await.unwrap();
+        assert_eq!(Get2::get(&loaded, 1u32).await, Some("a".into()));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_without_file() {
+        let cache = MapCache::<u32, String>::default();
+        assert!(!cache.supports_snapshot());
+        assert!(matches!(
+            cache.snapshot().await,
+            Err(CachemError::SnapshotNotSupported { .. })
+        ));
+        cache.load_snapshot().await.unwrap();
+    }
+}
@@ -0,0 +1,620 @@
+use crate::{CachemError, Command, Connection, Frame, LengthEncoding, Parse, Response};
+
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, oneshot};
+
+/// Body of a request and the sender the reply is send to
+type Request = (Vec<u8>, oneshot::Sender<Vec<u8>>);
+/// All requests that wait for their reply, grouped by their request id.
+/// Is set to `None` when the connection is closed.
+type Pending = Arc<Mutex<Option<HashMap<u32, oneshot::Sender<Vec<u8>>>>>>;
+
+/// Connection that can be shared by many tasks at the same time.
+///
+/// A background task owns the socket. Every request is tagged with an id and
+/// send to the server without waiting for the replies of other requests.
+/// The replies are matched by their id and given back to the waiting caller.
+///
+/// Cloning is cheap, all clones use the same socket. When the last clone is
+/// dropped, the connection is closed.
+///
+/// By default the server executes the requests of a connection one after
+/// another, so the requests of all clones still wait for each other on the
+/// server. Allow the server to execute them at the same time with
+/// [Server::set_max_concurrent_requests](crate::Server::set_max_concurrent_requests).
+///
+/// # Example
+///
+/// ```no_run
+/// # use cachem::*;
+/// enum CacheName { A }
+/// impl Into<u8> for CacheName {
+///     fn into(self) -> u8 { 0u8 }
+/// }
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize).await?;
+/// let conn = pool.multiplexed().await?;
+///
+/// let mut tasks = Vec::new();
+/// for i in 0..100u32 {
+///     let conn = conn.clone();
+///     tasks.push(tokio::spawn(async move {
+///         conn.get::<_, _, u32>(CacheName::A, i).await
+///     }));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct MultiplexedConnection {
+    /// Sender for requests to the background task
+    requests:        mpsc::Sender<Request>,
+    /// Encoding of collection lengths, taken from the connection
+    length_encoding: LengthEncoding,
+}
+
+impl MultiplexedConnection {
+    /// Number of requests that can be queued before callers have to wait
+    const QUEUE_SIZE: usize = 1024usize;
+
+    /// Takes the given connection and starts the background tasks that own
+    /// the socket.
+    ///
+    /// # Params
+    ///
+    /// * `connection` - Connection that already did the handshake
+    ///
+    pub fn new(connection: Connection) -> Self {
+        let (stream, next_id, length_encoding, max_frame_size) = connection.into_parts();
+        let (reader, writer) = stream.into_split();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+
+        let (tx, rx) = mpsc::channel(Self::QUEUE_SIZE);
+        tokio::spawn(Self::write_requests(writer, rx, pending.clone(), next_id));
+        tokio::spawn(Self::read_replies(reader, pending, max_frame_size));
+
+        Self {
+            requests: tx,
+            length_encoding,
+        }
+    }
+
+    /// Sends a PING command to the server, see [Connection::ping]
+    ///
+    /// # Errors
+    ///
+    /// Never fails, a failed request returns `false`
+    ///
+    pub async fn ping(&self) -> Result<bool, CachemError> {
+        Ok(self.send::<u8>(vec![Command::Ping.into()]).await.is_ok())
+    }
+
+    /// Sends a GET command to the server, see [Connection::get]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn get<C, I, R>(&self, cache: C, idx: I) -> Result<Option<R>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse,
+        R: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::Get, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<Option<R>>(req).await
+    }
+
+    /// Sends a MGET command to the server, see [Connection::mget]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn mget<C, I, R>(&self, cache: C, ids: Vec<I>) -> Result<Vec<Option<R>>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse + Send + Sync,
+        R: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::MGet, cache);
+        self.length_encoding.encode(&ids, &mut req).await?;
+        self.send::<Vec<Option<R>>>(req).await
+    }
+
+    /// Sends a KEYS command to the server, see [Connection::keys]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn keys<C, R>(&self, cache: C) -> Result<Vec<R>, CachemError>
+    where
+        C: Into<u8>,
+        R: Parse + Send + Sync {
+
+        let req = Connection::request(Command::Keys, cache);
+        self.send::<Vec<R>>(req).await
+    }
+
+    /// Sends a EXISTS command to the server, see [Connection::exists]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn exists<C, I>(&self, cache: C, idx: I) -> Result<bool, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Connection::request(Command::Exists, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<bool>(req).await
+    }
+
+    /// Sends a MEXISTS command to the server, see [Connection::mexists]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn mexists<C, I>(&self, cache: C, ids: Vec<I>) -> Result<Vec<bool>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::MExists, cache);
+        self.length_encoding.encode(&ids, &mut req).await?;
+        self.send::<Vec<bool>>(req).await
+    }
+
+    /// Sends a SET command to the server, see [Connection::set]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn set<C, I, D>(&self, cache: C, idx: I, data: D) -> Result<(), CachemError>
+    where
+        C: Into<u8>,
+        I: Parse,
+        D: Parse {
+
+        let mut req = Connection::request(Command::Set, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.length_encoding.encode(&data, &mut req).await?;
+        self.send::<()>(req).await
+    }
+
+    /// Sends a MSET command to the server, see [Connection::mset]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn mset<C, I, D>(&self, cache: C, data: HashMap<I, D>) -> Result<(), CachemError>
+    where
+        C: Into<u8>,
+        I: Parse + Eq + Hash + Send + Sync,
+        D: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::MSet, cache);
+        self.length_encoding.encode(&data, &mut req).await?;
+        self.send::<()>(req).await
+    }
+
+    /// Sends a DEL command to the server, see [Connection::del]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn del<C, I>(&self, cache: C, idx: I) -> Result<(), CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Connection::request(Command::Del, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<()>(req).await
+    }
+
+    /// Sends a MDEL command to the server, see [Connection::mdel]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn mdel<C, I>(&self, cache: C, ids: Vec<I>) -> Result<(), CachemError>
+    where
+        C: Into<u8>,
+        I: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::MDel, cache);
+        self.length_encoding.encode(&ids, &mut req).await?;
+        self.send::<()>(req).await
+    }
+
+    /// Sends a EXPIRE command to the server, see [Connection::expire]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn expire<C, I>(&self, cache: C, idx: I, ttl: Duration) -> Result<bool, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Connection::request(Command::Expire, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        (ttl.as_millis() as u64).write(&mut req).await?;
+        self.send::<bool>(req).await
+    }
+
+    /// Sends a SET_WITH_TTL command to the server, see
+    /// [Connection::set_with_ttl]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn set_with_ttl<C, I, D>(
+        &self,
+        cache: C,
+        idx:   I,
+        data:  D,
+        ttl:   Duration,
+    ) -> Result<(), CachemError>
+    where
+        C: Into<u8>,
+        I: Parse,
+        D: Parse {
+
+        let mut req = Connection::request(Command::SetWithTtl, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.length_encoding.encode(&data, &mut req).await?;
+        (ttl.as_millis() as u64).write(&mut req).await?;
+        self.send::<()>(req).await
+    }
+
+    /// Sends a TTL command to the server, see [Connection::ttl]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn ttl<C, I>(&self, cache: C, idx: I) -> Result<Option<Duration>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Connection::request(Command::Ttl, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<Option<u64>>(req)
+            .await
+            .map(|x| x.map(Duration::from_millis))
+    }
+
+    /// Sends a PERSIST command to the server, see [Connection::persist]
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed or
+    /// [CachemError::Remote] when the server answered with an error
+    ///
+    pub async fn persist<C, I>(&self, cache: C, idx: I) -> Result<bool, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Connection::request(Command::Persist, cache);
+        self.length_encoding.encode(&idx, &mut req).await?;
+        self.send::<bool>(req).await
+    }
+
+    /// Hands the request to the background task and waits for the reply
+    ///
+    /// # Params
+    ///
+    /// * `req` - Body of the request
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::NotReachable] if the connection is closed
+    ///
+    /// # Returns
+    ///
+    /// Parsed payload of the response
+    ///
+    async fn send<R>(&self, req: Vec<u8>) -> Result<R, CachemError>
+    where
+        R: Parse {
+
+        let (tx, rx) = oneshot::channel();
+        self.requests
+            .send((req, tx))
+            .await
+            .map_err(|_| CachemError::NotReachable)?;
+
+        let res = rx.await.map_err(|_| CachemError::NotReachable)?;
+        self.length_encoding
+            .scope(Response::read::<R, _>(&mut Cursor::new(res)))
+            .await
+    }
+
+    /// Background task that tags all incoming requests with an id and writes
+    /// them to the socket.
+    ///
+    /// # Params
+    ///
+    /// * `writer`  - Write half of the socket
+    /// * `rx`      - Receiver for new requests
+    /// * `pending` - Requests that wait for their reply
+    /// * `next_id` - Id for the next request
+    ///
+    async fn write_requests(
+        writer:      OwnedWriteHalf,
+        mut rx:      mpsc::Receiver<Request>,
+        pending:     Pending,
+        mut next_id: u32,
+    ) {
+        let mut writer = BufWriter::new(writer);
+
+        while let Some(request) = rx.recv().await {
+            let mut next = Some(request);
+            while let Some((req, reply)) = next {
+                let id = next_id;
+                next_id = next_id.wrapping_add(1);
+
+                if let Some(x) = pending.lock().unwrap().as_mut() {
+                    x.insert(id, reply);
+                } else {
+                    // the reader is gone, dropping the reply sender lets the
+                    // caller know that the connection is closed
+                    return;
+                }
+
+                if Frame::new(id, req).write(&mut writer).await.is_err() {
+                    return;
+                }
+
+                // write all requests that are already waiting before flushing
+                next = rx.recv().now_or_never().flatten();
+            }
+
+            if writer.flush().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Background task that reads all replies and hands them to the waiting
+    /// caller.
+    ///
+    /// # Params
+    ///
+    /// * `reader`   - Read half of the socket
+    /// * `pending`  - Requests that wait for their reply
+    /// * `max_size` - Maximum size of a reply in bytes
+    ///
+    async fn read_replies(
+        reader:   OwnedReadHalf,
+        pending:  Pending,
+        max_size: u32,
+    ) {
+        let mut reader = BufReader::new(reader);
+
+        loop {
+            let (id, body) = match Frame::read(&mut reader, max_size).await {
+                Ok(x) => (x.id, x.body),
+                // the reply was discarded, the caller gets the error instead
+                Err(CachemError::FrameTooLarge { id, size, max }) => {
+                    let e = CachemError::FrameTooLarge { id, size, max };
+                    let mut body = Vec::new();
+                    if Response::from(e).write(&mut body).await.is_err() {
+                        break;
+                    }
+                    (id, body)
+                },
+                Err(_) => break,
+            };
+
+            let reply = pending
+                .lock()
+                .unwrap()
+                .as_mut()
+                .and_then(|x| x.remove(&id));
+
+            if let Some(reply) = reply {
+                let _ = reply.send(body);
+            } else {
+                log::warn!("Received reply for unknown request {}", id);
+            }
+        }
+
+        // drops all waiting requests
+        pending.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cache, Hello, PROTOCOL_VERSION, Server};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::RwLock;
+
+    /// Stores `u32` values, requests for smaller keys take longer so that
+    /// later requests may be answered first
+    #[derive(Default)]
+    struct Shuffle {
+        /// Stored values
+        entries: RwLock<HashMap<u32, u32>>,
+        /// Number of requests that are currently executed
+        active:  AtomicUsize,
+        /// Highest number of requests that were executed at the same time
+        max:     AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Cache for Shuffle {
+        fn name(&self) -> String {
+            "shuffle".into()
+        }
+
+        async fn handle(
+            &self,
+            cmd: Command,
+            req: &mut Cursor<Vec<u8>>,
+            res: &mut Vec<u8>,
+        ) -> Result<(), CachemError> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(active, Ordering::SeqCst);
+
+            let id = u32::read(req).await?;
+            let delay = 3 - id as u64 % 4;
+            tokio::time::sleep(Duration::from_millis(delay * 5)).await;
+
+            let result = match cmd {
+                Command::Set => {
+                    let value = u32::read(req).await?;
+                    self.entries.write().await.insert(id, value);
+                    Ok(())
+                },
+                Command::Get => {
+                    let value = self.entries.read().await.get(&id).copied();
+                    value.write(res).await
+                },
+                _ => Err(CachemError::Empty),
+            };
+
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+
+        async fn cnc_listener(&self) {  }
+    }
+
+    /// Opens a connection to the given address and does the handshake
+    async fn connect(addr: &str) -> MultiplexedConnection {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut connection = Connection::new(stream);
+        connection.hello().await.unwrap();
+        MultiplexedConnection::new(connection)
+    }
+
+    #[tokio::test]
+    async fn test_replies_are_matched_to_their_request() {
+        let shuffle = Arc::new(Shuffle::default());
+        let (_, mut server) = Server::new("127.0.0.1:47015".into());
+        server
+            .add(0u8, shuffle.clone())
+            .set_max_concurrent_requests(4);
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            server.run_until(async { let _ = rx.await; }).await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let conn = connect("127.0.0.1:47015").await;
+        let mut tasks = Vec::new();
+        for i in 0..64u32 {
+            let conn = conn.clone();
+            tasks.push(tokio::spawn(async move {
+                conn.set(0u8, i, i * 10).await.unwrap();
+                let value = conn.get::<_, _, u32>(0u8, i).await.unwrap();
+                let missing = conn.get::<_, _, u32>(0u8, i + 1000).await.unwrap();
+                (i, value, missing)
+            }));
+        }
+
+        for task in tasks {
+            let (i, value, missing) = task.await.unwrap();
+            assert_eq!(value, Some(i * 10));
+            assert_eq!(missing, None);
+        }
+
+        // the requests were executed at the same time, but never more than
+        // the server allows
+        let max = shuffle.max.load(Ordering::SeqCst);
+        assert!(max > 1 && max <= 4, "max = {}", max);
+
+        drop(conn);
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pending_requests_fail_when_the_socket_closes() {
+        let listener = TcpListener::bind("127.0.0.1:47016").await.unwrap();
+
+        // answers the handshake, reads three requests without answering
+        // them and closes the socket
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = BufReader::new(socket);
+
+            let frame = Frame::read(&mut socket, Frame::DEFAULT_MAX_SIZE).await.unwrap();
+            let hello = Hello {
+                version:         PROTOCOL_VERSION,
+                name:            "closing".into(),
+                caches:          vec![0u8],
+                length_encoding: LengthEncoding::Fixed,
+            };
+            let mut payload = Vec::new();
+            hello.write(&mut payload).await.unwrap();
+            let mut body = Vec::new();
+            Response::Ok(payload).write(&mut body).await.unwrap();
+            Frame::new(frame.id, body).write(socket.get_mut()).await.unwrap();
+
+            for _ in 0..3 {
+                Frame::read(&mut socket, Frame::DEFAULT_MAX_SIZE).await.unwrap();
+            }
+        });
+
+        let conn = connect("127.0.0.1:47016").await;
+        let mut tasks = Vec::new();
+        for i in 0..3u32 {
+            let conn = conn.clone();
+            tasks.push(tokio::spawn(async move {
+                conn.get::<_, _, u32>(0u8, i).await
+            }));
+        }
+
+        server.await.unwrap();
+        for task in tasks {
+            assert!(matches!(task.await.unwrap(), Err(CachemError::NotReachable)));
+        }
+        // requests after the socket was closed fail as well
+        assert!(matches!(
+            conn.get::<_, _, u32>(0u8, 0u32).await,
+            Err(CachemError::NotReachable)
+        ));
+    }
+}
@@ -0,0 +1,411 @@
+use crate::{CachemError, Command, Connection, Frame, LengthEncoding, Parse, Response};
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Cursor;
+use std::marker::PhantomData;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+
+/// Queues multiple requests and sends them to the server at once.
+///
+/// Every queued request returns a [Ticket]. After [Pipeline::flush] the
+/// tickets are used to take the matching reply from the returned [Replies].
+///
+/// The requests are executed by the server in the order they were queued.
+///
+/// # Example
+///
+/// ```no_run
+/// # use cachem::*;
+/// enum CacheName { A }
+/// impl Into<u8> for CacheName {
+///     fn into(self) -> u8 { 0u8 }
+/// }
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// // creates a new pool with one connection
+/// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize).await?;
+/// // get a connection
+/// let mut conn = pool.acquire().await?;
+///
+/// let mut pipeline = conn.pipeline();
+/// for i in 0..1000u32 {
+///     pipeline.set(CacheName::A, i, i * 2).await?;
+/// }
+/// let ticket = pipeline.get::<_, _, u32>(CacheName::A, 5u32).await?;
+///
+/// let mut replies = pipeline.flush().await?;
+/// assert_eq!(replies.take(ticket).await?, Some(10u32));
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pipeline<'a> {
+    /// Connection the requests are send over
+    connection: &'a mut Connection,
+    /// All queued requests
+    requests:   Vec<Frame>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Creates a new empty pipeline
+    ///
+    /// # Params
+    ///
+    /// * `connection` - Connection the requests are send over
+    ///
+    pub fn new(connection: &'a mut Connection) -> Self {
+        Self {
+            connection,
+            requests: Vec::new(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Number of queued requests
+    ///
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// # Returns
+    ///
+    /// `true` if there are no queued requests
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Queues a GET command, see [Connection::get]
+    ///
+    /// # Errors
+    ///
+    /// If the payload of the request could not be encoded
+    ///
+    pub async fn get<C, I, R>(&mut self, cache: C, idx: I) -> Result<Ticket<Option<R>>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse,
+        R: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::Get, cache);
+        self.connection.length_encoding().encode(&idx, &mut req).await?;
+        Ok(self.push(req))
+    }
+
+    /// Queues a MGET command, see [Connection::mget]
+    ///
+    /// # Errors
+    ///
+    /// If the payload of the request could not be encoded
+    ///
+    pub async fn mget<C, I, R>(&mut self, cache: C, ids: Vec<I>) -> Result<Ticket<Vec<Option<R>>>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse + Send + Sync,
+        R: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::MGet, cache);
+        self.connection.length_encoding().encode(&ids, &mut req).await?;
+        Ok(self.push(req))
+    }
+
+    /// Queues a EXISTS command, see [Connection::exists]
+    ///
+    /// # Errors
+    ///
+    /// If the payload of the request could not be encoded
+    ///
+    pub async fn exists<C, I>(&mut self, cache: C, idx: I) -> Result<Ticket<bool>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Connection::request(Command::Exists, cache);
+        self.connection.length_encoding().encode(&idx, &mut req).await?;
+        Ok(self.push(req))
+    }
+
+    /// Queues a SET command, see [Connection::set]
+    ///
+    /// # Errors
+    ///
+    /// If the payload of the request could not be encoded
+    ///
+    pub async fn set<C, I, D>(&mut self, cache: C, idx: I, data: D) -> Result<Ticket<()>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse,
+        D: Parse {
+
+        let mut req = Connection::request(Command::Set, cache);
+        self.connection.length_encoding().encode(&idx, &mut req).await?;
+        self.connection.length_encoding().encode(&data, &mut req).await?;
+        Ok(self.push(req))
+    }
+
+    /// Queues a MSET command, see [Connection::mset]
+    ///
+    /// # Errors
+    ///
+    /// If the payload of the request could not be encoded
+    ///
+    pub async fn mset<C, I, D>(&mut self, cache: C, data: HashMap<I, D>) -> Result<Ticket<()>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse + Eq + Hash + Send + Sync,
+        D: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::MSet, cache);
+        self.connection.length_encoding().encode(&data, &mut req).await?;
+        Ok(self.push(req))
+    }
+
+    /// Queues a DEL command, see [Connection::del]
+    ///
+    /// # Errors
+    ///
+    /// If the payload of the request could not be encoded
+    ///
+    pub async fn del<C, I>(&mut self, cache: C, idx: I) -> Result<Ticket<()>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse {
+
+        let mut req = Connection::request(Command::Del, cache);
+        self.connection.length_encoding().encode(&idx, &mut req).await?;
+        Ok(self.push(req))
+    }
+
+    /// Queues a MDEL command, see [Connection::mdel]
+    ///
+    /// # Errors
+    ///
+    /// If the payload of the request could not be encoded
+    ///
+    pub async fn mdel<C, I>(&mut self, cache: C, ids: Vec<I>) -> Result<Ticket<()>, CachemError>
+    where
+        C: Into<u8>,
+        I: Parse + Send + Sync {
+
+        let mut req = Connection::request(Command::MDel, cache);
+        self.connection.length_encoding().encode(&ids, &mut req).await?;
+        Ok(self.push(req))
+    }
+
+    /// Sends all queued requests and waits for all replies.
+    ///
+    /// Writing the requests and reading the replies is done at the same time,
+    /// so that the server is never blocked by a full socket.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the connection breaks. Errors of single requests are returned
+    /// when the reply is taken with [Replies::take], this includes replies
+    /// that are larger than [Connection::max_frame_size].
+    ///
+    /// # Returns
+    ///
+    /// All replies of the server
+    ///
+    pub async fn flush(self) -> Result<Replies, CachemError> {
+        let count = self.requests.len();
+        let requests = self.requests;
+        let length_encoding = self.connection.length_encoding();
+        let max_frame_size = self.connection.max_frame_size();
+        // stays set if the caller is cancelled or the connection breaks
+        self.connection.set_awaiting_reply(true);
+        let (reader, writer) = self.connection
+            .as_mut()
+            .get_mut()
+            .split();
+
+        let write = async move {
+            let mut writer = BufWriter::new(writer);
+            for request in requests {
+                request.write(&mut writer).await?;
+            }
+            writer.flush().await?;
+            Ok::<_, CachemError>(())
+        };
+        let read = async move {
+            let mut reader = BufReader::new(reader);
+            let mut replies = HashMap::with_capacity(count);
+            for _ in 0..count {
+                match Frame::read(&mut reader, max_frame_size).await {
+                    Ok(frame) => {
+                        replies.insert(frame.id, Ok(frame.body));
+                    },
+                    // the body was discarded, the remaining replies can
+                    // still be read
+                    Err(CachemError::FrameTooLarge { id, size, max }) => {
+                        let e = CachemError::FrameTooLarge { id, size, max };
+                        replies.insert(id, Err(e));
+                    },
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok::<_, CachemError>(replies)
+        };
+
+        let (write, read) = tokio::join!(write, read);
+        write?;
+        let replies = read?;
+        self.connection.set_awaiting_reply(false);
+        Ok(Replies {
+            replies,
+            length_encoding,
+        })
+    }
+
+    /// Adds a new request to the queue
+    ///
+    /// # Params
+    ///
+    /// * `req` - Body of the request
+    ///
+    /// # Returns
+    ///
+    /// Ticket for taking the reply
+    ///
+    fn push<R>(&mut self, req: Vec<u8>) -> Ticket<R> {
+        let id = self.connection.next_id();
+        self.requests.push(Frame::new(id, req));
+        Ticket::new(id)
+    }
+}
+
+/// Identifies the reply of a request that was queued in a [Pipeline]
+#[derive(Debug)]
+pub struct Ticket<R> {
+    /// Id of the request
+    id:      u32,
+    /// Type of the reply
+    _marker: PhantomData<fn() -> R>,
+}
+
+impl<R> Ticket<R> {
+    /// Creates a new ticket for the given request id
+    fn new(id: u32) -> Self {
+        Self {
+            id,
+            _marker: PhantomData,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Id of the request
+    ///
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// All replies of a flushed [Pipeline]
+#[derive(Debug, Default)]
+pub struct Replies {
+    /// Bodies of the replies by their request id, or the error if the reply
+    /// could not be read
+    replies:         HashMap<u32, Result<Vec<u8>, CachemError>>,
+    /// Encoding of collection lengths, taken from the connection
+    length_encoding: LengthEncoding,
+}
+
+impl Replies {
+    /// Takes the reply that matches the ticket and parses it
+    ///
+    /// # Params
+    ///
+    /// * `ticket` - Ticket that was returned when queueing the request
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::Remote] if the server answered the request with an
+    /// error, [CachemError::FrameTooLarge] if the reply was larger than the
+    /// maximum frame size of the connection, [CachemError::Empty] if there
+    /// is no reply for the ticket
+    ///
+    /// # Returns
+    ///
+    /// Parsed reply
+    ///
+    pub async fn take<R>(&mut self, ticket: Ticket<R>) -> Result<R, CachemError>
+    where
+        R: Parse {
+
+        let body = self.replies
+            .remove(&ticket.id)
+            .ok_or(CachemError::Empty)??;
+        self.length_encoding
+            .scope(Response::read::<R, _>(&mut Cursor::new(body)))
+            .await
+    }
+
+    /// # Returns
+    ///
+    /// Number of replies that were not taken yet
+    ///
+    pub fn len(&self) -> usize {
+        self.replies.len()
+    }
+
+    /// # Returns
+    ///
+    /// `true` if all replies were taken
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.replies.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConnectionPool, MapCache, Server};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_too_large_reply_keeps_the_other_replies() {
+        let (_, mut server) = Server::new("127.0.0.1:47017".into());
+        server.add(0u8, Arc::new(MapCache::<u32, String>::default()));
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            server.run_until(async { let _ = rx.await; }).await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let pool = ConnectionPool::new("127.0.0.1:47017", 1)
+            .await
+            .unwrap()
+            .with_max_frame_size(64);
+        let mut conn = pool.acquire().await.unwrap();
+        conn.set(0u8, 1u32, String::from("a")).await.unwrap();
+        conn.set(0u8, 2u32, "b".repeat(128)).await.unwrap();
+        conn.set(0u8, 3u32, String::from("c")).await.unwrap();
+
+        let mut pipeline = conn.pipeline();
+        let first = pipeline.get::<_, _, String>(0u8, 1u32).await.unwrap();
+        let large = pipeline.get::<_, _, String>(0u8, 2u32).await.unwrap();
+        let last = pipeline.get::<_, _, String>(0u8, 3u32).await.unwrap();
+        let mut replies = pipeline.flush().await.unwrap();
+
+        assert!(matches!(
+            replies.take(large).await,
+            Err(CachemError::FrameTooLarge { .. })
+        ));
+        assert_eq!(replies.take(first).await.unwrap(), Some("a".into()));
+        assert_eq!(replies.take(last).await.unwrap(), Some("c".into()));
+        assert!(replies.is_empty());
+
+        // all replies were read, the connection can still be used
+        assert_eq!(conn.get::<_, _, String>(0u8, 1u32).await.unwrap(), Some("a".into()));
+
+        drop(conn);
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+}
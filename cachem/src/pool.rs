@@ -1,12 +1,14 @@
-use crate::{CachemError, ConnectionPoolError};
+use crate::{CachemError, ConnectionPoolError, Frame, Hello, LengthEncoding};
 
 use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::net::TcpStream;
-use tokio::time::{Duration, sleep};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, sleep, timeout};
 
-use super::{Connection, ConnectionGuard};
+use super::{Connection, ConnectionGuard, MultiplexedConnection};
 
 /// Manages connections to the database.
 ///
@@ -14,6 +16,11 @@ use super::{Connection, ConnectionGuard};
 /// To request a new connection use [`ConnectionPool::acquire()`].
 /// The connection is returned when the variable is dropped.
 ///
+/// If all connections are in use, [`ConnectionPool::acquire()`] waits until
+/// a connection is released. Waiting callers get the connections in the
+/// order they started waiting. See [`ConnectionPool::metrics()`] for how
+/// long callers wait.
+///
 /// ## Example:
 /// ```no_run
 /// # use cachem::*;
@@ -33,21 +40,33 @@ use super::{Connection, ConnectionGuard};
 ///
 #[derive(Clone)]
 pub struct ConnectionPool {
-    /// Number of available connections
-    available:    Arc<AtomicUsize>,
+    /// One permit for every available connection, waiting callers are
+    /// served in order
+    available:    Arc<Semaphore>,
     /// Size of the pool
     pool_size:    Arc<AtomicUsize>,
     /// When a dead connection is encoutered, this will be set to true
     has_dead_con: Arc<AtomicBool>,
 
     /// Holds all active connection
-    connections: Arc<Mutex<VecDeque<Connection>>>,
+    connections:     Arc<Mutex<VecDeque<Connection>>>,
+    /// Information the server send during the last handshake
+    server:          Arc<Mutex<Option<Hello>>>,
+    /// Statistics about waiting for connections
+    metrics:         Arc<Mutex<PoolMetrics>>,
     /// IP-Address to the database server
-    url:         &'static str,
+    url:             &'static str,
+    /// Encoding of collection lengths that is requested for every connection
+    length_encoding: LengthEncoding,
+    /// Maximum size of a response in bytes, shared with the reconnect task
+    max_frame_size:  Arc<AtomicU32>,
+    /// Maximum time [ConnectionPool::acquire] waits for a connection
+    acquire_timeout: Duration,
 }
 
 impl ConnectionPool {
-    /// Timeout for acquiring a connection from the pool, in milliseconds
+    /// Default timeout for acquiring a connection from the pool, in
+    /// milliseconds
     const ACQUIRE_TIMEOUT_MSEC:   u64 = 1000u64;
     /// Interval when the subtask checkes if there are broken connection, in
     /// milliseconds
@@ -62,18 +81,74 @@ impl ConnectionPool {
     /// * `url`   - Ip address + port of the database server
     /// * `count` - Number of connection to store
     ///
+    /// # Errors
+    ///
+    /// If a connection cannot be opened or the handshake fails
+    ///
     /// # Returns
     ///
     /// New pool containing the given number of connections
     ///
     pub async fn new(url: &'static str, count: usize) -> Result<Self, CachemError> {
+        Self::with_length_encoding(url, count, LengthEncoding::Fixed).await
+    }
+
+    /// Creates a new pool whose connections ask the server for the given
+    /// [LengthEncoding] during the handshake. The server may refuse
+    /// [LengthEncoding::Compact], see [Connection::length_encoding].
+    ///
+    /// # Params
+    ///
+    /// * `url`      - Ip address + port of the database server
+    /// * `count`    - Number of connection to store
+    /// * `encoding` - Encoding of collection lengths
+    ///
+    /// # Errors
+    ///
+    /// If a connection cannot be opened or the handshake fails
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it held the lock of the pool
+    ///
+    /// # Returns
+    ///
+    /// New pool containing the given number of connections
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let pool = ConnectionPool::with_length_encoding(
+    ///     "127.0.0.1:1337".into(),
+    ///     1usize,
+    ///     LengthEncoding::Compact
+    /// ).await?;
+    /// let conn = pool.acquire().await?;
+    /// assert_eq!(conn.length_encoding(), LengthEncoding::Compact);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn with_length_encoding(
+        url:      &'static str,
+        count:    usize,
+        encoding: LengthEncoding,
+    ) -> Result<Self, CachemError> {
         let pool = Self {
-            available:    Arc::new(AtomicUsize::new(count)),
+            available:    Arc::new(Semaphore::new(count)),
             pool_size:    Arc::new(AtomicUsize::new(count)),
             has_dead_con: Arc::new(AtomicBool::new(false)),
 
-            connections: Arc::new(Mutex::new(VecDeque::new())),
+            connections:     Arc::new(Mutex::new(VecDeque::new())),
+            server:          Arc::new(Mutex::new(None)),
+            metrics:         Arc::new(Mutex::new(PoolMetrics::default())),
             url,
+            length_encoding: encoding,
+            max_frame_size:  Arc::new(AtomicU32::new(Frame::DEFAULT_MAX_SIZE)),
+            acquire_timeout: Duration::from_millis(Self::ACQUIRE_TIMEOUT_MSEC),
         };
 
         let mut connections = VecDeque::new();
@@ -87,43 +162,128 @@ impl ConnectionPool {
         Ok(pool)
     }
 
+    /// Sets how long [ConnectionPool::acquire] waits for a connection,
+    /// defaults to one second
+    ///
+    /// # Params
+    ///
+    /// * `timeout` - Maximum time to wait
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize)
+    ///     .await?
+    ///     .with_acquire_timeout(Duration::from_secs(5));
+    ///
+    /// let first = pool.acquire().await?;
+    /// // waits until the first connection is released, at most 5 seconds
+    /// let second = pool.acquire().await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub fn with_acquire_timeout(mut self, timeout: Duration) -> Self {
+        self.acquire_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum size of a response for all connections of the pool,
+    /// see [Connection::set_max_frame_size]
+    ///
+    /// # Params
+    ///
+    /// * `size` - Maximum size in bytes, defaults to [Frame::DEFAULT_MAX_SIZE]
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it held the lock of the pool
+    ///
+    pub fn with_max_frame_size(self, size: u32) -> Self {
+        self.max_frame_size.store(size, Ordering::SeqCst);
+        for con in self.connections.lock().unwrap().iter_mut() {
+            con.set_max_frame_size(size);
+        }
+        self
+    }
+
     /// # Returns
     ///
     /// The number of currently available connections in the pool
     ///
     pub fn available_connections(&self) -> usize {
-        self.available.load(Ordering::SeqCst)
+        self.available.available_permits()
     }
 
-    /// Tries to acquire a connection in the given timeframe set by
-    /// Self::ACQUIRE_TIMEOUT_MSEC.
-    /// If there was no connection available it returns an error.
+    /// # Returns
     ///
+    /// Statistics about waiting for connections, shared by all clones of the
+    /// pool
+    ///
+    /// # Panics
+    ///
+    /// If another thread panicked while it held the lock of the pool
+    ///
+    pub fn metrics(&self) -> PoolMetrics {
+        *self.metrics.lock().unwrap()
+    }
+
     /// # Returns
     ///
-    /// If successful a connection from the pool, if not a
-    /// [ConnectionPoolError::TimeoutGettingConnection] error.
+    /// Information about the server that was exchanged during the last
+    /// handshake
     ///
-    pub async fn acquire(&self) -> Result<ConnectionGuard, CachemError> {
-        let sleep = sleep(Duration::from_millis(Self::ACQUIRE_TIMEOUT_MSEC));
-        tokio::pin!(sleep);
+    /// # Panics
+    ///
+    /// If another thread panicked while it held the lock of the pool
+    ///
+    pub fn server(&self) -> Option<Hello> {
+        self.server.lock().unwrap().clone()
+    }
 
-        tokio::select! {
-            _ = &mut sleep => {
-                Err(CachemError::ConnectionPoolError(ConnectionPoolError::TimeoutGettingConnection))
-            }
-            c = self.try_acquire() => {
-                c
-            }
+    /// Acquires a connection. If all connections are in use, it waits until
+    /// a connection is released, at most the time set with
+    /// [ConnectionPool::with_acquire_timeout]. Callers that wait longer are
+    /// served first.
+    ///
+    /// # Errors
+    ///
+    /// [ConnectionPoolError::TimeoutGettingConnection] if no connection was
+    /// released in time, [ConnectionPoolError::NoConnectionAvailable] if the
+    /// pool has a dead connection and [ConnectionPoolError::CannotConnect]
+    /// if the healthcheck failed
+    ///
+    /// # Returns
+    ///
+    /// Connection that is returned to the pool when it is dropped
+    ///
+    pub async fn acquire(&self) -> Result<ConnectionGuard, CachemError> {
+        // Make sure that there is no dead connection
+        if self.has_dead_con.load(Ordering::SeqCst) {
+            log::error!("Dead connection");
+            return Err(CachemError::ConnectionPoolError(ConnectionPoolError::NoConnectionAvailable));
         }
+
+        let permit = match self.available.clone().try_acquire_owned() {
+            Ok(x)  => x,
+            Err(_) => self.wait_for_permit().await?,
+        };
+        self.take_connection(permit).await
     }
 
     /// Tries to instantly get a connection from the pool.
     ///
-    /// # Returns
+    /// # Errors
     ///
     /// An error if there is either a dead connection, there are no connections
     /// in the pool or the healthcheck failed.
+    ///
+    /// # Returns
+    ///
     /// If successful if will return a [`ConnectionGuard`].
     ///
     pub async fn try_acquire(&self) -> Result<ConnectionGuard, CachemError> {
@@ -135,25 +295,29 @@ impl ConnectionPool {
 
         // Before locking the connections mutex, check if there are connections
         // available, if not return an error
-        if self.available.load(Ordering::SeqCst) == 0 {
-            log::warn!("No connection available");
-            return Err(CachemError::ConnectionPoolError(ConnectionPoolError::NoConnectionAvailable));
-        }
-
-        // Required, removing this will cause some problems regarding Send and await
-        let con = { self.connections.lock().unwrap() }.pop_front();
-        self.available.fetch_sub(1, Ordering::SeqCst);
-        if let Some(mut con) = con {
-            if con.is_healthy().await {
-                Ok(ConnectionGuard::new(self.clone(), con))
-            } else {
-                // Connection is dead, set the flag
-                self.has_dead_con.store(true, Ordering::Relaxed);
-                Err(CachemError::ConnectionPoolError(ConnectionPoolError::CannotConnect))
+        let permit = match self.available.clone().try_acquire_owned() {
+            Ok(x)  => x,
+            Err(_) => {
+                log::warn!("No connection available");
+                return Err(CachemError::ConnectionPoolError(ConnectionPoolError::NoConnectionAvailable));
             }
-        } else {
-            Err(CachemError::ConnectionPoolError(ConnectionPoolError::NoConnectionAvailable))
-        }
+        };
+        self.take_connection(permit).await
+    }
+
+    /// Opens a new connection that is not managed by the pool and turns it
+    /// into a [MultiplexedConnection]
+    ///
+    /// # Errors
+    ///
+    /// If the connection cannot be opened or the handshake fails
+    ///
+    /// # Returns
+    ///
+    /// New multiplexed connection
+    ///
+    pub async fn multiplexed(&self) -> Result<MultiplexedConnection, CachemError> {
+        Ok(self.connect().await?.into_multiplexed())
     }
 
     /// Releases a connection back into the connection pool
@@ -164,10 +328,108 @@ impl ConnectionPool {
     ///
     pub(crate) fn release(&self, connection: Connection) {
         self.connections.lock().unwrap().push_back(connection);
-        self.available.fetch_add(1, Ordering::SeqCst);
+        self.available.add_permits(1);
+    }
+
+    /// Waits in the queue until a connection is released or the timeout
+    /// passed
+    ///
+    /// # Errors
+    ///
+    /// [ConnectionPoolError::TimeoutGettingConnection] if no connection was
+    /// released in time
+    ///
+    /// # Returns
+    ///
+    /// Permit for taking a connection
+    ///
+    async fn wait_for_permit(&self) -> Result<OwnedSemaphorePermit, CachemError> {
+        let start = Instant::now();
+        let queue = QueueEntry::new(&self.metrics);
+
+        let permit = timeout(self.acquire_timeout, self.available.clone().acquire_owned()).await;
+        queue.leave(start.elapsed(), permit.is_ok());
+
+        match permit {
+            // the semaphore is never closed
+            Ok(x)  => Ok(x.unwrap()),
+            Err(_) => {
+                log::warn!("No connection available after {:?}", self.acquire_timeout);
+                Err(CachemError::ConnectionPoolError(ConnectionPoolError::TimeoutGettingConnection))
+            }
+        }
+    }
+
+    /// Takes a connection out of the pool and checks its health
+    ///
+    /// The permit is kept until the [ConnectionGuard] exists. If the future
+    /// is dropped during the health check, the connection is in an unknown
+    /// state, so it is replaced by a new one in the background.
+    ///
+    /// # Params
+    ///
+    /// * `permit` - Permit for the connection, given back on release
+    ///
+    /// # Errors
+    ///
+    /// [ConnectionPoolError::NoConnectionAvailable] if the pool was emptied
+    /// in the meantime, [ConnectionPoolError::CannotConnect] if the
+    /// healthcheck failed
+    ///
+    /// # Returns
+    ///
+    /// Connection that is returned to the pool when it is dropped
+    ///
+    async fn take_connection(&self, permit: OwnedSemaphorePermit) -> Result<ConnectionGuard, CachemError> {
+        // Required, removing this will cause some problems regarding Send and await
+        let con = { self.connections.lock().unwrap() }.pop_front();
+        let mut con = match con {
+            Some(x) => x,
+            None    => {
+                // the connections were dropped, the permit belongs to none
+                permit.forget();
+                return Err(CachemError::ConnectionPoolError(ConnectionPoolError::NoConnectionAvailable));
+            }
+        };
+
+        let checkout = Checkout {
+            pool:   self,
+            permit: Some(permit),
+        };
+        if con.is_healthy().await {
+            let guard = ConnectionGuard::new(self.clone(), con);
+            // the guard gives the permit back on release
+            checkout.finish();
+            Ok(guard)
+        } else {
+            checkout.finish();
+            // Connection is dead, set the flag
+            self.has_dead_con.store(true, Ordering::Relaxed);
+            Err(CachemError::ConnectionPoolError(ConnectionPoolError::CannotConnect))
+        }
+    }
+
+    /// Opens a new connection and adds it to the pool, used when a
+    /// connection was lost while it was taken out of the pool
+    pub(crate) fn replace_connection(&self) {
+        let pool = self.clone();
+        tokio::task::spawn(async move {
+            match pool.connect().await {
+                Ok(x)  => pool.release(x),
+                Err(e) => {
+                    log::error!("Cannot replace connection; err = {:?}", e);
+                    pool.has_dead_con.store(true, Ordering::SeqCst);
+                }
+            }
+        });
     }
 
-    /// Opens a connection and returns it
+    /// Opens a connection and does the handshake with the server
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::IncompatibleVersion] if the server speaks another
+    /// protocol version
     ///
     /// # Returns
     ///
@@ -177,7 +439,13 @@ impl ConnectionPool {
         let stream = TcpStream::connect(&self.url)
             .await
             .map_err(|_| CachemError::ConnectionPoolError(ConnectionPoolError::CannotConnect))?;
-        Ok(Connection::new(stream))
+        let mut con = Connection::new(stream);
+        con.set_max_frame_size(self.max_frame_size.load(Ordering::SeqCst));
+
+        let hello = con.hello_with(self.length_encoding).await?;
+        *self.server.lock().unwrap() = Some(hello);
+
+        Ok(con)
     }
 
     /// Drops all connections from the pool
@@ -186,7 +454,11 @@ impl ConnectionPool {
         log::warn!("Dropping all connections");
         let mut cons = self.connections.lock().unwrap();
         for _ in 0..cons.len() {
-            self.available.fetch_sub(1, Ordering::SeqCst);
+            // if the permit was already taken, the caller finds no
+            // connection and forgets the permit
+            if let Ok(x) = self.available.try_acquire() {
+                x.forget();
+            }
             std::mem::drop(cons.pop_front());
         }
     }
@@ -217,14 +489,254 @@ impl ConnectionPool {
                             let mut cons = connections_copy.lock().unwrap();
                             cons.push_back(con);
 
-                            self_copy.available.fetch_add(1, Ordering::SeqCst);
+                            self_copy.available.add_permits(1);
                         }
                     }
                 }
                 self_copy.has_dead_con.store(false, Ordering::SeqCst);
-                std::thread::sleep(std::time::Duration::from_millis(Self::CHECK_CONNECTIONS_MSEC));
+                sleep(Duration::from_millis(Self::CHECK_CONNECTIONS_MSEC)).await;
             }
         });
     }
 }
 
+/// Statistics about callers that waited for a connection of a
+/// [ConnectionPool]
+///
+/// Callers that got a connection without waiting are not counted.
+///
+/// # Example
+///
+/// ```no_run
+/// # use cachem::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = ConnectionPool::new("127.0.0.1:1337".into(), 1usize).await?;
+/// let metrics = pool.metrics();
+/// println!(
+///     "{} waiting, {:?} average wait, {} timeouts",
+///     metrics.queue_depth,
+///     metrics.average_wait(),
+///     metrics.timeouts
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// Number of callers that currently wait for a connection
+    pub queue_depth:     usize,
+    /// Highest number of callers that waited at the same time
+    pub max_queue_depth: usize,
+    /// Number of callers that got a connection after waiting
+    pub waited:          u64,
+    /// Number of callers that got no connection in time
+    pub timeouts:        u64,
+    /// Time all callers waited together, including callers that timed out
+    pub total_wait:      Duration,
+    /// Longest time a caller waited
+    pub max_wait:        Duration,
+}
+
+impl PoolMetrics {
+    /// # Returns
+    ///
+    /// Average time a caller waited, including callers that timed out
+    ///
+    pub fn average_wait(&self) -> Duration {
+        let count = self.waited + self.timeouts;
+        if count == 0 {
+            Duration::default()
+        } else {
+            self.total_wait / count as u32
+        }
+    }
+}
+
+/// Permit of a connection that is taken out of the pool. If it is dropped
+/// before [Checkout::finish] was called, the connection was lost and is
+/// replaced.
+struct Checkout<'a> {
+    /// Pool the connection belongs to
+    pool:   &'a ConnectionPool,
+    /// Permit of the connection, `None` after [Checkout::finish]
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<'a> Checkout<'a> {
+    /// Forgets the permit, the connection either gives it back on release
+    /// or is gone
+    fn finish(mut self) {
+        if let Some(x) = self.permit.take() {
+            x.forget();
+        }
+    }
+}
+
+impl<'a> Drop for Checkout<'a> {
+    fn drop(&mut self) {
+        if let Some(x) = self.permit.take() {
+            // the new connection brings its own permit
+            x.forget();
+            self.pool.replace_connection();
+        }
+    }
+}
+
+/// Counts a caller in [PoolMetrics::queue_depth] as long as it exists, even
+/// if the waiting future is dropped
+struct QueueEntry<'a>(&'a Mutex<PoolMetrics>);
+
+impl<'a> QueueEntry<'a> {
+    /// Adds a caller to the queue
+    ///
+    /// # Params
+    ///
+    /// * `metrics` - Metrics of the pool
+    ///
+    fn new(metrics: &'a Mutex<PoolMetrics>) -> Self {
+        let mut x = metrics.lock().unwrap();
+        x.queue_depth += 1;
+        x.max_queue_depth = x.max_queue_depth.max(x.queue_depth);
+        Self(metrics)
+    }
+
+    /// Records the result of waiting and removes the caller from the queue
+    ///
+    /// # Params
+    ///
+    /// * `wait`     - Time the caller waited
+    /// * `acquired` - `true` if the caller got a connection
+    ///
+    fn leave(self, wait: Duration, acquired: bool) {
+        let mut x = self.0.lock().unwrap();
+        if acquired {
+            x.waited += 1;
+        } else {
+            x.timeouts += 1;
+        }
+        x.total_wait += wait;
+        x.max_wait = x.max_wait.max(wait);
+    }
+}
+
+impl<'a> Drop for QueueEntry<'a> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().queue_depth -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    /// Starts a server without any caches, it only answers pings
+    async fn start_server(
+        addr: &str,
+    ) -> (oneshot::Sender<()>, JoinHandle<Result<(), CachemError>>) {
+        let (_, server) = Server::new(addr.into());
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            server.run_until(async { let _ = rx.await; }).await
+        });
+        sleep(Duration::from_millis(100)).await;
+        (tx, server)
+    }
+
+    #[tokio::test]
+    async fn test_waiting_callers_are_served_in_order() {
+        let (tx, server) = start_server("127.0.0.1:47012").await;
+        let pool = ConnectionPool::new("127.0.0.1:47012", 1)
+            .await
+            .unwrap()
+            .with_acquire_timeout(Duration::from_secs(5));
+
+        let conn = pool.acquire().await.unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for i in 0..3usize {
+            let pool = pool.clone();
+            let order = order.clone();
+            waiters.push(tokio::spawn(async move {
+                let _conn = pool.acquire().await.unwrap();
+                order.lock().unwrap().push(i);
+                sleep(Duration::from_millis(10)).await;
+            }));
+            // makes sure the waiters are queued one after another
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(pool.metrics().queue_depth, 3);
+
+        drop(conn);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.queue_depth, 0);
+        assert_eq!(metrics.max_queue_depth, 3);
+        assert_eq!(metrics.waited, 3);
+        assert_eq!(metrics.timeouts, 0);
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_acquire_timeout() {
+        let (tx, server) = start_server("127.0.0.1:47013").await;
+        let pool = ConnectionPool::new("127.0.0.1:47013", 1)
+            .await
+            .unwrap()
+            .with_acquire_timeout(Duration::from_millis(50));
+
+        let conn = pool.acquire().await.unwrap();
+        match pool.acquire().await {
+            Err(CachemError::ConnectionPoolError(
+                ConnectionPoolError::TimeoutGettingConnection
+            )) => (),
+            _ => panic!("Expected a timeout"),
+        }
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.queue_depth, 0);
+        assert_eq!(metrics.timeouts, 1);
+        assert_eq!(metrics.waited, 0);
+        assert!(metrics.max_wait >= Duration::from_millis(50));
+
+        // the connection is still usable after the timeout
+        drop(conn);
+        assert!(pool.acquire().await.is_ok());
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_acquire_keeps_pool_size() {
+        let (tx, server) = start_server("127.0.0.1:47014").await;
+        let pool = ConnectionPool::new("127.0.0.1:47014", 1)
+            .await
+            .unwrap()
+            .with_acquire_timeout(Duration::from_secs(5));
+
+        // polls the acquire once, it stops while the health check waits
+        // for the server and is then dropped
+        let mut acquire = Box::pin(pool.acquire());
+        assert!(futures::poll!(acquire.as_mut()).is_pending());
+        drop(acquire);
+
+        // the lost connection is replaced in the background
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(pool.available_connections(), 1);
+        assert!(pool.acquire().await.is_ok());
+
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+}
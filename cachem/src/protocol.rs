@@ -10,6 +10,13 @@ use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
 
 /// Provides functions to parse a message into an struct
 ///
+/// Collections are prefixed with their length, which is written according to
+/// the [crate::LengthEncoding] of the current task. Outside of
+/// [crate::LengthEncoding::scope] the encoding falls back to
+/// [crate::LengthEncoding::Fixed]. Task-local values are not inherited, so a
+/// future that is moved into `tokio::spawn` uses the fixed encoding, even if
+/// it was created within a scope.
+///
 /// ## Implementation example:
 /// ```
 /// # use async_trait::*;
@@ -0,0 +1,230 @@
+//! Envelope that is written by the server for every request.
+//!
+//! Every response starts with a single status byte. When the status is
+//! [Response::STATUS_OK] the payload written by the cache follows, otherwise
+//! an [ErrorCode] and a message describing the error.
+
+use crate::{CachemError, Parse};
+
+use async_trait::async_trait;
+use std::convert::TryFrom;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// All errors the server may answer with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// There is no cache registered for the requested cache id
+    UnknownCache,
+    /// The command byte is not a valid [crate::Command]
+    UnknownCommand,
+    /// The payload of the request could not be parsed
+    ParseError,
+    /// The cache failed while handling the request
+    HandlerError,
+    /// The protocol version of the client is not supported or the client
+    /// did not start with a handshake
+    IncompatibleVersion,
+    /// The request is larger than the maximum frame size of the server
+    FrameTooLarge,
+}
+
+impl TryFrom<u8> for ErrorCode {
+    type Error = CachemError;
+
+    fn try_from(x: u8) -> Result<Self, Self::Error> {
+        let code = match x {
+            0 => Self::UnknownCache,
+            1 => Self::UnknownCommand,
+            2 => Self::ParseError,
+            3 => Self::HandlerError,
+            4 => Self::IncompatibleVersion,
+            5 => Self::FrameTooLarge,
+            _ => return Err(CachemError::InvalidDiscriminant {
+                name: "ErrorCode".into(),
+                tag:  x,
+            }),
+        };
+        Ok(code)
+    }
+}
+
+impl From<ErrorCode> for u8 {
+    fn from(x: ErrorCode) -> u8 {
+        match x {
+            ErrorCode::UnknownCache        => 0,
+            ErrorCode::UnknownCommand      => 1,
+            ErrorCode::ParseError          => 2,
+            ErrorCode::HandlerError        => 3,
+            ErrorCode::IncompatibleVersion => 4,
+            ErrorCode::FrameTooLarge       => 5,
+        }
+    }
+}
+
+#[async_trait]
+impl Parse for ErrorCode {
+    async fn read<B>(
+        buf: &mut B
+    ) -> Result<Self, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        Self::try_from(u8::read(buf).await?)
+    }
+
+    async fn write<B>(
+        &self,
+        buf: &mut B
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        u8::from(*self).write(buf).await
+    }
+}
+
+/// Response the server writes for every request
+#[derive(Debug)]
+pub enum Response {
+    /// The request was successful, contains the payload written by the cache
+    Ok(Vec<u8>),
+    /// The request failed
+    Err(ErrorCode, String),
+}
+
+impl Response {
+    /// Status byte for a successful request
+    pub const STATUS_OK:  u8 = 0u8;
+    /// Status byte for a failed request
+    pub const STATUS_ERR: u8 = 1u8;
+
+    /// Creates a new error response
+    ///
+    /// # Params
+    ///
+    /// * `code`    - Kind of error
+    /// * `message` - Message that is shown to the client
+    ///
+    pub fn err<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Self::Err(code, message.into())
+    }
+
+    /// Writes the response envelope into the given buffer
+    ///
+    /// # Params
+    ///
+    /// * `buf` - Buffer to write the response into
+    ///
+    /// # Errors
+    ///
+    /// If writing into the buffer fails
+    ///
+    pub async fn write<B>(
+        &self,
+        buf: &mut B,
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        match self {
+            Self::Ok(payload) => {
+                buf.write_u8(Self::STATUS_OK).await?;
+                buf.write_all(payload).await?;
+            },
+            Self::Err(code, message) => {
+                buf.write_u8(Self::STATUS_ERR).await?;
+                code.write(buf).await?;
+                message.write(buf).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a response envelope from the given buffer and parses the
+    /// payload
+    ///
+    /// # Params
+    ///
+    /// * `buf` - Buffer to read the response from
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::Remote] when the server replied with an error, or the
+    /// error of reading the buffer
+    ///
+    /// # Returns
+    ///
+    /// The parsed payload
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use cachem::*;
+    /// # use std::io::Cursor;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut buf = Vec::new();
+    /// Response::err(ErrorCode::UnknownCache, "Could not find cache 5")
+    ///     .write(&mut buf)
+    ///     .await?;
+    ///
+    /// let res = Response::read::<u32, _>(&mut Cursor::new(buf)).await;
+    /// assert!(matches!(
+    ///     res,
+    ///     Err(CachemError::Remote { code: ErrorCode::UnknownCache, .. })
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn read<R, B>(
+        buf: &mut B,
+    ) -> Result<R, CachemError>
+    where
+        R: Parse,
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        if u8::read(buf).await? == Self::STATUS_OK {
+            R::read(buf).await
+        } else {
+            let code = ErrorCode::read(buf).await?;
+            let message = String::read(buf).await?;
+            Err(CachemError::Remote { code, message })
+        }
+    }
+}
+
+impl From<CachemError> for Response {
+    fn from(x: CachemError) -> Self {
+        match x {
+            CachemError::Remote { code, message } => Self::Err(code, message),
+            _ => Self::Err(x.code(), x.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn test_error_code_rw() {
+        let mut buf = Vec::new();
+        ErrorCode::HandlerError.write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![3]);
+
+        let is = ErrorCode::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, ErrorCode::HandlerError);
+    }
+
+    #[tokio::test]
+    async fn test_error_code_unknown() {
+        let is = ErrorCode::read(&mut Cursor::new(vec![200])).await;
+        assert!(matches!(
+            is,
+            Err(CachemError::InvalidDiscriminant { tag: 200, .. })
+        ));
+    }
+}
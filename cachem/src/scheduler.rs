@@ -0,0 +1,473 @@
+//! Takes snapshots of caches in the background.
+//!
+//! Every cache that opted in with [crate::Server::schedule_snapshots] and
+//! supports snapshots, see [crate::Cache::supports_snapshot], gets its own
+//! task. The task calls [crate::Cache::snapshot] when the configured
+//! interval passed or when the configured number of writes was executed,
+//! whatever happens first.
+
+use crate::{Cache, Command};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{watch, Mutex as AsyncMutex, Notify};
+use tokio::time::sleep;
+
+/// Configures when a snapshot of a cache is taken
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # use std::time::Duration;
+/// // snapshot every minute or after 1000 writes
+/// let policy = SnapshotPolicy::default()
+///     .every(Duration::from_secs(60))
+///     .after_writes(1000);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotPolicy {
+    /// Time between two snapshots
+    interval: Option<Duration>,
+    /// Number of writes after that a snapshot is taken
+    writes:   Option<u64>,
+}
+
+impl SnapshotPolicy {
+    /// Takes a snapshot every time the interval passed
+    ///
+    /// # Params
+    ///
+    /// * `interval` - Time between two snapshots
+    ///
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Takes a snapshot after the given number of SET, MSET, DEL and MDEL
+    /// commands. If the snapshot fails, the next write tries again.
+    ///
+    /// # Params
+    ///
+    /// * `writes` - Number of writes, must be at least `1`
+    ///
+    pub fn after_writes(mut self, writes: u64) -> Self {
+        self.writes = Some(writes.max(1));
+        self
+    }
+}
+
+/// Time of the last successful snapshot of every cache.
+///
+/// Cloning is cheap, all clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct Snapshots {
+    /// Time of the last successful snapshot of every cache
+    times:   Arc<Mutex<HashMap<u8, SystemTime>>>,
+    /// Locked while a snapshot of the cache is taken, so that the snapshots
+    /// of a cache are taken one after another and the latest one is kept
+    running: Arc<Mutex<HashMap<u8, Arc<AsyncMutex<()>>>>>,
+}
+
+impl Snapshots {
+    /// Gets the time of the last successful snapshot of the given cache
+    ///
+    /// # Params
+    ///
+    /// * `cache` - Id of the cache
+    ///
+    /// # Returns
+    ///
+    /// `None` if there was no successful snapshot yet
+    ///
+    /// # Panics
+    ///
+    /// If a thread panicked while recording a snapshot
+    ///
+    pub fn last_snapshot<C: Into<u8>>(&self, cache: C) -> Option<SystemTime> {
+        self.times
+            .lock()
+            .unwrap()
+            .get(&cache.into())
+            .copied()
+    }
+
+    /// Takes a snapshot of the given cache and records the time if it was
+    /// successful. Caches that do not support snapshots are skipped, see
+    /// [Cache::supports_snapshot]. Snapshots of the same cache wait for each
+    /// other.
+    ///
+    /// # Params
+    ///
+    /// * `id`    - Id of the cache
+    /// * `cache` - Cache to take the snapshot of
+    ///
+    /// # Returns
+    ///
+    /// `true` if the snapshot was successful
+    ///
+    /// # Panics
+    ///
+    /// If a thread panicked while recording a snapshot
+    ///
+    pub(crate) async fn take(&self, id: u8, cache: &dyn Cache) -> bool {
+        if !cache.supports_snapshot() {
+            return false;
+        }
+
+        let running = self.running
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_default()
+            .clone();
+        let _running = running.lock().await;
+
+        match cache.snapshot().await {
+            Ok(_) => {
+                self.times.lock().unwrap().insert(id, SystemTime::now());
+                true
+            },
+            Err(e) => {
+                log::error!("Failed to save cache {}; err = {:?}", id, e);
+                false
+            },
+        }
+    }
+
+    /// Takes a snapshot of all given caches that support snapshots, see
+    /// [Cache::supports_snapshot]
+    ///
+    /// # Params
+    ///
+    /// * `entries` - Caches to take the snapshot of
+    ///
+    /// # Returns
+    ///
+    /// `true` if all caches support snapshots and all snapshots were
+    /// successful
+    ///
+    pub(crate) async fn take_all(&self, entries: &HashMap<u8, Arc<dyn Cache>>) -> bool {
+        let mut success = true;
+        for (id, cache) in entries.iter() {
+            success &= self.take(*id, cache.as_ref()).await;
+        }
+        success
+    }
+}
+
+/// Counts the writes of a single cache
+#[derive(Debug, Default)]
+pub(crate) struct WriteCounter {
+    /// Writes since the last snapshot
+    count:     AtomicU64,
+    /// Number of writes that trigger a snapshot
+    threshold: Option<u64>,
+    /// Wakes the snapshot task when the threshold is reached
+    notify:    Notify,
+}
+
+impl WriteCounter {
+    /// Counts the command if it changes the cache
+    ///
+    /// # Params
+    ///
+    /// * `cmd` - Command that was executed successfully
+    ///
+    pub(crate) fn record(&self, cmd: Command) {
+        if !cmd.is_write() {
+            return;
+        }
+
+        // the count stays above the threshold after a failed snapshot, so
+        // that every following write tries again
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.threshold.map(|x| count >= x).unwrap_or_default() {
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// Runs the snapshot tasks of all caches that opted in
+pub(crate) struct Scheduler {
+    /// Policy for every cache that opted in
+    policies: HashMap<u8, SnapshotPolicy>,
+    /// Write counter for every cache that opted in
+    counters: HashMap<u8, Arc<WriteCounter>>,
+}
+
+impl Scheduler {
+    /// Creates the write counters for the given policies. Policies of
+    /// unknown caches and caches that do not support snapshots are ignored.
+    ///
+    /// # Params
+    ///
+    /// * `policies` - Policy for every cache that opted in
+    /// * `entries`  - All registered caches
+    ///
+    pub(crate) fn new(
+        policies: HashMap<u8, SnapshotPolicy>,
+        entries:  &HashMap<u8, Arc<dyn Cache>>,
+    ) -> Self {
+        let policies = policies
+            .into_iter()
+            .filter(|(id, _)| match entries.get(id) {
+                Some(x) if x.supports_snapshot() => true,
+                Some(_) => {
+                    log::warn!("Cache {} does not support snapshots, ignoring its policy", id);
+                    false
+                },
+                None => {
+                    log::warn!("Snapshots scheduled for unknown cache {}", id);
+                    false
+                },
+            })
+            .collect::<HashMap<_, _>>();
+
+        let counters = policies
+            .iter()
+            .map(|(id, policy)| {
+                let counter = WriteCounter {
+                    threshold: policy.writes,
+                    ..WriteCounter::default()
+                };
+                (*id, Arc::new(counter))
+            })
+            .collect::<HashMap<_, _>>();
+
+        Self {
+            policies,
+            counters,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Write counter for every cache that opted in
+    ///
+    pub(crate) fn counters(&self) -> HashMap<u8, Arc<WriteCounter>> {
+        self.counters.clone()
+    }
+
+    /// Starts a task for every cache that opted in. The tasks stop when the
+    /// shutdown receiver changes.
+    ///
+    /// # Params
+    ///
+    /// * `entries`   - All registered caches
+    /// * `snapshots` - Records the time of every successful snapshot
+    /// * `shutdown`  - Changes when the server shuts down
+    ///
+    pub(crate) fn start(
+        &self,
+        entries:   &HashMap<u8, Arc<dyn Cache>>,
+        snapshots: Snapshots,
+        shutdown:  watch::Receiver<bool>,
+    ) {
+        for (id, policy) in self.policies.iter() {
+            let cache = if let Some(x) = entries.get(id) {
+                x.clone()
+            } else {
+                continue;
+            };
+
+            tokio::spawn(Self::run(
+                *id,
+                cache,
+                *policy,
+                self.counters[id].clone(),
+                snapshots.clone(),
+                shutdown.clone(),
+            ));
+        }
+    }
+
+    /// Takes snapshots of a single cache until the server shuts down
+    async fn run(
+        id:           u8,
+        cache:        Arc<dyn Cache>,
+        policy:       SnapshotPolicy,
+        counter:      Arc<WriteCounter>,
+        snapshots:    Snapshots,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        loop {
+            let interval = async {
+                match policy.interval {
+                    Some(x) => sleep(x).await,
+                    None    => futures::future::pending::<()>().await,
+                }
+            };
+
+            tokio::select! {
+                _ = interval => (),
+                _ = counter.notify.notified() => (),
+                _ = shutdown.changed() => break,
+            };
+
+            // nothing changed since the last snapshot
+            let count = counter.count.swap(0, Ordering::Relaxed);
+            if count == 0 {
+                continue;
+            }
+            if !snapshots.take(id, cache.as_ref()).await {
+                // the cache is still not saved
+                counter.count.fetch_add(count, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CachemError;
+
+    use async_trait::async_trait;
+    use std::io::Cursor;
+
+    /// Counts the snapshots that were taken
+    #[derive(Default)]
+    struct Counted {
+        /// `true` if the cache supports snapshots
+        supports:  bool,
+        /// Number of snapshots
+        snapshots: AtomicU64,
+        /// Number of snapshots that fail before the next one succeeds
+        failures:  AtomicU64,
+    }
+
+    #[async_trait]
+    impl Cache for Counted {
+        fn name(&self) -> String {
+            "counted".into()
+        }
+
+        async fn handle(
+            &self,
+            _: Command,
+            _: &mut Cursor<Vec<u8>>,
+            _: &mut Vec<u8>,
+        ) -> Result<(), CachemError> {
+            Ok(())
+        }
+
+        async fn cnc_listener(&self) {  }
+
+        async fn snapshot(&self) -> Result<(), CachemError> {
+            let failures = self.failures.load(Ordering::SeqCst);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::SeqCst);
+                return Err(CachemError::Empty);
+            }
+            self.snapshots.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn supports_snapshot(&self) -> bool {
+            self.supports
+        }
+    }
+
+    /// Cache `0` supports snapshots, cache `1` does not
+    fn entries() -> HashMap<u8, Arc<dyn Cache>> {
+        let mut entries: HashMap<u8, Arc<dyn Cache>> = HashMap::new();
+        entries.insert(0u8, Arc::new(Counted { supports: true, ..Counted::default() }));
+        entries.insert(1u8, Arc::new(Counted::default()));
+        entries
+    }
+
+    #[tokio::test]
+    async fn test_take_skips_unsupported() {
+        let snapshots = Snapshots::default();
+        let entries = entries();
+
+        assert!(snapshots.take(0u8, entries[&0u8].as_ref()).await);
+        assert!(snapshots.last_snapshot(0u8).is_some());
+
+        assert!(!snapshots.take(1u8, entries[&1u8].as_ref()).await);
+        assert!(snapshots.last_snapshot(1u8).is_none());
+
+        // not all caches could be saved
+        assert!(!snapshots.take_all(&entries).await);
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_ignores_unsupported() {
+        let mut policies = HashMap::new();
+        policies.insert(0u8, SnapshotPolicy::default().after_writes(1));
+        policies.insert(1u8, SnapshotPolicy::default().after_writes(1));
+        policies.insert(2u8, SnapshotPolicy::default().after_writes(1));
+
+        let scheduler = Scheduler::new(policies, &entries());
+        let counters = scheduler.counters();
+        assert_eq!(counters.len(), 1);
+        assert!(counters.contains_key(&0u8));
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_after_writes() {
+        let cache = Arc::new(Counted { supports: true, ..Counted::default() });
+        let mut entries: HashMap<u8, Arc<dyn Cache>> = HashMap::new();
+        entries.insert(0u8, cache.clone());
+
+        let mut policies = HashMap::new();
+        policies.insert(0u8, SnapshotPolicy::default().after_writes(2));
+
+        let snapshots = Snapshots::default();
+        let (tx, rx) = watch::channel(false);
+        let scheduler = Scheduler::new(policies, &entries);
+        scheduler.start(&entries, snapshots.clone(), rx);
+
+        let counter = scheduler.counters()[&0u8].clone();
+        counter.record(Command::Get);
+        counter.record(Command::Set);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(cache.snapshots.load(Ordering::SeqCst), 0);
+
+        counter.record(Command::Del);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(cache.snapshots.load(Ordering::SeqCst), 1);
+        assert!(snapshots.last_snapshot(0u8).is_some());
+
+        let _ = tx.send(true);
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_retries_failed_snapshot() {
+        let cache = Arc::new(Counted {
+            supports: true,
+            failures: AtomicU64::new(1),
+            ..Counted::default()
+        });
+        let mut entries: HashMap<u8, Arc<dyn Cache>> = HashMap::new();
+        entries.insert(0u8, cache.clone());
+
+        let mut policies = HashMap::new();
+        policies.insert(0u8, SnapshotPolicy::default().after_writes(2));
+
+        let snapshots = Snapshots::default();
+        let (tx, rx) = watch::channel(false);
+        let scheduler = Scheduler::new(policies, &entries);
+        scheduler.start(&entries, snapshots.clone(), rx);
+
+        let counter = scheduler.counters()[&0u8].clone();
+        counter.record(Command::Set);
+        counter.record(Command::Set);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(cache.failures.load(Ordering::SeqCst), 0);
+        assert_eq!(cache.snapshots.load(Ordering::SeqCst), 0);
+        assert!(snapshots.last_snapshot(0u8).is_none());
+
+        // the writes of the failed snapshot are still counted
+        counter.record(Command::Set);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(cache.snapshots.load(Ordering::SeqCst), 1);
+        assert!(snapshots.last_snapshot(0u8).is_some());
+
+        let _ = tx.send(true);
+    }
+}
@@ -1,21 +1,59 @@
-use super::{Cache, Command};
+use super::{Cache, CachemError, Command, ErrorCode, Frame, Hello, LengthEncoding, Parse, PROTOCOL_VERSION, Response};
+use super::{Scheduler, SnapshotPolicy, Snapshots, WriteCounter, WriteLog, WriteLogConfig};
 
 use async_trait::*;
+use futures::FutureExt;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io::{Cursor, ErrorKind};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufStream};
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, Mutex, Semaphore};
 use tokio::sync::watch::{self, Sender, Receiver};
+use tokio::time::timeout;
 
 /// Struct for creating a new database server
 pub struct Server {
     /// Address the server should listen to
-    addr:    String,
+    addr:             String,
+    /// Name of the server that is send to clients during the handshake
+    name:             String,
+    /// Maximum size of a single request in bytes
+    max_frame_size:   u32,
+    /// Maximum number of requests of a single connection that are executed
+    /// at the same time
+    max_concurrent:   usize,
+    /// Time to wait for open requests when shutting down
+    shutdown_timeout: Duration,
+    /// Caches that are saved in the background
+    policies:         HashMap<u8, SnapshotPolicy>,
+    /// Time of the last successful snapshot of every cache
+    snapshots:        Snapshots,
+    /// Configuration of the write log, if enabled
+    write_log:        Option<WriteLogConfig>,
+    /// Time between two sweeps for expired entries
+    sweep_interval:   Duration,
+    /// `true` if clients may use [LengthEncoding::Compact]
+    compact_lengths:  bool,
     /// All manges caches
-    entries: HashMap<u8, Arc<dyn Cache>>,
+    entries:          HashMap<u8, Arc<dyn Cache>>,
 }
 
 impl Server {
+    /// Default for the maximum number of requests of a single connection that
+    /// are executed at the same time. With `1` the requests of a connection
+    /// are executed in the order they were send.
+    pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 1usize;
+    /// Default time to wait for open requests when shutting down, in
+    /// milliseconds
+    pub const DEFAULT_SHUTDOWN_TIMEOUT_MSEC: u64 = 5000u64;
+    /// Default time between two sweeps for expired entries, in milliseconds
+    pub const DEFAULT_SWEEP_INTERVAL_MSEC: u64 = 1000u64;
+
     /// Creates a new server instance
     ///
     /// # Params
@@ -34,7 +72,16 @@ impl Server {
 
         let s = Self {
             addr,
-            entries:      map,
+            name:             "cachem".into(),
+            max_frame_size:   Frame::DEFAULT_MAX_SIZE,
+            max_concurrent:   Self::DEFAULT_MAX_CONCURRENT_REQUESTS,
+            shutdown_timeout: Duration::from_millis(Self::DEFAULT_SHUTDOWN_TIMEOUT_MSEC),
+            policies:         HashMap::new(),
+            snapshots:        Snapshots::default(),
+            write_log:        None,
+            sweep_interval:   Duration::from_millis(Self::DEFAULT_SWEEP_INTERVAL_MSEC),
+            compact_lengths:  true,
+            entries:          map,
         };
 
         (rx, s)
@@ -51,6 +98,138 @@ impl Server {
         self
     }
 
+    /// Sets the name that is send to clients during the handshake
+    ///
+    /// # Params
+    ///
+    /// * `name` - Name of the server, defaults to `cachem`
+    pub fn set_name<T: Into<String>>(&mut self, name: T) -> &mut Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the maximum size of a single request. Requests that are larger
+    /// are skipped and answered with [ErrorCode::FrameTooLarge].
+    ///
+    /// # Params
+    ///
+    /// * `size` - Maximum size in bytes, defaults to [Frame::DEFAULT_MAX_SIZE]
+    pub fn set_max_frame_size(&mut self, size: u32) -> &mut Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// Sets the maximum number of requests of a single connection that are
+    /// executed at the same time.
+    ///
+    /// By default the requests of a connection are executed one after
+    /// another, in the order they were send. With a higher count the requests
+    /// of a connection may be executed and answered in any order, so a
+    /// request must not depend on an earlier request of the same connection,
+    /// for example a GET after a SET of the same key.
+    ///
+    /// # Params
+    ///
+    /// * `count` - Maximum number of requests, defaults to
+    ///   [Server::DEFAULT_MAX_CONCURRENT_REQUESTS]
+    pub fn set_max_concurrent_requests(&mut self, count: usize) -> &mut Self {
+        self.max_concurrent = count.max(1);
+        self
+    }
+
+    /// Sets the time the server waits for open requests when shutting down
+    ///
+    /// # Params
+    ///
+    /// * `timeout` - Time to wait, defaults to
+    ///   [Server::DEFAULT_SHUTDOWN_TIMEOUT_MSEC]
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Saves the given cache in the background, see [SnapshotPolicy].
+    ///
+    /// The snapshot is taken by calling [Cache::snapshot]. The time of the
+    /// last successful snapshot is available with [Server::snapshots].
+    /// Caches that do not support snapshots are not scheduled, see
+    /// [Cache::supports_snapshot].
+    ///
+    /// # Params
+    ///
+    /// * `name`   - Name of the cache, this must implement Into<u8>
+    /// * `policy` - Configures when a snapshot is taken
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// # use std::time::Duration;
+    /// let (_, mut server) = Server::new("0.0.0.0:55555".into());
+    /// server.schedule_snapshots(0u8, SnapshotPolicy::default().every(Duration::from_secs(60)));
+    ///
+    /// let snapshots = server.snapshots();
+    /// assert!(snapshots.last_snapshot(0u8).is_none());
+    /// ```
+    pub fn schedule_snapshots<T: Into<u8>>(&mut self, name: T, policy: SnapshotPolicy) -> &mut Self {
+        self.policies.insert(name.into(), policy);
+        self
+    }
+
+    /// # Returns
+    ///
+    /// Handle for reading the time of the last successful snapshot of every
+    /// cache
+    ///
+    pub fn snapshots(&self) -> Snapshots {
+        self.snapshots.clone()
+    }
+
+    /// Sets the time between two sweeps for expired entries, see
+    /// [Cache::sweep]
+    ///
+    /// # Params
+    ///
+    /// * `interval` - Time between two sweeps, defaults to
+    ///   [Server::DEFAULT_SWEEP_INTERVAL_MSEC]
+    pub fn set_sweep_interval(&mut self, interval: Duration) -> &mut Self {
+        self.sweep_interval = interval;
+        self
+    }
+
+    /// Sets if clients may use [LengthEncoding::Compact] for their
+    /// connection. Clients ask for it during the handshake, when it is not
+    /// allowed, the connection uses [LengthEncoding::Fixed].
+    ///
+    /// # Params
+    ///
+    /// * `allow` - `true` if the compact encoding is allowed, defaults to
+    ///   `true`
+    pub fn set_compact_lengths(&mut self, allow: bool) -> &mut Self {
+        self.compact_lengths = allow;
+        self
+    }
+
+    /// Enables the append-only log of all writes, see [WriteLogConfig].
+    ///
+    /// Every SET, MSET, DEL and MDEL is appended to the log before it is
+    /// answered. When the server is started, all writes in the log are
+    /// executed again, so all caches must be loaded from their snapshot
+    /// before the server is started.
+    ///
+    /// When the log gets too large, [Cache::snapshot] is called for every
+    /// cache in the background and the log is compacted. That only works, if
+    /// all caches support snapshots, see [Cache::supports_snapshot].
+    /// Otherwise the log is never compacted.
+    ///
+    /// # Params
+    ///
+    /// * `config` - Configuration of the log
+    pub fn set_write_log(&mut self, config: WriteLogConfig) -> &mut Self {
+        self.write_log = Some(config);
+        self
+    }
+
     /// Stats the cnc network listener
     pub fn listen_cnc(&self) {
         let mut tasks = Vec::new();
@@ -60,56 +239,652 @@ impl Server {
         }
     }
 
-    /// Starts the tcp listener for incoming connections
+    /// Starts the tcp listener for incoming connections and runs forever.
+    ///
+    /// See [Server::run_until] for more information.
     ///
     /// # Panics
     ///
-    /// This should really not panic
-    /// TODO
+    /// If the server cannot bind to the configured address
     ///
     pub async fn listen_tcp(&self) {
-        let listener = TcpListener::bind(&self.addr).await.unwrap();
-        loop {
-            let entries_copy = self.entries.clone();
-            let (mut socket, _) = listener.accept().await.unwrap();
+        self
+            .run_until(futures::future::pending::<()>())
+            .await
+            .unwrap()
+    }
 
+    /// Starts the tcp listener for incoming connections and runs until the
+    /// given future resolves.
+    ///
+    /// The first request of every connection must be a HELLO, see
+    /// [Hello]. Connections with an incompatible protocol version are closed
+    /// after the server answered the HELLO.
+    ///
+    /// Every request is read as a [Frame] and answered with a [Response].
+    /// Frames that are larger than the configured maximum are skipped.
+    ///
+    /// Requests of a single connection are executed in the order they were
+    /// send, unless [Server::set_max_concurrent_requests] allows more than one
+    /// request at the same time. The responses carry the id of their request.
+    ///
+    /// When the `shutdown` future resolves, the server stops accepting new
+    /// connections and requests. Requests that are already executing are
+    /// answered, as long as they finish in the configured shutdown timeout.
+    /// Afterwards [Cache::snapshot] is called for every registered cache that
+    /// supports snapshots, see [Cache::supports_snapshot].
+    ///
+    /// Caches that opted in with [Server::schedule_snapshots] are also saved
+    /// while the server is running.
+    ///
+    /// If the write log is enabled, all writes in the log are executed before
+    /// the server starts listening. See [Server::set_write_log].
+    ///
+    /// # Params
+    ///
+    /// * `shutdown` - Future that resolves when the server should stop
+    ///
+    /// # Errors
+    ///
+    /// If the write log cannot be read or the server cannot bind to the
+    /// configured address
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use cachem::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let (_, server) = Server::new("0.0.0.0:55555".into());
+    /// server.run_until(async {
+    ///     let _ = tokio::signal::ctrl_c().await;
+    /// }).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn run_until<F>(&self, shutdown: F) -> Result<(), CachemError>
+    where
+        F: Future<Output = ()> {
+
+        let (log, records) = match self.write_log.clone() {
+            Some(x) => {
+                let (log, records) = WriteLog::open(x).await?;
+                (Some(log), records)
+            },
+            None => (None, Vec::new()),
+        };
+
+        let unsupported = self.entries
+            .iter()
+            .filter(|(_, x)| !x.supports_snapshot())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        if log.is_some() && !unsupported.is_empty() {
+            log::warn!(
+                "The write log is never compacted, the caches {:?} do not support snapshots",
+                unsupported
+            );
+        }
+
+        let scheduler = Scheduler::new(self.policies.clone(), &self.entries);
+        let shared = Arc::new(Shared {
+            entries:         self.entries.clone(),
+            writes:          scheduler.counters(),
+            write_locks:     self.entries
+                .keys()
+                .map(|x| (*x, Mutex::new(())))
+                .collect::<HashMap<_, _>>(),
+            log,
+            compactable:     unsupported.is_empty(),
+            snapshots:       self.snapshots.clone(),
+            hello:           self.hello(),
+            compact_lengths: self.compact_lengths,
+            max_frame_size:  self.max_frame_size,
+            max_concurrent:  self.max_concurrent,
+        });
+        Self::replay(&shared, records).await;
+
+        let listener = TcpListener::bind(&self.addr).await?;
+
+        // Every connection holds a clone of the sender, when all connections
+        // are closed, the receiver returns `None`
+        let (drain_tx, mut drain_rx) = mpsc::channel::<()>(1);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        scheduler.start(&self.entries, self.snapshots.clone(), shutdown_rx.clone());
+        tokio::spawn(Self::sweep(
+            self.entries.clone(),
+            self.sweep_interval,
+            shutdown_rx.clone(),
+        ));
+        if shared.log.is_some() {
+            let shared = shared.clone();
+            let shutdown = shutdown_rx.clone();
             tokio::spawn(async move {
-                let mut cmd: [u8; 1] = [0; 1];
-                loop {
-                    let mut buf_socket = tokio::io::BufStream::new(socket);
-                    match buf_socket.read(&mut cmd).await {
-                        // socket closed
-                        Ok(n) if n == 0 => return,
-                        Ok(n) => n,
-                        Err(e) => {
-                            eprintln!("failed to read from socket; err = {:?}", e);
-                            return;
-                        }
-                    };
-
-                    let cmd = Command::from(cmd[0]);
-                    if cmd == Command::Ping {
-                        buf_socket.write_u8(Command::Pong.into()).await.unwrap();
-                        buf_socket.flush().await.unwrap();
-                        socket = buf_socket.into_inner();
-                        continue;
+                if let Some(x) = shared.log.as_ref() {
+                    x.sync_every_second(shutdown).await;
+                }
+            });
+        }
+
+        tokio::pin!(shutdown);
+        loop {
+            let socket = tokio::select! {
+                x = listener.accept() => x,
+                _ = &mut shutdown => break,
+            };
+
+            let socket = match socket {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    log::error!("Failed to accept connection; err = {:?}", e);
+                    continue;
+                }
+            };
+
+            tokio::spawn(Self::serve(
+                shared.clone(),
+                socket,
+                shutdown_rx.clone(),
+                drain_tx.clone(),
+            ));
+        }
+
+        log::info!("Shutting down, waiting for open requests");
+        drop(listener);
+        drop(drain_tx);
+        let _ = shutdown_tx.send(true);
+
+        if timeout(self.shutdown_timeout, drain_rx.recv()).await.is_err() {
+            log::warn!("Not all requests finished in time");
+        }
+
+        Self::save_all(&shared).await;
+        if let Some(x) = shared.log.as_ref() {
+            if let Err(e) = x.lock().await.sync().await {
+                log::error!("Failed to close write log; err = {:?}", e);
+            }
+        }
+
+        log::info!("Shutdown complete");
+        Ok(())
+    }
+
+    /// Removes expired entries of all caches until the server shuts down
+    ///
+    /// # Params
+    ///
+    /// * `entries`  - All registered caches
+    /// * `interval` - Time between two sweeps
+    /// * `shutdown` - Changes when the server shuts down
+    ///
+    async fn sweep(
+        entries:      HashMap<u8, Arc<dyn Cache>>,
+        interval:     Duration,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => (),
+                _ = shutdown.changed() => break,
+            };
+
+            for cache in entries.values() {
+                cache.sweep().await;
+            }
+        }
+    }
+
+    /// Handles a single connection until it is closed or the server shuts
+    /// down.
+    ///
+    /// The requests are read one after another and executed in their own
+    /// task. A request only starts when a permit is free, with a single
+    /// permit the previous request is answered before the next one starts.
+    /// The responses are collected by a writer task that writes them back to
+    /// the socket.
+    ///
+    /// # Params
+    ///
+    /// * `shared`   - State that is shared between all connections
+    /// * `socket`   - Socket of the new connection
+    /// * `shutdown` - Changes when the server shuts down
+    /// * `_drain`   - Dropped when the connection is closed
+    ///
+    async fn serve(
+        shared:       Arc<Shared>,
+        socket:       TcpStream,
+        mut shutdown: watch::Receiver<bool>,
+        _drain:       mpsc::Sender<()>,
+    ) {
+        let (reader, writer) = socket.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+
+        let encoding = tokio::select! {
+            x = Self::handshake(&shared, &mut reader, &mut writer) => x,
+            _ = shutdown.changed() => None,
+        };
+        let encoding = match encoding {
+            Some(x) => x,
+            None    => return,
+        };
+
+        let (tx, rx) = mpsc::channel::<(u32, Response)>(shared.max_concurrent);
+        let writer = tokio::spawn(Self::write_responses(writer, rx));
+        let permits = Arc::new(Semaphore::new(shared.max_concurrent));
+
+        loop {
+            let frame = tokio::select! {
+                x = Frame::read(&mut reader, shared.max_frame_size) => x,
+                _ = shutdown.changed() => break,
+            };
+
+            let frame = match frame {
+                Ok(x) => x,
+                Err(CachemError::FrameTooLarge { id, size, max }) => {
+                    let response = CachemError::FrameTooLarge { id, size, max }.into();
+                    if tx.send((id, response)).await.is_err() {
+                        break;
                     }
+                    continue;
+                },
+                // socket closed
+                Err(CachemError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    log::error!("failed to read from socket; err = {:?}", e);
+                    break;
+                }
+            };
+
+            // Limits the number of requests that are executed at the same time,
+            // when all permits are taken, no more requests are read
+            let permit = match permits.clone().acquire_owned().await {
+                Ok(x) => x,
+                Err(_) => break,
+            };
+            let shared = shared.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let response = encoding
+                    .scope(Self::execute(&shared, frame.body))
+                    .await;
+                if let Response::Err(code, ref message) = response {
+                    log::error!("Request failed; code = {:?}, message = {}", code, message);
+                }
 
-                    let cache = buf_socket.read_u8().await.unwrap();
-                    if let Some(e) = entries_copy.get(&cache) {
-                        e.handle(cmd, &mut buf_socket).await;
-                    } else {
-                        log::error!("Could not find cache");
+                let _ = tx.send((frame.id, response)).await;
+                drop(permit);
+            });
+        }
+
+        // Wait until all running requests are answered
+        drop(tx);
+        let _ = writer.await;
+    }
+
+    /// Writes all responses it receives to the socket.
+    ///
+    /// The socket is only flushed when there are no more responses waiting,
+    /// so that multiple responses are send together.
+    ///
+    /// # Params
+    ///
+    /// * `writer` - Write half of the socket
+    /// * `rx`     - Receiver for the responses and their request id
+    ///
+    async fn write_responses(
+        mut writer: BufWriter<OwnedWriteHalf>,
+        mut rx:     mpsc::Receiver<(u32, Response)>,
+    ) {
+        while let Some((id, response)) = rx.recv().await {
+            if Self::respond(&mut writer, id, response).await.is_err() {
+                return;
+            }
+
+            while let Some(Some((id, response))) = rx.recv().now_or_never() {
+                if Self::respond(&mut writer, id, response).await.is_err() {
+                    return;
+                }
+            }
+
+            if writer.flush().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Collects the information that is send to clients during the handshake
+    fn hello(&self) -> Hello {
+        let mut caches = self.entries
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        caches.sort_unstable();
+
+        Hello {
+            version:         PROTOCOL_VERSION,
+            name:            self.name.clone(),
+            caches,
+            length_encoding: LengthEncoding::Fixed,
+        }
+    }
+
+    /// Answers the HELLO of a new connection
+    ///
+    /// # Params
+    ///
+    /// * `shared` - State that is shared between all connections
+    /// * `reader` - Read half of the new connection
+    /// * `writer` - Write half of the new connection
+    ///
+    /// # Returns
+    ///
+    /// The [LengthEncoding] of the connection if the handshake was
+    /// successful and the connection can be used
+    ///
+    async fn handshake(
+        shared: &Shared,
+        reader: &mut BufReader<OwnedReadHalf>,
+        writer: &mut BufWriter<OwnedWriteHalf>,
+    ) -> Option<LengthEncoding> {
+        let frame = match Frame::read(reader, shared.max_frame_size).await {
+            Ok(x) => x,
+            // the socket was closed before sending a valid frame
+            Err(_) => return None,
+        };
+        let mut req = Cursor::new(frame.body);
+
+        let cmd = u8::read(&mut req).await.map(Command::try_from);
+        let (response, accepted) = match cmd {
+            Ok(Ok(Command::Hello)) => match Self::read_hello(shared, &mut req).await {
+                Ok((hello, accepted)) => {
+                    let mut res = Vec::new();
+                    if hello.write(&mut res).await.is_err() {
+                        return None;
                     }
+                    (Response::Ok(res), accepted.then_some(hello.length_encoding))
+                },
+                Err(e) => (e.into(), None),
+            },
+            _ => (
+                Response::err(
+                    ErrorCode::IncompatibleVersion,
+                    "The first command must be HELLO"
+                ),
+                None
+            ),
+        };
+
+        if Self::respond(writer, frame.id, response).await.is_ok() &&
+           writer.flush().await.is_ok() {
+            accepted
+        } else {
+            None
+        }
+    }
+
+    /// Reads the protocol version and the requested [LengthEncoding] of a
+    /// HELLO
+    ///
+    /// # Params
+    ///
+    /// * `shared` - State that is shared between all connections
+    /// * `req`    - Body of the HELLO after the command
+    ///
+    /// # Errors
+    ///
+    /// If the HELLO cannot be parsed
+    ///
+    /// # Returns
+    ///
+    /// The answer for the client and `true` if the client is accepted
+    ///
+    async fn read_hello(
+        shared: &Shared,
+        req:    &mut Cursor<Vec<u8>>,
+    ) -> Result<(Hello, bool), CachemError> {
+        let version = u16::read(req).await?;
+        let mut hello = shared.hello.clone();
+
+        if !Hello::is_compatible(version) {
+            log::warn!(
+                "Refusing client with protocol version {}, expected {}",
+                version,
+                PROTOCOL_VERSION
+            );
+            return Ok((hello, false));
+        }
+
+        let requested = LengthEncoding::read(req).await?;
+        if shared.compact_lengths {
+            hello.length_encoding = requested;
+        }
+        Ok((hello, true))
+    }
+
+    /// Executes all writes of the write log
+    ///
+    /// # Params
+    ///
+    /// * `shared`  - State that is shared between all connections
+    /// * `records` - Bodies of all logged requests and their encoding
+    ///
+    async fn replay(
+        shared:  &Shared,
+        records: Vec<(LengthEncoding, Vec<u8>)>,
+    ) {
+        if records.is_empty() {
+            return;
+        }
+
+        log::info!("Replaying {} writes", records.len());
+        for (encoding, record) in records {
+            let response = encoding
+                .scope(Self::apply(shared, &mut Cursor::new(record)))
+                .await;
+            if let Response::Err(code, message) = response {
+                log::warn!("Failed to replay write; code = {:?}, message = {}", code, message);
+            }
+        }
+    }
+
+    /// Executes the request and appends it to the write log if it changes a
+    /// cache. Must run in the [LengthEncoding::scope] of the connection.
+    ///
+    /// When the log gets too large, the compaction is started in the
+    /// background.
+    ///
+    /// # Params
+    ///
+    /// * `shared` - State that is shared between all connections
+    /// * `body`   - Body of the frame containing the request
+    ///
+    /// # Returns
+    ///
+    /// Response that should be written back to the client
+    ///
+    async fn execute(
+        shared: &Arc<Shared>,
+        body:   Vec<u8>,
+    ) -> Response {
+        let is_write = body
+            .first()
+            .and_then(|x| Command::try_from(*x).ok())
+            .map(|x| x.is_write())
+            .unwrap_or_default();
+
+        // writes of a cache are executed one after another, so that the
+        // records of a cache have the same order as the writes
+        let _write_lock = match (shared.log.as_ref(), body.get(1)) {
+            (Some(_), Some(id)) if is_write => match shared.write_locks.get(id) {
+                Some(x) => Some(x.lock().await),
+                None    => None,
+            },
+            _ => None,
+        };
+
+        let mut req = Cursor::new(body);
+        let response = Self::apply(shared, &mut req).await;
+
+        if let (Some(log), true, Response::Ok(_)) = (shared.log.as_ref(), is_write, &response) {
+            // only the part of the request that was read is logged, so that
+            // a time to live is always at the end, see WriteLog
+            let body = &req.get_ref()[..req.position() as usize];
+            let mut log_file = log.lock().await;
+            if let Err(e) = log_file.append(LengthEncoding::current(), body).await {
+                log::error!("Failed to append to write log; err = {:?}", e);
+                return e.into();
+            }
+
+            if shared.compactable && log_file.needs_compaction() {
+                let start = log_file.start_compaction();
+                let shared = shared.clone();
+                tokio::spawn(async move { Self::compact(&shared, start).await });
+            }
+        }
 
-                    buf_socket.flush().await.unwrap();
+        response
+    }
+
+    /// Saves all caches and compacts the write log, if all caches could be
+    /// saved
+    ///
+    /// # Params
+    ///
+    /// * `shared` - State that is shared between all connections
+    ///
+    async fn save_all(shared: &Shared) {
+        let start = match shared.log.as_ref() {
+            Some(x) => x.lock().await.start_compaction(),
+            None    => {
+                shared.snapshots.take_all(&shared.entries).await;
+                return;
+            },
+        };
+        Self::compact(shared, start).await;
+    }
+
+    /// Saves all caches and removes all records before `start` from the
+    /// write log. The log is not locked while the caches are saved, records
+    /// that are added in the meantime are kept.
+    ///
+    /// # Params
+    ///
+    /// * `shared` - State that is shared between all connections
+    /// * `start`  - Position of the end of the log when the compaction
+    ///   started
+    ///
+    async fn compact(shared: &Shared, start: u64) {
+        let log = match shared.log.as_ref() {
+            Some(x) => x,
+            None    => return,
+        };
+
+        let saved = shared.snapshots.take_all(&shared.entries).await;
+        let mut log_file = log.lock().await;
+        if saved {
+            if let Err(e) = log_file.compact(start).await {
+                log::error!("Failed to compact write log; err = {:?}", e);
+            }
+        } else {
+            log_file.postpone_compaction();
+        }
+    }
+
+    /// Parses the request and lets the targeted cache execute it
+    ///
+    /// # Params
+    ///
+    /// * `shared` - State that is shared between all connections
+    /// * `req`    - Body of the frame containing the request
+    ///
+    /// # Returns
+    ///
+    /// Response that should be written back to the client
+    ///
+    async fn apply(
+        shared: &Shared,
+        req:    &mut Cursor<Vec<u8>>,
+    ) -> Response {
+        let cmd = match u8::read(req).await.and_then(Command::try_from) {
+            Ok(x) => x,
+            Err(e) => return e.into(),
+        };
+
+        if cmd == Command::Ping {
+            return Response::Ok(vec![Command::Pong.into()]);
+        }
 
-                    // return the socket so that we don´t consume it
-                    socket = buf_socket.into_inner();
+        let id = match u8::read(req).await {
+            Ok(x) => x,
+            Err(e) => return e.into(),
+        };
+        let cache = if let Some(x) = shared.entries.get(&id) {
+            x
+        } else {
+            return Response::err(
+                ErrorCode::UnknownCache,
+                format!("Could not find cache {}", id)
+            );
+        };
+
+        let mut res = Vec::new();
+        match cache.handle(cmd, req, &mut res).await {
+            Ok(_)  => {
+                if let Some(x) = shared.writes.get(&id) {
+                    x.record(cmd);
                 }
-            });
+                Response::Ok(res)
+            },
+            Err(e) => e.into(),
         }
     }
+
+    /// Writes the response as [Frame] into the socket, the caller is
+    /// responsible for flushing the socket
+    ///
+    /// # Params
+    ///
+    /// * `writer`   - Socket to write the response to
+    /// * `id`       - Id of the request
+    /// * `response` - Response to write
+    ///
+    async fn respond(
+        writer:   &mut BufWriter<OwnedWriteHalf>,
+        id:       u32,
+        response: Response,
+    ) -> Result<(), CachemError> {
+        let mut body = Vec::new();
+        response.write(&mut body).await?;
+        Frame::new(id, body).write(writer).await
+    }
+}
+
+/// State that is shared between all connections
+struct Shared {
+    /// All registered caches
+    entries:         HashMap<u8, Arc<dyn Cache>>,
+    /// Write counters of all caches that are saved in the background
+    writes:          HashMap<u8, Arc<WriteCounter>>,
+    /// Serializes the writes of every cache while the write log is enabled
+    write_locks:     HashMap<u8, Mutex<()>>,
+    /// Log of all writes, if enabled
+    log:             Option<WriteLog>,
+    /// `true` if all caches support snapshots, so the log can be compacted
+    compactable:     bool,
+    /// Time of the last successful snapshot of every cache
+    snapshots:       Snapshots,
+    /// Information that is send to clients during the handshake
+    hello:           Hello,
+    /// `true` if clients may use [LengthEncoding::Compact]
+    compact_lengths: bool,
+    /// Maximum size of a single request in bytes
+    max_frame_size:  u32,
+    /// Maximum number of requests of a single connection that are executed
+    /// at the same time
+    max_concurrent:  usize,
 }
 
 /// Command and control network for inter service communication
@@ -134,10 +909,100 @@ impl Cache for CommandAndControl {
         "Command n Control".into()
     }
 
-    async fn handle(&self, _: Command, _: &mut BufStream<TcpStream>) {
-        self.cnc_rec.send(Command::Get).unwrap();
+    async fn handle(
+        &self,
+        _:   Command,
+        _:   &mut Cursor<Vec<u8>>,
+        _:   &mut Vec<u8>,
+    ) -> Result<(), CachemError> {
+        self.cnc_rec
+            .send(Command::Get)
+            .map_err(|_| CachemError::NotReachable)
     }
 
     async fn cnc_listener(&self) {  }
+
+    /// The cnc network has no state, so there is nothing to save
+    async fn snapshot(&self) -> Result<(), CachemError> {
+        Ok(())
+    }
+
+    fn supports_snapshot(&self) -> bool {
+        true
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConnectionPool;
+    use tokio::sync::{oneshot, Mutex};
+
+    /// Records the order of all SETs, earlier requests take longer
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<u32>>);
+
+    #[async_trait]
+    impl Cache for Recorder {
+        fn name(&self) -> String {
+            "recorder".into()
+        }
+
+        async fn handle(
+            &self,
+            cmd: Command,
+            req: &mut Cursor<Vec<u8>>,
+            res: &mut Vec<u8>,
+        ) -> Result<(), CachemError> {
+            match cmd {
+                Command::Set => {
+                    let id = u32::read(req).await?;
+                    let _ = u32::read(req).await?;
+                    let delay = 50u64.saturating_sub(id as u64 * 10);
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    self.0.lock().await.push(id);
+                },
+                Command::Get => {
+                    let _ = u32::read(req).await?;
+                    let count = self.0.lock().await.len() as u32;
+                    Some(count).write(res).await?;
+                },
+                _ => return Err(CachemError::Empty),
+            }
+            Ok(())
+        }
+
+        async fn cnc_listener(&self) {  }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_executed_in_order() {
+        let recorder = Arc::new(Recorder::default());
+        let (_, mut server) = Server::new("127.0.0.1:47011".into());
+        server.add(0u8, recorder.clone());
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            server.run_until(async { let _ = rx.await; }).await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let pool = ConnectionPool::new("127.0.0.1:47011", 1).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let mut pipeline = conn.pipeline();
+        for i in 0..5u32 {
+            pipeline.set(0u8, i, i).await.unwrap();
+        }
+        let ticket = pipeline.get::<_, _, u32>(0u8, 0u32).await.unwrap();
+
+        let mut replies = pipeline.flush().await.unwrap();
+        // the GET is executed after all SETs
+        assert_eq!(replies.take(ticket).await.unwrap(), Some(5u32));
+        assert_eq!(*recorder.0.lock().await, vec![0, 1, 2, 3, 4]);
+
+        drop(conn);
+        let _ = tx.send(());
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,387 @@
+//! File format for snapshots of caches.
+//!
+//! ```text
+//! [magic: 4 bytes][format version: u16][length encoding: u8]
+//! [cache name: String][entries: u32][payload][crc32: u32]
+//! ```
+//!
+//! The payload is written with the [LengthEncoding] of the task that saves
+//! the snapshot, readers decode it with [SnapshotHeader::length_encoding].
+//! Files of version 1 have no length encoding and always use
+//! [LengthEncoding::Fixed].
+//!
+//! The checksum covers everything before it. Snapshots are written to a
+//! temporary file with a unique name, synced to disk and renamed to the
+//! target file, so that a crash while saving never destroys the previous
+//! snapshot and snapshots of the same cache that are written at the same time
+//! never mix.
+
+use crate::{CachemError, LengthEncoding, Parse};
+use crate::writelog::sync_parent;
+
+use std::io::{Cursor, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+/// Header of a snapshot file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    /// Version of the file format
+    pub version:         u16,
+    /// Encoding of collection lengths in the payload
+    pub length_encoding: LengthEncoding,
+    /// Name of the cache the snapshot belongs to
+    pub name:            String,
+    /// Number of entries in the payload
+    pub entries:         u32,
+}
+
+/// Reads and writes snapshot files
+pub struct SnapshotFile;
+
+impl SnapshotFile {
+    /// Bytes every snapshot file starts with
+    pub const MAGIC: [u8; 4] = *b"CCHM";
+    /// Current version of the file format
+    pub const VERSION: u16 = 2u16;
+
+    /// Writes a new snapshot file, replacing the existing one.
+    ///
+    /// The header notes [LengthEncoding::current], so the payload must be
+    /// encoded in the same task.
+    ///
+    /// # Params
+    ///
+    /// * `path`    - Path of the snapshot file
+    /// * `name`    - Name of the cache
+    /// * `entries` - Number of entries in the payload
+    /// * `payload` - Encoded entries of the cache
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be written, the previous snapshot is untouched
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use cachem::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let path = std::env::temp_dir().join("cachem_doc_snapshot");
+    /// let path = path.to_str().unwrap();
+    ///
+    /// SnapshotFile::write(path, "numbers", 1u32, &[0, 0, 0, 5]).await?;
+    /// let (header, payload) = SnapshotFile::read(path).await?.unwrap();
+    /// assert_eq!(header.name, "numbers");
+    /// assert_eq!(header.length_encoding, LengthEncoding::Fixed);
+    /// assert_eq!(header.entries, 1u32);
+    /// assert_eq!(payload, vec![0, 0, 0, 5]);
+    ///
+    /// // flip a byte of the payload
+    /// let mut file = std::fs::read(path)?;
+    /// let idx = file.len() - 5;
+    /// file[idx] = 6;
+    /// std::fs::write(path, file)?;
+    /// assert!(matches!(
+    ///     SnapshotFile::read(path).await,
+    ///     Err(CachemError::CorruptSnapshot { .. })
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    pub async fn write(
+        path:    &str,
+        name:    &str,
+        entries: u32,
+        payload: &[u8],
+    ) -> Result<(), CachemError> {
+        let mut data = Vec::with_capacity(payload.len() + name.len() + 16);
+        data.extend_from_slice(&Self::MAGIC);
+        Self::VERSION.write(&mut data).await?;
+        LengthEncoding::current().write(&mut data).await?;
+        name.to_string().write(&mut data).await?;
+        entries.write(&mut data).await?;
+        data.extend_from_slice(payload);
+        crc32(&data).write(&mut data).await?;
+
+        let tmp = Self::tmp_path(path);
+        let result = Self::write_tmp(&tmp, &data).await;
+        let result = match result {
+            Ok(_) => tokio::fs::rename(&tmp, path).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+
+        sync_parent(path).await
+    }
+
+    /// Writes the data to the temporary file and syncs it to disk
+    ///
+    /// # Params
+    ///
+    /// * `tmp`  - Path of the temporary file
+    /// * `data` - Content of the snapshot file
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be written
+    ///
+    async fn write_tmp(tmp: &str, data: &[u8]) -> Result<(), CachemError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(tmp)
+            .await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+        Ok(())
+    }
+
+    /// # Returns
+    ///
+    /// Path of a temporary file next to the snapshot that no other write
+    /// uses
+    ///
+    fn tmp_path(path: &str) -> String {
+        /// Number of temporary files that were created by this process
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        format!(
+            "{}.{}.{}.tmp",
+            path,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    /// Reads and verifies a snapshot file
+    ///
+    /// # Params
+    ///
+    /// * `path` - Path of the snapshot file
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::CorruptSnapshot] if the file is not a snapshot, has an
+    /// unknown version or the checksum does not match
+    ///
+    /// # Returns
+    ///
+    /// `None` if the file does not exist, otherwise the header and the
+    /// payload
+    ///
+    pub async fn read(
+        path: &str,
+    ) -> Result<Option<(SnapshotHeader, Vec<u8>)>, CachemError> {
+        let data = match tokio::fs::read(path).await {
+            Ok(x) => x,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let corrupt = |reason: &str| CachemError::CorruptSnapshot {
+            file:   path.into(),
+            reason: reason.into(),
+        };
+
+        if data.len() < Self::MAGIC.len() || data[..Self::MAGIC.len()] != Self::MAGIC {
+            return Err(corrupt("not a snapshot file"));
+        }
+        if data.len() < Self::MAGIC.len() + std::mem::size_of::<u32>() {
+            return Err(corrupt("file is truncated"));
+        }
+
+        let (content, checksum) = data.split_at(data.len() - std::mem::size_of::<u32>());
+        let checksum = u32::read(&mut Cursor::new(checksum)).await?;
+        if crc32(content) != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+
+        let mut buf = Cursor::new(&content[Self::MAGIC.len()..]);
+        let version = u16::read(&mut buf)
+            .await
+            .map_err(|_| corrupt("file is truncated"))?;
+        let length_encoding = match version {
+            1 => LengthEncoding::Fixed,
+            Self::VERSION => LengthEncoding::read(&mut buf)
+                .await
+                .map_err(|_| corrupt("invalid header"))?,
+            _ => return Err(corrupt(&format!("unsupported format version {}", version))),
+        };
+        let name = String::read(&mut buf)
+            .await
+            .map_err(|_| corrupt("invalid header"))?;
+        let entries = u32::read(&mut buf)
+            .await
+            .map_err(|_| corrupt("invalid header"))?;
+
+        let header = SnapshotHeader { version, length_encoding, name, entries };
+        let payload = content[Self::MAGIC.len() + buf.position() as usize..].to_vec();
+        Ok(Some((header, payload)))
+    }
+}
+
+/// Lookup table for [crc32]
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// Builds the lookup table for the CRC-32 (IEEE) polynomial
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Calculates the CRC-32 (IEEE) checksum of the given data
+///
+/// # Params
+///
+/// * `data` - Data to calculate the checksum for
+///
+/// # Returns
+///
+/// Checksum of the data
+///
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, x| {
+        CRC32_TABLE[((crc ^ *x as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path of a new snapshot in the temp directory
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("cachem_snapshot_{}", name));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().into()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let path = path("round_trip");
+        assert!(SnapshotFile::read(&path).await.unwrap().is_none());
+
+        let payload = LengthEncoding::Compact
+            .scope(async {
+                let mut payload = Vec::new();
+                vec![1u32, 2u32].write(&mut payload).await.unwrap();
+                SnapshotFile::write(&path, "numbers", 2u32, &payload).await.unwrap();
+                payload
+            })
+            .await;
+
+        let (header, read) = SnapshotFile::read(&path).await.unwrap().unwrap();
+        assert_eq!(header, SnapshotHeader {
+            version:         SnapshotFile::VERSION,
+            length_encoding: LengthEncoding::Compact,
+            name:            "numbers".into(),
+            entries:         2u32,
+        });
+        assert_eq!(read, payload);
+        assert_eq!(
+            header.length_encoding.decode::<Vec<u32>>(&read).await.unwrap(),
+            vec![1u32, 2u32]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version_1() {
+        let path = path("version_1");
+
+        let mut data = SnapshotFile::MAGIC.to_vec();
+        1u16.write(&mut data).await.unwrap();
+        String::from("old").write(&mut data).await.unwrap();
+        1u32.write(&mut data).await.unwrap();
+        5u32.write(&mut data).await.unwrap();
+        crc32(&data).write(&mut data).await.unwrap();
+        std::fs::write(&path, data).unwrap();
+
+        let (header, payload) = SnapshotFile::read(&path).await.unwrap().unwrap();
+        assert_eq!(header.version, 1u16);
+        assert_eq!(header.length_encoding, LengthEncoding::Fixed);
+        assert_eq!(header.name, "old");
+        assert_eq!(payload, vec![0, 0, 0, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_corruption() {
+        let path = path("corruption");
+        SnapshotFile::write(&path, "numbers", 1u32, &[0, 0, 0, 5]).await.unwrap();
+        let valid = std::fs::read(&path).unwrap();
+
+        let mut flipped = valid.clone();
+        flipped[valid.len() - 5] ^= 1;
+        let mut truncated = valid.clone();
+        truncated.truncate(valid.len() - 1);
+        let mut magic = valid.clone();
+        magic[0] = b'X';
+
+        for data in [flipped, truncated, magic, vec![b'C']] {
+            std::fs::write(&path, data).unwrap();
+            assert!(matches!(
+                SnapshotFile::read(&path).await,
+                Err(CachemError::CorruptSnapshot { .. })
+            ));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_writes() {
+        let path = path("concurrent");
+
+        let mut tasks = Vec::new();
+        for i in 0..16u32 {
+            let path = path.clone();
+            tasks.push(tokio::spawn(async move {
+                let payload = vec![i as u8; 4096];
+                SnapshotFile::write(&path, "numbers", i, &payload).await.unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // one of the writes wins completely
+        let (header, payload) = SnapshotFile::read(&path).await.unwrap().unwrap();
+        assert_eq!(payload, vec![header.entries as u8; 4096]);
+
+        let name = std::path::Path::new(&path).file_name().unwrap().to_str().unwrap();
+        let tmp_files = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|x| x.ok())
+            .filter(|x| {
+                let file = x.file_name();
+                let file = file.to_str().unwrap_or_default();
+                file.starts_with(name) && file.ends_with(".tmp")
+            })
+            .count();
+        assert_eq!(tmp_files, 0);
+    }
+}
@@ -1,47 +1,71 @@
-use crate::CachemError;
+use crate::{CachemError, SnapshotFile};
 
 use async_trait::async_trait;
-use tokio::{fs::OpenOptions, io::AsyncWriteExt};
-use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufStream};
+use std::io::Cursor;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
 
+/// Trait for caches that are saved to and loaded from a file.
+///
+/// The file uses the format of [SnapshotFile].
 #[async_trait]
 pub trait Storage: Sized {
     /// Returns the filename
     fn file() -> &'static str;
 
+    /// Name of the cache that is stored in the header of the file, defaults
+    /// to [Storage::file]
+    fn name() -> &'static str {
+        Self::file()
+    }
+
     /// Loads the cache from the given buffer
-    async fn load<B>(&self, buf: &mut B) -> Result<(), CachemError> 
+    async fn load<B>(&self, buf: &mut B) -> Result<(), CachemError>
         where B: AsyncBufRead + AsyncRead + Send + Unpin;
 
     /// Saves the current cache to the buffer
-    async fn save<B>(&self, buf: &mut B) -> Result<(), CachemError>
+    ///
+    /// # Returns
+    ///
+    /// Number of entries that were written
+    ///
+    async fn save<B>(&self, buf: &mut B) -> Result<u32, CachemError>
         where B: AsyncWrite + Send + Unpin;
 
-    /// Loads a cache from file. Uses [Storage::load] internally.
+    /// Loads a cache from file. Uses [Storage::load] internally, with the
+    /// [crate::LengthEncoding] noted in the file.
+    ///
+    /// If the file does not exist, nothing is loaded.
+    ///
+    /// # Errors
+    ///
+    /// [CachemError::CorruptSnapshot] if the file is damaged or belongs to
+    /// another cache
+    ///
     async fn load_from_file(&self) -> Result<(), CachemError> {
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(Self::file())
-            .await?;
-        let mut buf = BufStream::new(file);
-        self.load(&mut buf).await
+        let (header, payload) = match SnapshotFile::read(Self::file()).await? {
+            Some(x) => x,
+            None    => return Ok(()),
+        };
+
+        if header.name != Self::name() {
+            return Err(CachemError::CorruptSnapshot {
+                file:   Self::file().into(),
+                reason: format!("snapshot belongs to cache {}", header.name),
+            });
+        }
+
+        header.length_encoding
+            .scope(self.load(&mut Cursor::new(payload)))
+            .await
     }
 
     /// Saves the current cache to file. Uses [Storage::save] internally.
+    ///
+    /// The previous file is replaced atomically, if the process crashes while
+    /// saving, the previous file is still intact.
     async fn save_to_file(&self) -> Result<(), CachemError> {
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(Self::file())
-            .await?;
-        let mut buf = BufStream::new(file);
-        self.save(&mut buf).await?;
-        buf.flush().await?;
-
-        Ok(())
+        let mut payload = Vec::new();
+        let entries = self.save(&mut payload).await?;
+        SnapshotFile::write(Self::file(), Self::name(), entries, &payload).await
     }
 }
-
@@ -1,13 +1,13 @@
 //! Contains all traits that are used across the database
 
-use crate::Command;
-use crate::Parse;
+use crate::{CachemError, Command, Parse};
 
 use async_trait::*;
 use tokio::fs::OpenOptions;
 use std::collections::HashMap;
+use std::io::Cursor;
+use std::time::Duration;
 use tokio::io::{AsyncWriteExt, BufStream};
-use tokio::net::TcpStream;
 
 /// This trait implements default functions for caches
 #[async_trait]
@@ -20,11 +20,74 @@ pub trait Cache: Send + Sync {
     ///
     fn name(&self) -> String;
 
-    /// TODO
-    async fn handle(&self, cmd: Command, buf: &mut BufStream<TcpStream>);
+    /// Handles a single command that targets this cache.
+    ///
+    /// The server calls the function within the [crate::LengthEncoding] of
+    /// the connection, so [Parse] uses it for `req` and `res`. Work that is
+    /// moved into `tokio::spawn` falls back to
+    /// [crate::LengthEncoding::Fixed] and must be wrapped in
+    /// [crate::LengthEncoding::scope] again.
+    ///
+    /// # Params
+    ///
+    /// * `cmd` - Command that should be executed
+    /// * `req` - Payload of the request, contains exactly one request
+    /// * `res` - Buffer the payload of the response is written to
+    ///
+    /// # Errors
+    ///
+    /// If reading the request or executing the command fails, the server
+    /// answers with an error response instead of the payload in `res`.
+    ///
+    async fn handle(
+        &self,
+        cmd: Command,
+        req: &mut Cursor<Vec<u8>>,
+        res: &mut Vec<u8>,
+    ) -> Result<(), CachemError>;
 
     /// TODO
     async fn cnc_listener(&self);
+
+    /// Persists the current state of the cache.
+    ///
+    /// Called by the server for every registered cache that returns `true`
+    /// from [Cache::supports_snapshot] after it was shut down, see
+    /// [crate::Server::run_until]. Caches that should survive a restart call
+    /// [crate::Storage::save_to_file] or [Save::save] here.
+    ///
+    /// # Errors
+    ///
+    /// If the cache could not be saved, the error is logged and the other
+    /// caches are still saved. The default implementation always fails with
+    /// [CachemError::SnapshotNotSupported].
+    ///
+    async fn snapshot(&self) -> Result<(), CachemError> {
+        Err(CachemError::SnapshotNotSupported {
+            cache: self.name(),
+        })
+    }
+
+    /// Caches that implement [Cache::snapshot] must return `true`, otherwise
+    /// the server never calls [Cache::snapshot] and treats the cache as not
+    /// saved.
+    ///
+    /// # Returns
+    ///
+    /// `true` if [Cache::snapshot] persists the cache, defaults to `false`
+    ///
+    fn supports_snapshot(&self) -> bool {
+        false
+    }
+
+    /// Removes all entries that expired.
+    ///
+    /// Called by the server in the configured sweep interval, see
+    /// [crate::Server::set_sweep_interval]. Caches that support expiration
+    /// remove the keys returned by [crate::Expirations::expired] here.
+    /// The default implementation does nothing.
+    ///
+    async fn sweep(&self) {}
 }
 
 /// Trait for getting data from the cache.
@@ -302,6 +365,71 @@ pub trait Set2<Id, Val>
     }
 }
 
+/// Trait for entries that expire after a given time.
+///
+/// Entries that expired must not be returned by [Get2::get], even if the
+/// sweeper did not remove them yet. See [crate::Expirations] for keeping
+/// track of the expiration times.
+///
+/// # Generics
+///
+/// * `Id`  - Datatype for the id
+/// * `Val` - Datatype of the value
+///
+#[async_trait]
+pub trait Expire<Id, Val>
+    where
+        Id:  Parse + Send + 'static,
+        Val: Parse + Send + 'static {
+
+    /// Sets a value that expires after the given time
+    ///
+    /// # Params
+    ///
+    /// * `id`  - Id of the new entry
+    /// * `val` - Value that should be set
+    /// * `ttl` - Time until the entry expires
+    ///
+    async fn set_with_ttl(&self, id: Id, val: Val, ttl: Duration);
+
+    /// Sets the time to live of an existing entry
+    ///
+    /// # Params
+    ///
+    /// * `id`  - Id of the entry
+    /// * `ttl` - Time until the entry expires
+    ///
+    /// # Returns
+    ///
+    /// `true` if the entry exists
+    ///
+    async fn expire(&self, id: Id, ttl: Duration) -> bool;
+
+    /// Gets the remaining time to live of an entry
+    ///
+    /// # Params
+    ///
+    /// * `id` - Id of the entry
+    ///
+    /// # Returns
+    ///
+    /// `None` if the entry does not exist or does not expire
+    ///
+    async fn ttl(&self, id: Id) -> Option<Duration>;
+
+    /// Removes the time to live of an entry
+    ///
+    /// # Params
+    ///
+    /// * `id` - Id of the entry
+    ///
+    /// # Returns
+    ///
+    /// `true` if the entry had a time to live
+    ///
+    async fn persist(&self, id: Id) -> bool;
+}
+
 /// Trait for deleting entries from the cache
 #[async_trait]
 pub trait Del {
@@ -366,6 +494,7 @@ pub trait Save {
             .create(true)
             .read(true)
             .write(true)
+            .truncate(true)
             .open(self.file())
             .await;
         if let Ok(file) = file {
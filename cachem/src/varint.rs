@@ -0,0 +1,325 @@
+//! Integers with a variable length and the encoding of collection lengths.
+//!
+//! [VarU32], [VarU64] and [VarI64] are written as LEB128 varint, see
+//! [crate::codec::varint] and [crate::codec::zigzag]. Small values take a
+//! single byte.
+//!
+//! Collections like `Vec<T>` and `HashMap<K, V>` are prefixed with their
+//! length. By default the length is a [u32], with [LengthEncoding::Compact]
+//! it is a varint. The encoding is chosen for the current task with
+//! [LengthEncoding::scope]. Connections agree on the encoding during the
+//! handshake, snapshots note it in their header.
+
+use crate::{codec, CachemError, Parse};
+
+use async_trait::async_trait;
+use std::future::Future;
+use std::io::Cursor;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+tokio::task_local! {
+    /// Length encoding of the current task
+    static LENGTH_ENCODING: LengthEncoding;
+}
+
+/// Encoding of the length prefix of collections
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let entries = vec![1u8, 2u8];
+///
+/// let mut fixed = Vec::new();
+/// entries.write(&mut fixed).await?;
+/// assert_eq!(fixed, vec![0, 0, 0, 2, 1, 2]);
+///
+/// let mut compact = Vec::new();
+/// LengthEncoding::Compact.encode(&entries, &mut compact).await?;
+/// assert_eq!(compact, vec![2, 1, 2]);
+///
+/// let read: Vec<u8> = LengthEncoding::Compact.decode(&compact).await?;
+/// assert_eq!(read, entries);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LengthEncoding {
+    /// Lengths are written as [u32]
+    #[default]
+    Fixed,
+    /// Lengths are written as varint
+    Compact,
+}
+
+impl LengthEncoding {
+    /// # Returns
+    ///
+    /// Encoding of the current task, [LengthEncoding::Fixed] if the task
+    /// did not choose one or runs outside of [LengthEncoding::scope], for
+    /// example in a task created with `tokio::spawn`
+    ///
+    pub fn current() -> Self {
+        LENGTH_ENCODING
+            .try_with(|x| *x)
+            .unwrap_or_default()
+    }
+
+    /// Runs the future with this encoding. All collections that are read or
+    /// written by the future use it for their length.
+    ///
+    /// # Params
+    ///
+    /// * `fut` - Future to run
+    ///
+    /// # Returns
+    ///
+    /// Output of the future
+    ///
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        LENGTH_ENCODING.scope(self, fut).await
+    }
+
+    /// Writes the value with this encoding
+    ///
+    /// # Params
+    ///
+    /// * `val` - Value to write
+    /// * `buf` - Buffer to write the value into
+    ///
+    /// # Errors
+    ///
+    /// If the value cannot be written
+    ///
+    pub async fn encode<T>(self, val: &T, buf: &mut Vec<u8>) -> Result<(), CachemError>
+    where
+        T: Parse {
+
+        self.scope(val.write(buf)).await
+    }
+
+    /// Reads a value with this encoding
+    ///
+    /// # Params
+    ///
+    /// * `buf` - Bytes to read the value from
+    ///
+    /// # Errors
+    ///
+    /// If the bytes do not contain a valid value
+    ///
+    /// # Returns
+    ///
+    /// The parsed value
+    ///
+    pub async fn decode<T>(self, buf: &[u8]) -> Result<T, CachemError>
+    where
+        T: Parse {
+
+        self.scope(T::read(&mut Cursor::new(buf))).await
+    }
+}
+
+#[async_trait]
+impl Parse for LengthEncoding {
+    async fn read<B>(
+        buf: &mut B
+    ) -> Result<Self, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        match u8::read(buf).await? {
+            0 => Ok(Self::Fixed),
+            1 => Ok(Self::Compact),
+            x => Err(CachemError::InvalidDiscriminant {
+                name: "LengthEncoding".into(),
+                tag:  x,
+            }),
+        }
+    }
+
+    async fn write<B>(
+        &self,
+        buf: &mut B
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        match self {
+            Self::Fixed   => 0u8.write(buf).await,
+            Self::Compact => 1u8.write(buf).await,
+        }
+    }
+}
+
+/// Reads the length of a collection with the encoding of the current task
+pub(crate) async fn read_len<B>(buf: &mut B) -> Result<u32, CachemError>
+where
+    B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+    match LengthEncoding::current() {
+        LengthEncoding::Fixed   => u32::read(buf).await,
+        LengthEncoding::Compact => codec::varint::read(buf).await,
+    }
+}
+
+/// Writes the length of a collection with the encoding of the current task
+pub(crate) async fn write_len<B>(len: usize, buf: &mut B) -> Result<(), CachemError>
+where
+    B: AsyncWrite + Send + Unpin {
+
+    let len = len as u32;
+    match LengthEncoding::current() {
+        LengthEncoding::Fixed   => len.write(buf).await,
+        LengthEncoding::Compact => codec::varint::write(&len, buf).await,
+    }
+}
+
+/// [u32] that is written as varint, values below 128 take a single byte
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut buf = Vec::new();
+/// VarU32(300u32).write(&mut buf).await?;
+/// assert_eq!(buf, vec![0xAC, 0x02]);
+///
+/// let read = VarU32::read(&mut std::io::Cursor::new(buf)).await?;
+/// assert_eq!(u32::from(read), 300u32);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarU32(pub u32);
+
+/// [u64] that is written as varint, values below 128 take a single byte
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarU64(pub u64);
+
+/// [i64] that is written as zigzag encoded varint, values between -64 and
+/// 63 take a single byte
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut buf = Vec::new();
+/// VarI64(-1i64).write(&mut buf).await?;
+/// assert_eq!(buf, vec![1]);
+///
+/// let read = VarI64::read(&mut std::io::Cursor::new(buf)).await?;
+/// assert_eq!(read, VarI64(-1i64));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarI64(pub i64);
+
+impl From<u32> for VarU32 {
+    fn from(x: u32) -> Self {
+        Self(x)
+    }
+}
+
+impl From<VarU32> for u32 {
+    fn from(x: VarU32) -> Self {
+        x.0
+    }
+}
+
+impl From<u64> for VarU64 {
+    fn from(x: u64) -> Self {
+        Self(x)
+    }
+}
+
+impl From<VarU64> for u64 {
+    fn from(x: VarU64) -> Self {
+        x.0
+    }
+}
+
+impl From<i64> for VarI64 {
+    fn from(x: i64) -> Self {
+        Self(x)
+    }
+}
+
+impl From<VarI64> for i64 {
+    fn from(x: VarI64) -> Self {
+        x.0
+    }
+}
+
+#[async_trait]
+impl Parse for VarU32 {
+    async fn read<B>(
+        buf: &mut B
+    ) -> Result<Self, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        Ok(Self(codec::varint::read(buf).await?))
+    }
+
+    async fn write<B>(
+        &self,
+        buf: &mut B
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        codec::varint::write(&self.0, buf).await
+    }
+}
+
+#[async_trait]
+impl Parse for VarU64 {
+    async fn read<B>(
+        buf: &mut B
+    ) -> Result<Self, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        Ok(Self(codec::varint::read(buf).await?))
+    }
+
+    async fn write<B>(
+        &self,
+        buf: &mut B
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        codec::varint::write(&self.0, buf).await
+    }
+}
+
+#[async_trait]
+impl Parse for VarI64 {
+    async fn read<B>(
+        buf: &mut B
+    ) -> Result<Self, CachemError>
+    where
+        B: AsyncBufRead + AsyncRead + Send + Unpin {
+
+        Ok(Self(codec::zigzag::read(buf).await?))
+    }
+
+    async fn write<B>(
+        &self,
+        buf: &mut B
+    ) -> Result<(), CachemError>
+    where
+        B: AsyncWrite + Send + Unpin {
+
+        codec::zigzag::write(&self.0, buf).await
+    }
+}
@@ -352,7 +352,7 @@ impl<T: Parse + Send + Sync> Parse for Vec<T> {
     where
         B: AsyncBufRead + AsyncRead + Send + Unpin {
 
-        let entry_count = u32::read(buf).await?;
+        let entry_count = crate::varint::read_len(buf).await?;
         let mut entries = Vec::with_capacity(entry_count as usize);
 
         for _ in 0..entry_count {
@@ -369,7 +369,7 @@ impl<T: Parse + Send + Sync> Parse for Vec<T> {
     where
         B: AsyncWrite + Send + Unpin {
 
-        buf.write_u32(self.len() as u32).await?;
+        crate::varint::write_len(self.len(), buf).await?;
         for entry in self {
             entry.write(buf).await?;
         }
@@ -390,7 +390,7 @@ where
     where
         B: AsyncBufRead + AsyncRead + Send + Unpin {
 
-        let entry_count = u32::read(buf).await?;
+        let entry_count = crate::varint::read_len(buf).await?;
         let mut entries = HashMap::with_capacity(entry_count as usize);
 
         for _ in 0..entry_count {
@@ -409,7 +409,7 @@ where
     where
         B: AsyncWrite + Send + Unpin {
 
-        buf.write_u32(self.len() as u32).await?;
+        crate::varint::write_len(self.len(), buf).await?;
         for (k, v) in self {
             k.write(buf).await?;
             v.write(buf).await?;
@@ -542,7 +542,7 @@ impl Parse for EmptyMsg {
         B: AsyncBufRead + AsyncRead + Send + Unpin  {
 
         let _ = buf.read_u8().await?;
-        Ok(Self::default())
+        Ok(Self)
     }
 
     async fn write<B>(
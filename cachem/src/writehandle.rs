@@ -0,0 +1,372 @@
+//! Writes to a [LeftRight] that are committed automatically.
+//!
+//! Every write to a [LeftRight] is only visible to readers after a commit.
+//! A [WriteHandle] counts the writes and commits according to its
+//! [CommitPolicy], so that changes are batched without the caller having to
+//! remember to commit.
+
+use crate::{Absorb, LeftRight};
+
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Configures when the writes of a [WriteHandle] are committed
+///
+/// Without any option the writes are only committed by
+/// [WriteHandle::flush].
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # use std::time::Duration;
+/// // commit every 10 milliseconds or after 100 writes
+/// let policy = CommitPolicy::default()
+///     .every(Duration::from_millis(10))
+///     .after_writes(100);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommitPolicy {
+    /// Time between two commits
+    interval: Option<Duration>,
+    /// Number of writes after that a commit is done
+    writes:   Option<u64>,
+}
+
+impl CommitPolicy {
+    /// Commits every time the interval passed, if there were writes since
+    /// the last commit
+    ///
+    /// # Params
+    ///
+    /// * `interval` - Time between two commits
+    ///
+    pub fn every(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Commits after the given number of writes
+    ///
+    /// # Params
+    ///
+    /// * `writes` - Number of writes, must be at least `1`
+    ///
+    pub fn after_writes(mut self, writes: u64) -> Self {
+        self.writes = Some(writes.max(1));
+        self
+    }
+}
+
+/// Handle for writing to a [LeftRight] that commits by itself.
+///
+/// Cloning is cheap, all clones share the same write counter, so the policy
+/// applies to the writes of all clones together. When the last clone is
+/// dropped, writes that were not committed yet are committed by a spawned
+/// task, so they become visible shortly after the drop. Call
+/// [WriteHandle::flush] before dropping the handle to see them right away.
+///
+/// # Example
+///
+/// ```
+/// # use cachem::*;
+/// # use std::collections::HashMap;
+/// # use std::sync::Arc;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let left_right = Arc::new(LeftRight::<HashMap<u32, u32>, MapOp<u32, u32>>::default());
+/// let handle = WriteHandle::new(left_right.clone(), CommitPolicy::default().after_writes(2));
+///
+/// handle.write(MapOp::Insert(1, 1)).await;
+/// assert_eq!(left_right.read(|x| x.len()), 0);
+///
+/// // the second write commits both
+/// handle.write(MapOp::Insert(2, 2)).await;
+/// assert_eq!(left_right.read(|x| x.len()), 2);
+///
+/// handle.write(MapOp::Remove(1)).await;
+/// handle.flush().await;
+/// assert_eq!(left_right.read(|x| x.len()), 1);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct WriteHandle<T, O>
+where
+    T: Absorb<O> + Send + Sync + 'static,
+    O: Send + 'static {
+
+    /// State that is shared between all clones
+    inner: Arc<Inner<T, O>>,
+}
+
+/// State of all clones of a [WriteHandle]
+#[derive(Debug)]
+struct Inner<T, O>
+where
+    T: Absorb<O> + Send + Sync + 'static,
+    O: Send + 'static {
+
+    /// Datastructure the writes are applied to
+    left_right: Arc<LeftRight<T, O>>,
+    /// When the writes are committed
+    policy:     CommitPolicy,
+    /// Writes since the last commit
+    writes:     AtomicU64,
+}
+
+impl<T, O> WriteHandle<T, O>
+where
+    T: Absorb<O> + Send + Sync + 'static,
+    O: Send + 'static {
+
+    /// Creates a new handle. If the policy has an interval, a task is
+    /// spawned that commits in that interval. The task stops when all
+    /// clones of the handle are dropped.
+    ///
+    /// # Params
+    ///
+    /// * `left_right` - Datastructure to write to
+    /// * `policy`     - When the writes are committed
+    ///
+    /// # Panics
+    ///
+    /// If the policy has an interval and the function is not called within
+    /// a tokio runtime
+    ///
+    pub fn new(left_right: Arc<LeftRight<T, O>>, policy: CommitPolicy) -> Self {
+        let inner = Arc::new(Inner {
+            left_right,
+            policy,
+            writes: AtomicU64::new(0),
+        });
+
+        if let Some(interval) = policy.interval {
+            tokio::spawn(Self::commit_task(Arc::downgrade(&inner), interval));
+        }
+
+        Self { inner }
+    }
+
+    /// Applies the operation to the write side, see [LeftRight::write].
+    /// Commits if the number of writes of the policy is reached.
+    ///
+    /// # Params
+    ///
+    /// * `op` - Operation to apply
+    ///
+    pub async fn write(&self, op: O) {
+        self.write_all(std::iter::once(op)).await
+    }
+
+    /// Applies all operations to the write side and counts them as one
+    /// write each. Commits at most once, after all operations are applied.
+    ///
+    /// # Params
+    ///
+    /// * `ops` - Operations to apply
+    ///
+    pub async fn write_all<I>(&self, ops: I)
+    where
+        I: IntoIterator<Item = O> {
+
+        let mut count = 0;
+        for op in ops {
+            self.inner.left_right.write(op);
+            count += 1;
+        }
+
+        // the counter is incremented after the write, so a commit that
+        // resets the counter includes all counted writes
+        let writes = self.inner.writes.fetch_add(count, Ordering::SeqCst) + count;
+        let reached = self.inner.policy.writes
+            .map(|x| writes >= x)
+            .unwrap_or_default();
+        // another writer may have committed our writes already
+        if reached && self.inner.writes.swap(0, Ordering::SeqCst) > 0 {
+            self.inner.left_right.commit().await;
+        }
+    }
+
+    /// Commits all writes, regardless of the policy
+    pub async fn flush(&self) {
+        self.inner.writes.store(0, Ordering::SeqCst);
+        self.inner.left_right.commit().await;
+    }
+
+    /// # Returns
+    ///
+    /// Datastructure the writes are applied to, for reading it
+    ///
+    pub fn left_right(&self) -> &Arc<LeftRight<T, O>> {
+        &self.inner.left_right
+    }
+
+    /// # Returns
+    ///
+    /// Number of writes since the last commit
+    ///
+    pub fn uncommitted(&self) -> u64 {
+        self.inner.writes.load(Ordering::SeqCst)
+    }
+
+    /// Commits in the given interval, as long as there are handles
+    ///
+    /// # Params
+    ///
+    /// * `inner`    - State of the handles
+    /// * `interval` - Time between two commits
+    ///
+    async fn commit_task(inner: Weak<Inner<T, O>>, interval: Duration) {
+        loop {
+            sleep(interval).await;
+
+            let inner = match inner.upgrade() {
+                Some(x) => x,
+                None    => break,
+            };
+            if inner.writes.swap(0, Ordering::SeqCst) > 0 {
+                inner.left_right.commit().await;
+            }
+        }
+    }
+}
+
+impl<T, O> Clone for WriteHandle<T, O>
+where
+    T: Absorb<O> + Send + Sync + 'static,
+    O: Send + 'static {
+
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T, O> Drop for Inner<T, O>
+where
+    T: Absorb<O> + Send + Sync + 'static,
+    O: Send + 'static {
+
+    fn drop(&mut self) {
+        if *self.writes.get_mut() == 0 {
+            return;
+        }
+
+        // blocking would stall the runtime while readers hold a guard
+        match tokio::runtime::Handle::try_current() {
+            Ok(x) => {
+                let left_right = self.left_right.clone();
+                x.spawn(async move { left_right.commit().await });
+            },
+            Err(_) => self.left_right.blocking_commit(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapOp;
+
+    use std::collections::HashMap;
+
+    type Map = LeftRight<HashMap<u32, u32>, MapOp<u32, u32>>;
+
+    #[tokio::test]
+    async fn test_after_writes() {
+        let left_right = Arc::new(Map::default());
+        let handle = WriteHandle::new(left_right.clone(), CommitPolicy::default().after_writes(3));
+
+        handle.write(MapOp::Insert(1, 1)).await;
+        handle.write(MapOp::Insert(2, 2)).await;
+        assert_eq!(handle.uncommitted(), 2);
+        assert_eq!(left_right.read(|x| x.len()), 0);
+
+        handle.write(MapOp::Insert(3, 3)).await;
+        assert_eq!(handle.uncommitted(), 0);
+        assert_eq!(left_right.read(|x| x.len()), 3);
+
+        // all operations count, but the commit is done once at the end
+        handle.write_all((4..9).map(|x| MapOp::Insert(x, x))).await;
+        assert_eq!(handle.uncommitted(), 0);
+        assert_eq!(left_right.read(|x| x.len()), 8);
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_writes() {
+        let left_right = Arc::new(Map::default());
+        let handle = WriteHandle::new(left_right.clone(), CommitPolicy::default().after_writes(2));
+        let clone = handle.clone();
+
+        handle.write(MapOp::Insert(1, 1)).await;
+        assert_eq!(clone.uncommitted(), 1);
+        clone.write(MapOp::Insert(2, 2)).await;
+        assert_eq!(handle.uncommitted(), 0);
+        assert_eq!(left_right.read(|x| x.len()), 2);
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        let left_right = Arc::new(Map::default());
+        let handle = WriteHandle::new(left_right.clone(), CommitPolicy::default());
+
+        for i in 0..10 {
+            handle.write(MapOp::Insert(i, i)).await;
+        }
+        assert_eq!(handle.uncommitted(), 10);
+        assert_eq!(left_right.read(|x| x.len()), 0);
+
+        handle.flush().await;
+        assert_eq!(handle.uncommitted(), 0);
+        assert_eq!(left_right.read(|x| x.len()), 10);
+    }
+
+    #[tokio::test]
+    async fn test_every() {
+        let left_right = Arc::new(Map::default());
+        let handle = WriteHandle::new(
+            left_right.clone(),
+            CommitPolicy::default().every(Duration::from_millis(10))
+        );
+
+        handle.write(MapOp::Insert(1, 1)).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(handle.uncommitted(), 0);
+        assert_eq!(left_right.read(|x| x.len()), 1);
+
+        // the task stops with the last handle
+        drop(handle);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(Arc::strong_count(&left_right), 1);
+    }
+
+    #[tokio::test]
+    async fn test_drop_commits_in_task() {
+        let left_right = Arc::new(Map::default());
+        let handle = WriteHandle::new(left_right.clone(), CommitPolicy::default());
+        handle.write(MapOp::Insert(1, 1)).await;
+
+        // a blocking commit would wait for the guard forever
+        let guard = left_right.read_guard();
+        drop(handle);
+        assert!(guard.is_empty());
+        drop(guard);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(left_right.read(|x| x.get(&1).copied()), Some(1));
+    }
+
+    #[test]
+    fn test_drop_without_runtime() {
+        let left_right = Arc::new(Map::default());
+        let handle = WriteHandle::new(left_right.clone(), CommitPolicy::default());
+        left_right.write(MapOp::Insert(1, 1));
+        handle.inner.writes.store(1, Ordering::SeqCst);
+
+        drop(handle);
+        assert_eq!(left_right.read(|x| x.get(&1).copied()), Some(1));
+    }
+}
@@ -0,0 +1,734 @@
+//! Append-only log of all writes.
+//!
+//! Every SET, MSET, DEL and MDEL that was executed successfully is appended
+//! to the log before the server answers the request. The log starts with
+//! [WriteLog::MAGIC] and the version of the format. A record is the
+//! [LengthEncoding] of the connection followed by the body of the request,
+//! containing the command, the cache and the payload. It is written as
+//! `Vec<u8>` with a [u32] length.
+//!
+//! EXPIRE and SET_WITH_TTL end with the time to live in milliseconds. In the
+//! log it is replaced by the time the entry expires, in milliseconds since
+//! the unix epoch. When the records are read, the remaining time to live is
+//! calculated again, so executing a record never extends the life of an
+//! entry. Entries that expired in the meantime get a time to live of `0`.
+//!
+//! When the server starts, all records are executed again, so that writes
+//! since the last snapshot are not lost. When the log gets too large, all
+//! caches are saved with [crate::Cache::snapshot] in the background and the
+//! records that were written before the snapshots started are removed.
+//! Records that are written while the snapshots are taken are kept, executing
+//! them again on top of the snapshots results in the same entries.
+
+use crate::{CachemError, Command, LengthEncoding, Parse};
+
+use std::convert::TryFrom;
+use std::io::{Cursor, ErrorKind, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::sync::{watch, Mutex, MutexGuard};
+
+/// Configures when the write log is synced to disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Syncs after every write, no write is lost on a crash
+    Always,
+    /// Syncs once every second, writes of the last second may be lost
+    #[default]
+    EverySecond,
+    /// Leaves syncing to the operating system
+    Never,
+}
+
+/// Configuration of the write log
+///
+/// # Example
+///
+/// ```no_run
+/// # use cachem::*;
+/// let (_, mut server) = Server::new("0.0.0.0:55555".into());
+/// server.set_write_log(
+///     WriteLogConfig::new("./writes.log")
+///         .fsync(FsyncPolicy::Always)
+///         .compact_after(1024 * 1024)
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteLogConfig {
+    /// Path of the log file
+    path:         String,
+    /// Configures when the log is synced to disk
+    fsync:        FsyncPolicy,
+    /// Size in bytes after that the log is compacted
+    compact_size: u64,
+}
+
+impl WriteLogConfig {
+    /// Default size in bytes after that the log is compacted, 64 MiB
+    pub const DEFAULT_COMPACT_SIZE: u64 = 64 * 1024 * 1024;
+
+    /// Creates a new configuration with [FsyncPolicy::EverySecond]
+    ///
+    /// # Params
+    ///
+    /// * `path` - Path of the log file
+    ///
+    pub fn new<P: Into<String>>(path: P) -> Self {
+        Self {
+            path:         path.into(),
+            fsync:        FsyncPolicy::default(),
+            compact_size: Self::DEFAULT_COMPACT_SIZE,
+        }
+    }
+
+    /// Sets when the log is synced to disk
+    ///
+    /// # Params
+    ///
+    /// * `policy` - Configures when the log is synced
+    ///
+    pub fn fsync(mut self, policy: FsyncPolicy) -> Self {
+        self.fsync = policy;
+        self
+    }
+
+    /// Sets the size after that all caches are saved and the log is
+    /// compacted
+    ///
+    /// # Params
+    ///
+    /// * `size` - Size in bytes, defaults to
+    ///   [WriteLogConfig::DEFAULT_COMPACT_SIZE]
+    ///
+    pub fn compact_after(mut self, size: u64) -> Self {
+        self.compact_size = size;
+        self
+    }
+}
+
+/// Open write log, all access goes through [WriteLog::lock]
+pub(crate) struct WriteLog(Mutex<LogFile>);
+
+impl WriteLog {
+    /// Bytes every write log starts with
+    pub(crate) const MAGIC: [u8; 4] = *b"CCWL";
+    /// Current version of the file format
+    pub(crate) const VERSION: u16 = 1u16;
+    /// Size of the magic and the version
+    const HEADER_SIZE: u64 = 6u64;
+
+    /// Opens the log and reads all records.
+    ///
+    /// If the last record is incomplete, because the process crashed while
+    /// writing it, the record is removed from the log. A new log only
+    /// contains the header.
+    ///
+    /// # Params
+    ///
+    /// * `config` - Configuration of the log
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be opened or contains invalid records,
+    /// [CachemError::CorruptWriteLog] if it does not start with the header or
+    /// was written by a newer version
+    ///
+    /// # Returns
+    ///
+    /// The opened log and all records that should be executed again, with
+    /// the encoding they were written in
+    ///
+    pub(crate) async fn open(
+        config: WriteLogConfig,
+    ) -> Result<(Self, Vec<(LengthEncoding, Vec<u8>)>), CachemError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&config.path)
+            .await?;
+        let len = file.metadata().await?.len();
+
+        let mut reader = BufReader::new(&mut file);
+        if len > 0 {
+            let mut magic = [0u8; 4];
+            if len >= Self::HEADER_SIZE {
+                reader.read_exact(&mut magic).await?;
+            }
+            if magic != Self::MAGIC {
+                return Err(CachemError::CorruptWriteLog {
+                    file:   config.path,
+                    reason: "missing header".into(),
+                });
+            }
+
+            let version = u16::read(&mut reader).await?;
+            if version > Self::VERSION {
+                return Err(CachemError::CorruptWriteLog {
+                    file:   config.path,
+                    reason: format!("unknown version {}", version),
+                });
+            }
+        }
+
+        let mut records = Vec::new();
+        let mut size = 0u64;
+        while Self::HEADER_SIZE + size < len {
+            let record = LengthEncoding::Fixed
+                .scope(Vec::<u8>::read(&mut reader))
+                .await;
+            match record {
+                Ok(x) => {
+                    size += Self::record_size(&x);
+
+                    let mut record = Cursor::new(x);
+                    let encoding = LengthEncoding::read(&mut record).await?;
+                    let mut body = record.get_ref()[record.position() as usize..].to_vec();
+                    Self::deadline_to_ttl(&mut body, SystemTime::now());
+                    records.push((encoding, body));
+                },
+                Err(CachemError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    log::warn!("Removing incomplete record at the end of the write log");
+                    break;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        drop(reader);
+
+        let mut log = LogFile {
+            path:         config.path,
+            file,
+            size,
+            dirty:        false,
+            removed:      0,
+            compacting:   false,
+            fsync:        config.fsync,
+            compact_size: config.compact_size,
+            next_compact: config.compact_size,
+        };
+
+        if len == 0 {
+            log.replace(Vec::new()).await?;
+        } else {
+            log.file.set_len(Self::HEADER_SIZE + size).await?;
+            log.file.seek(SeekFrom::End(0)).await?;
+        }
+        Ok((Self(Mutex::new(log)), records))
+    }
+
+    /// Locks the log, writes should be executed while holding the lock so
+    /// that the records have the same order as the writes
+    pub(crate) async fn lock(&self) -> MutexGuard<'_, LogFile> {
+        self.0.lock().await
+    }
+
+    /// Syncs the log every second until the server shuts down, only used
+    /// with [FsyncPolicy::EverySecond]
+    ///
+    /// # Params
+    ///
+    /// * `shutdown` - Changes when the server shuts down
+    ///
+    pub(crate) async fn sync_every_second(
+        &self,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(1)) => (),
+                _ = shutdown.changed() => break,
+            };
+
+            if let Err(e) = self.lock().await.sync().await {
+                log::error!("Failed to sync write log; err = {:?}", e);
+            }
+        }
+    }
+
+    /// Creates a record, a time to live at the end of the request is
+    /// replaced with the time the entry expires
+    ///
+    /// # Params
+    ///
+    /// * `encoding` - Encoding the request was written in
+    /// * `body`     - Body of the request
+    /// * `now`      - Time the request was executed
+    ///
+    /// # Errors
+    ///
+    /// If the record cannot be written
+    ///
+    /// # Returns
+    ///
+    /// The record as it is written to the file
+    ///
+    async fn record(
+        encoding: LengthEncoding,
+        body:     &[u8],
+        now:      SystemTime,
+    ) -> Result<Vec<u8>, CachemError> {
+        let mut content = Vec::with_capacity(body.len() + 1);
+        encoding.write(&mut content).await?;
+        let start = content.len();
+        content.extend_from_slice(body);
+        Self::ttl_to_deadline(&mut content[start..], now);
+
+        // same encoding as `Vec<u8>` with a fixed length
+        let mut record = Vec::with_capacity(Self::record_size(&content) as usize);
+        (content.len() as u32).write(&mut record).await?;
+        record.extend_from_slice(&content);
+        Ok(record)
+    }
+
+    /// # Returns
+    ///
+    /// Size of the record with the given content in the file
+    ///
+    fn record_size(body: &[u8]) -> u64 {
+        std::mem::size_of::<u32>() as u64 + body.len() as u64
+    }
+
+    /// # Returns
+    ///
+    /// Position of the time to live in the body of the request, if the
+    /// command ends with one
+    ///
+    fn ttl_position(body: &[u8]) -> Option<usize> {
+        let cmd = body.first().and_then(|x| Command::try_from(*x).ok())?;
+        match cmd {
+            Command::Expire |
+            Command::SetWithTtl => body.len().checked_sub(std::mem::size_of::<u64>()),
+            _ => None,
+        }
+    }
+
+    /// Replaces the time to live at the end of the request with the time the
+    /// entry expires
+    ///
+    /// # Params
+    ///
+    /// * `body` - Body of the request
+    /// * `now`  - Time the request was executed
+    ///
+    fn ttl_to_deadline(body: &mut [u8], now: SystemTime) {
+        if let Some(pos) = Self::ttl_position(body) {
+            let ttl = Self::read_u64(&body[pos..]);
+            let now = now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            body[pos..].copy_from_slice(&now.saturating_add(ttl).to_be_bytes());
+        }
+    }
+
+    /// Replaces the time the entry expires at the end of the request with the
+    /// remaining time to live
+    ///
+    /// # Params
+    ///
+    /// * `body` - Body of the logged request
+    /// * `now`  - Time the request is executed again
+    ///
+    fn deadline_to_ttl(body: &mut [u8], now: SystemTime) {
+        if let Some(pos) = Self::ttl_position(body) {
+            let deadline = Self::read_u64(&body[pos..]);
+            let now = now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            body[pos..].copy_from_slice(&deadline.saturating_sub(now).to_be_bytes());
+        }
+    }
+
+    /// Reads a [u64] the same way as [Parse] does
+    ///
+    /// # Params
+    ///
+    /// * `buf` - Exactly 8 bytes
+    ///
+    fn read_u64(buf: &[u8]) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(buf);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+/// File of the write log
+pub(crate) struct LogFile {
+    /// Path of the log file
+    path:         String,
+    /// Opened log file
+    file:         File,
+    /// Size of all records in bytes
+    size:         u64,
+    /// `true` if there are records that are not synced yet
+    dirty:        bool,
+    /// Bytes that were removed from the start of the log by compactions
+    removed:      u64,
+    /// `true` while the caches are saved for a compaction
+    compacting:   bool,
+    /// Configures when the log is synced to disk
+    fsync:        FsyncPolicy,
+    /// Size in bytes after that the log is compacted
+    compact_size: u64,
+    /// Size in bytes at which the next compaction is done
+    next_compact: u64,
+}
+
+impl LogFile {
+    /// Appends a new record to the log, a time to live at the end of the
+    /// request is replaced with the time the entry expires
+    ///
+    /// # Params
+    ///
+    /// * `encoding` - Encoding the request was written in
+    /// * `body`     - Body of the request
+    ///
+    pub(crate) async fn append(
+        &mut self,
+        encoding: LengthEncoding,
+        body:     &[u8],
+    ) -> Result<(), CachemError> {
+        let record = WriteLog::record(encoding, body, SystemTime::now()).await?;
+        self.file.write_all(&record).await?;
+        self.file.flush().await?;
+        self.size += record.len() as u64;
+        self.dirty = true;
+
+        if self.fsync == FsyncPolicy::Always {
+            self.sync().await?;
+        }
+        Ok(())
+    }
+
+    /// Syncs all records to disk
+    pub(crate) async fn sync(&mut self) -> Result<(), CachemError> {
+        if self.dirty && self.fsync != FsyncPolicy::Never {
+            self.file.sync_data().await?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Marks the start of a compaction, must be called before the caches
+    /// are saved
+    ///
+    /// # Returns
+    ///
+    /// Position of the end of the log, all records before it are included in
+    /// the snapshots that are taken afterwards. The position stays valid
+    /// when another compaction finishes in the meantime.
+    ///
+    pub(crate) fn start_compaction(&mut self) -> u64 {
+        self.compacting = true;
+        self.removed + self.size
+    }
+
+    /// Removes all records before the given size, must only be called after
+    /// all caches were saved.
+    ///
+    /// The remaining records are written to a new file that replaces the log,
+    /// so that a crash while compacting never destroys the log.
+    ///
+    /// # Params
+    ///
+    /// * `start` - Position returned by [LogFile::start_compaction]
+    ///
+    /// # Errors
+    ///
+    /// If the new log could not be written, the previous log is kept
+    ///
+    pub(crate) async fn compact(&mut self, start: u64) -> Result<(), CachemError> {
+        // a failed compaction is tried again after the log grew
+        self.postpone_compaction();
+
+        // records before the position may be removed already
+        let start = start.saturating_sub(self.removed);
+        if start == 0 {
+            return Ok(());
+        }
+
+        // the records are read with their own handle, so that the position
+        // of the log file stays at the end
+        let mut records = Vec::with_capacity((self.size - start) as usize);
+        let mut log = File::open(&self.path).await?;
+        log.seek(SeekFrom::Start(WriteLog::HEADER_SIZE + start)).await?;
+        log.read_to_end(&mut records).await?;
+
+        self.replace(records).await?;
+        self.removed += start;
+        self.next_compact = self.compact_size;
+        Ok(())
+    }
+
+    /// Replaces the log with a new file that contains the header and the
+    /// given records. The new file is written next to the log and renamed,
+    /// so that a crash never destroys the log.
+    ///
+    /// # Params
+    ///
+    /// * `records` - Records of the new log
+    ///
+    /// # Errors
+    ///
+    /// If the new log could not be written, the previous log is kept
+    ///
+    async fn replace(&mut self, records: Vec<u8>) -> Result<(), CachemError> {
+        let mut content = Vec::with_capacity(WriteLog::HEADER_SIZE as usize + records.len());
+        content.extend_from_slice(&WriteLog::MAGIC);
+        WriteLog::VERSION.write(&mut content).await?;
+        content.extend_from_slice(&records);
+
+        let tmp = format!("{}.compact", self.path);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp)
+            .await?;
+        file.write_all(&content).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+
+        tokio::fs::rename(&tmp, &self.path).await?;
+        sync_parent(&self.path).await?;
+
+        self.file = file;
+        self.size = records.len() as u64;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// # Returns
+    ///
+    /// `true` if the log is larger than the configured compaction size and
+    /// no compaction is running
+    ///
+    pub(crate) fn needs_compaction(&self) -> bool {
+        !self.compacting && self.size >= self.next_compact
+    }
+
+    /// Called when the compaction failed, the next compaction is tried after
+    /// the log grew by the configured compaction size
+    pub(crate) fn postpone_compaction(&mut self) {
+        self.compacting = false;
+        self.next_compact = self.size + self.compact_size;
+    }
+}
+
+/// Syncs the directory of the given file, so that a rename of the file
+/// survives a crash
+///
+/// # Params
+///
+/// * `path` - Path of the file
+///
+/// # Errors
+///
+/// If the directory cannot be opened or synced
+///
+pub(crate) async fn sync_parent(path: &str) -> Result<(), CachemError> {
+    let parent = match Path::new(path).parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+    File::open(parent).await?.sync_all().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a config for a new log in the temp directory
+    fn config(name: &str) -> WriteLogConfig {
+        let path = std::env::temp_dir().join(format!("cachem_writelog_{}", name));
+        let _ = std::fs::remove_file(&path);
+        WriteLogConfig::new(path.to_str().unwrap())
+            .fsync(FsyncPolicy::Never)
+            .compact_after(16)
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let config = config("replay");
+        let (log, records) = WriteLog::open(config.clone()).await.unwrap();
+        assert!(records.is_empty());
+
+        let mut log_file = log.lock().await;
+        log_file.append(LengthEncoding::Fixed, &[1, 2]).await.unwrap();
+        log_file.append(LengthEncoding::Compact, &[3]).await.unwrap();
+        drop(log_file);
+        drop(log);
+
+        let (_, records) = WriteLog::open(config).await.unwrap();
+        assert_eq!(records, vec![
+            (LengthEncoding::Fixed, vec![1, 2]),
+            (LengthEncoding::Compact, vec![3]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_header() {
+        let config = config("header");
+        let (log, _) = WriteLog::open(config.clone()).await.unwrap();
+        log.lock().await.append(LengthEncoding::Compact, &[1]).await.unwrap();
+        drop(log);
+
+        let file = std::fs::read(&config.path).unwrap();
+        assert_eq!(file, vec![b'C', b'C', b'W', b'L', 0, 1, 0, 0, 0, 2, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_header() {
+        let config = config("missing_header");
+        let mut file = Vec::new();
+        vec![1u8, 2].write(&mut file).await.unwrap();
+        std::fs::write(&config.path, &file).unwrap();
+
+        assert!(matches!(
+            WriteLog::open(config.clone()).await,
+            Err(CachemError::CorruptWriteLog { .. })
+        ));
+
+        // a damaged header is not read as records either
+        std::fs::write(&config.path, [b'C', b'X', b'W', b'L', 0, 1]).unwrap();
+        assert!(matches!(
+            WriteLog::open(config.clone()).await,
+            Err(CachemError::CorruptWriteLog { .. })
+        ));
+
+        // the file is left untouched
+        assert_eq!(std::fs::read(&config.path).unwrap(), vec![b'C', b'X', b'W', b'L', 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_newer_version() {
+        let config = config("newer_version");
+        std::fs::write(&config.path, [b'C', b'C', b'W', b'L', 0, 2]).unwrap();
+
+        assert!(matches!(
+            WriteLog::open(config).await,
+            Err(CachemError::CorruptWriteLog { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ttl_is_logged_as_deadline() {
+        let mut body = vec![Command::Expire.into(), 0u8, 0, 0, 0, 1];
+        body.extend_from_slice(&500u64.to_be_bytes());
+
+        let executed = UNIX_EPOCH + Duration::from_millis(1000);
+        WriteLog::ttl_to_deadline(&mut body, executed);
+        assert_eq!(WriteLog::read_u64(&body[6..]), 1500u64);
+
+        let mut replayed = body.clone();
+        WriteLog::deadline_to_ttl(&mut replayed, executed + Duration::from_millis(200));
+        assert_eq!(WriteLog::read_u64(&replayed[6..]), 300u64);
+
+        // the entry expired before the log was replayed
+        let mut replayed = body.clone();
+        WriteLog::deadline_to_ttl(&mut replayed, executed + Duration::from_secs(10));
+        assert_eq!(WriteLog::read_u64(&replayed[6..]), 0u64);
+
+        // other commands are not changed
+        let mut body = vec![Command::Set.into(), 0u8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, 244];
+        let expected = body.clone();
+        WriteLog::ttl_to_deadline(&mut body, executed);
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_replay_expire() {
+        let config = config("replay_expire");
+        let (log, _) = WriteLog::open(config.clone()).await.unwrap();
+
+        let mut body = vec![Command::SetWithTtl.into(), 0u8, 0, 0, 0, 1, 0, 0, 0, 2];
+        body.extend_from_slice(&60_000u64.to_be_bytes());
+        log.lock().await.append(LengthEncoding::Fixed, &body).await.unwrap();
+        drop(log);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let (_, records) = WriteLog::open(config).await.unwrap();
+        let (_, replayed) = &records[0];
+        assert_eq!(replayed[..10], body[..10]);
+
+        // the time since the write is not added again
+        let ttl = WriteLog::read_u64(&replayed[10..]);
+        assert!(ttl < 60_000u64 - 10);
+        assert!(ttl > 50_000u64);
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_record() {
+        let config = config("incomplete");
+        let (log, _) = WriteLog::open(config.clone()).await.unwrap();
+        log.lock().await.append(LengthEncoding::Fixed, &[1, 2]).await.unwrap();
+        drop(log);
+
+        // crash while writing the length of the next record
+        let mut file = std::fs::read(&config.path).unwrap();
+        let len = file.len() as u64;
+        file.extend_from_slice(&[0, 0]);
+        std::fs::write(&config.path, file).unwrap();
+
+        let (_, records) = WriteLog::open(config.clone()).await.unwrap();
+        assert_eq!(records, vec![(LengthEncoding::Fixed, vec![1, 2])]);
+        assert_eq!(std::fs::metadata(&config.path).unwrap().len(), len);
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let config = config("compact");
+        let (log, _) = WriteLog::open(config.clone()).await.unwrap();
+
+        let mut log_file = log.lock().await;
+        log_file.append(LengthEncoding::Fixed, &[0u8; 16]).await.unwrap();
+        assert!(log_file.needs_compaction());
+        let start = log_file.start_compaction();
+        assert!(!log_file.needs_compaction());
+
+        // written while the caches are saved
+        log_file.append(LengthEncoding::Fixed, &[1]).await.unwrap();
+        log_file.compact(start).await.unwrap();
+        assert!(!log_file.needs_compaction());
+
+        // appends go to the compacted log
+        log_file.append(LengthEncoding::Fixed, &[2]).await.unwrap();
+        drop(log_file);
+        drop(log);
+
+        let (_, records) = WriteLog::open(config).await.unwrap();
+        assert_eq!(records, vec![
+            (LengthEncoding::Fixed, vec![1]),
+            (LengthEncoding::Fixed, vec![2]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_compactions() {
+        let config = config("concurrent");
+        let (log, _) = WriteLog::open(config.clone()).await.unwrap();
+
+        let mut log_file = log.lock().await;
+        log_file.append(LengthEncoding::Fixed, &[1]).await.unwrap();
+        let first = log_file.start_compaction();
+        log_file.append(LengthEncoding::Fixed, &[2]).await.unwrap();
+        let second = log_file.start_compaction();
+        log_file.append(LengthEncoding::Fixed, &[3]).await.unwrap();
+
+        // the first compaction finishes last and must not remove more
+        log_file.compact(second).await.unwrap();
+        log_file.compact(first).await.unwrap();
+        drop(log_file);
+        drop(log);
+
+        let (_, records) = WriteLog::open(config).await.unwrap();
+        assert_eq!(records, vec![(LengthEncoding::Fixed, vec![3])]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Checks every interleaving of concurrent reads, writes and commits on a
+//! [LeftRight] with [loom](https://docs.rs/loom).
+//!
+//! Run with `make loom` or
+//! `RUSTFLAGS="--cfg cachem_loom" cargo test -p cachem --test loom_leftright --release`
+#![cfg(cachem_loom)]
+
+use cachem::{LeftRight, MapOp};
+use loom::sync::Arc;
+use loom::thread;
+use std::collections::HashMap;
+
+type Map = LeftRight<HashMap<u32, u32>, MapOp<u32, u32>>;
+
+/// A reader that arrives while a commit swaps the sides must never see the
+/// side that is changed by the commit
+#[test]
+fn read_while_commit() {
+    loom::model(|| {
+        let left_right = Arc::new(Map::default());
+        left_right.write(MapOp::Insert(1, 1));
+
+        let reader = {
+            let left_right = left_right.clone();
+            thread::spawn(move || {
+                let entries = left_right.read_guard();
+                // either before or after the commit, never in between
+                let value = entries.get(&1).copied();
+                assert!(value.is_none() || value == Some(1));
+                assert_eq!(entries.get(&1).copied(), value);
+            })
+        };
+
+        left_right.blocking_commit();
+        assert_eq!(left_right.read(|x| x.get(&1).copied()), Some(1));
+
+        reader.join().unwrap();
+    });
+}
+
+/// Writes that happen during a commit are queued and end up on both sides
+#[test]
+fn write_while_commit() {
+    loom::model(|| {
+        let left_right = Arc::new(Map::default());
+        left_right.write(MapOp::Insert(1, 1));
+
+        let writer = {
+            let left_right = left_right.clone();
+            thread::spawn(move || {
+                left_right.write(MapOp::Insert(2, 2));
+            })
+        };
+
+        left_right.blocking_commit();
+        writer.join().unwrap();
+
+        // both sides contain both entries
+        left_right.blocking_commit();
+        assert_eq!(left_right.read(|x| x.len()), 2);
+        left_right.blocking_commit();
+        assert_eq!(left_right.read(|x| x.len()), 2);
+    });
+}
+
+/// Two commits at the same time wait for each other
+#[test]
+fn concurrent_commits() {
+    loom::model(|| {
+        let left_right = Arc::new(Map::default());
+        left_right.write(MapOp::Insert(1, 1));
+
+        let committer = {
+            let left_right = left_right.clone();
+            thread::spawn(move || {
+                left_right.blocking_commit();
+            })
+        };
+
+        left_right.blocking_commit();
+        committer.join().unwrap();
+
+        assert_eq!(left_right.read(|x| x.get(&1).copied()), Some(1));
+        left_right.blocking_commit();
+        assert_eq!(left_right.read(|x| x.get(&1).copied()), Some(1));
+    });
+}
+
+/// A reader that arrives on a side while two commits switch away from the
+/// side and back must not see the side while it is changed
+#[test]
+fn read_while_two_commits() {
+    loom::model(|| {
+        let left_right = Arc::new(Map::default());
+
+        let reader = {
+            let left_right = left_right.clone();
+            thread::spawn(move || {
+                let entries = left_right.read_guard();
+                let value = entries.get(&1).copied();
+                assert!(value.is_none() || value == Some(1) || value == Some(2));
+                assert_eq!(entries.get(&1).copied(), value);
+            })
+        };
+
+        left_right.write(MapOp::Insert(1, 1));
+        left_right.blocking_commit();
+        left_right.write(MapOp::Insert(1, 2));
+        left_right.blocking_commit();
+        assert_eq!(left_right.read(|x| x.get(&1).copied()), Some(2));
+
+        reader.join().unwrap();
+    });
+}
+
+/// A commit waits until a reader that holds a guard of the previous side
+/// dropped it, the guard keeps seeing the previous entries
+#[test]
+fn commit_while_read_guard() {
+    loom::model(|| {
+        let left_right = Arc::new(Map::default());
+        left_right.write(MapOp::Insert(1, 1));
+
+        let entries = left_right.read_guard();
+        let committer = {
+            let left_right = left_right.clone();
+            thread::spawn(move || {
+                left_right.blocking_commit();
+            })
+        };
+
+        assert_eq!(entries.get(&1).copied(), None);
+        thread::yield_now();
+        assert_eq!(entries.get(&1).copied(), None);
+        drop(entries);
+
+        committer.join().unwrap();
+        assert_eq!(left_right.read(|x| x.get(&1).copied()), Some(1));
+    });
+}
@@ -0,0 +1,207 @@
+//! Parsing of the `#[cachem(..)]` attribute on cache structs
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Ident, Lit, LitStr, Meta, NestedMeta, Type};
+use syn::spanned::Spanned;
+
+/// Options of a cache struct, given with
+/// `#[cachem(map = "entries", key = "u32", value = "MyEntry", file = "./my.cachem")]`
+///
+/// The map field must be a `tokio::sync::RwLock<HashMap<key, value>>`.
+pub struct CacheAttrs {
+    /// Name of the struct
+    ident: Ident,
+    /// Field that contains the entries
+    map:   Option<Ident>,
+    /// Type of the keys
+    key:   Option<Type>,
+    /// Type of the values
+    value: Option<Type>,
+    /// Path of the snapshot file
+    file:  Option<LitStr>,
+    /// Name of the cache, defaults to the name of the struct
+    name:  Option<LitStr>,
+}
+
+impl CacheAttrs {
+    /// Reads all `#[cachem(..)]` attributes of the struct
+    ///
+    /// # Params
+    ///
+    /// * `input` - Information about the cache struct
+    ///
+    /// # Error
+    ///
+    /// Returns an error if the input is not a struct with named fields, if
+    /// an option is unknown or if the map field does not exist
+    ///
+    pub fn parse(input: &DeriveInput) -> Result<Self, TokenStream> {
+        let fields = match &input.data {
+            Data::Struct(x) => match &x.fields {
+                Fields::Named(x) => x
+                    .named
+                    .iter()
+                    .filter_map(|x| x.ident.clone())
+                    .collect::<Vec<_>>(),
+                _ => return Err(crate::utils::error(
+                            input.ident.span(),
+                            "Only structs with named fields are supported".into()
+                        )
+                    ),
+            },
+            _ => return Err(crate::utils::error(
+                        input.ident.span(),
+                        "The given type is not supported".into()
+                    )
+                ),
+        };
+
+        if !input.generics.params.is_empty() {
+            return Err(crate::utils::error(
+                        input.generics.span(),
+                        "Generic structs are not supported".into()
+                    )
+                )
+        }
+
+        let mut attrs = Self {
+            ident: input.ident.clone(),
+            map:   None,
+            key:   None,
+            value: None,
+            file:  None,
+            name:  None,
+        };
+
+        for attr in input.attrs.iter().filter(|x| x.path.is_ident("cachem")) {
+            let list = match attr.parse_meta() {
+                Ok(Meta::List(x)) => x,
+                _ => return Err(crate::utils::error(
+                            attr.span(),
+                            "Expected #[cachem(option = \"value\")]".into()
+                        )
+                    ),
+            };
+
+            for nested in list.nested.iter() {
+                let (option, value) = match nested {
+                    NestedMeta::Meta(Meta::NameValue(x)) => match &x.lit {
+                        Lit::Str(lit) => (x.path.get_ident(), lit.clone()),
+                        _ => return Err(crate::utils::error(
+                                    x.lit.span(),
+                                    "Expected a string".into()
+                                )
+                            ),
+                    },
+                    _ => return Err(crate::utils::error(
+                                nested.span(),
+                                "Expected option = \"value\"".into()
+                            )
+                        ),
+                };
+
+                match option.map(|x| x.to_string()).as_deref() {
+                    Some("map") => {
+                        let map = value.parse::<Ident>()
+                            .map_err(|_| crate::utils::error(
+                                value.span(),
+                                "Expected the name of a field".into()
+                            ))?;
+                        if !fields.contains(&map) {
+                            return Err(crate::utils::error(
+                                        value.span(),
+                                        format!("The struct has no field `{}`", map)
+                                    )
+                                )
+                        }
+                        attrs.map = Some(map);
+                    },
+                    Some("key")   => attrs.key   = Some(Self::parse_type(&value)?),
+                    Some("value") => attrs.value = Some(Self::parse_type(&value)?),
+                    Some("file")  => attrs.file  = Some(value),
+                    Some("name")  => attrs.name  = Some(value),
+                    _ => return Err(crate::utils::error(
+                                nested.span(),
+                                "Unknown option, expected map, key, value, file or name".into()
+                            )
+                        ),
+                }
+            }
+        }
+
+        Ok(attrs)
+    }
+
+    /// # Error
+    ///
+    /// Returns an error if the option `map` is missing
+    ///
+    pub fn map(&self) -> Result<&Ident, TokenStream> {
+        self.map.as_ref().ok_or_else(|| self.missing("map", "entries"))
+    }
+
+    /// # Error
+    ///
+    /// Returns an error if the option `key` is missing
+    ///
+    pub fn key(&self) -> Result<&Type, TokenStream> {
+        self.key.as_ref().ok_or_else(|| self.missing("key", "u32"))
+    }
+
+    /// # Error
+    ///
+    /// Returns an error if the option `value` is missing
+    ///
+    pub fn value(&self) -> Result<&Type, TokenStream> {
+        self.value.as_ref().ok_or_else(|| self.missing("value", "MyEntry"))
+    }
+
+    /// # Error
+    ///
+    /// Returns an error if the option `file` is missing
+    ///
+    pub fn file(&self) -> Result<&LitStr, TokenStream> {
+        self.file.as_ref().ok_or_else(|| self.missing("file", "./my.cachem"))
+    }
+
+    /// # Returns
+    ///
+    /// `true` if the option `file` is set
+    ///
+    pub fn has_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// # Returns
+    ///
+    /// Name of the cache, defaults to the name of the struct
+    ///
+    pub fn name(&self) -> TokenStream {
+        match self.name.as_ref() {
+            Some(x) => quote! { #x },
+            None    => {
+                let name = self.ident.to_string();
+                quote! { #name }
+            },
+        }
+    }
+
+    /// Parses the type given as string
+    fn parse_type(value: &LitStr) -> Result<Type, TokenStream> {
+        value
+            .parse::<Type>()
+            .map_err(|_| crate::utils::error(
+                value.span(),
+                "Expected a type".into()
+            ))
+    }
+
+    /// Generates the error for a missing option
+    fn missing(&self, option: &str, example: &str) -> TokenStream {
+        crate::utils::error(
+            self.ident.span(),
+            format!("Missing attribute #[cachem({} = \"{}\")]", option, example)
+        )
+    }
+}
@@ -0,0 +1,130 @@
+use crate::attrs::CacheAttrs;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+/// Codegen for implementing the [cachem::Cache] trait on a cache struct.
+///
+/// Get, MGet, Exists and MExists read the map directly. All other commands
+/// are dispatched to [cachem::Set2], [cachem::Del] and [cachem::Key], which
+/// are implemented by the derives `Set`, `Del` and `Key` or by hand.
+/// If the option `file` is set, [cachem::Cache::snapshot] saves the cache
+/// with [cachem::Storage::save_to_file], which is implemented by the derive
+/// `Save`. Expire, Ttl, Persist and SetWithTtl are not supported.
+///
+/// # Params
+///
+/// * `input` - Information about the cache struct
+///
+/// # Error
+///
+/// Returns an error if one of the options `map`, `key` or `value` is missing
+///
+/// # Returns
+///
+/// [proc_macro2::TokenStream] with the implementation of [cachem::Cache]
+///
+pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
+    let attrs = CacheAttrs::parse(&input)?;
+    let name = &input.ident;
+    let map = attrs.map()?;
+    let key = attrs.key()?;
+    let value = attrs.value()?;
+    let cache_name = attrs.name();
+
+    let snapshot = if attrs.has_file() {
+        quote! {
+            async fn snapshot(&self) -> Result<(), cachem::CachemError> {
+                cachem::Storage::save_to_file(self).await
+            }
+
+            fn supports_snapshot(&self) -> bool {
+                true
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    Ok(quote! {
+        #[async_trait::async_trait]
+        impl cachem::Cache for #name {
+            fn name(&self) -> String {
+                #cache_name.into()
+            }
+
+            async fn handle(
+                &self,
+                cmd: cachem::Command,
+                req: &mut std::io::Cursor<Vec<u8>>,
+                res: &mut Vec<u8>,
+            ) -> Result<(), cachem::CachemError> {
+                use cachem::Parse;
+
+                match cmd {
+                    cachem::Command::Get => {
+                        let id = <#key as Parse>::read(req).await?;
+                        self.#map.read().await.get(&id).cloned().write(res).await
+                    },
+                    cachem::Command::MGet => {
+                        let ids = <Vec<#key> as Parse>::read(req).await?;
+                        let entries = self.#map.read().await;
+                        ids
+                            .iter()
+                            .map(|x| entries.get(x).cloned())
+                            .collect::<Vec<_>>()
+                            .write(res)
+                            .await
+                    },
+                    cachem::Command::Keys => {
+                        cachem::Key::keys(self).await.write(res).await
+                    },
+                    cachem::Command::Exists => {
+                        let id = <#key as Parse>::read(req).await?;
+                        self.#map.read().await.contains_key(&id).write(res).await
+                    },
+                    cachem::Command::MExists => {
+                        let ids = <Vec<#key> as Parse>::read(req).await?;
+                        let entries = self.#map.read().await;
+                        ids
+                            .iter()
+                            .map(|x| entries.contains_key(x))
+                            .collect::<Vec<_>>()
+                            .write(res)
+                            .await
+                    },
+                    cachem::Command::Set => {
+                        let id = <#key as Parse>::read(req).await?;
+                        let val = <#value as Parse>::read(req).await?;
+                        cachem::Set2::<#key, #value>::set(self, id, val).await;
+                        Ok(())
+                    },
+                    cachem::Command::MSet => {
+                        let entries = <std::collections::HashMap<#key, #value> as Parse>::read(req).await?;
+                        cachem::Set2::<#key, #value>::mset(self, entries).await;
+                        Ok(())
+                    },
+                    cachem::Command::Del => {
+                        let id = <#key as Parse>::read(req).await?;
+                        cachem::Del::del(self, id).await;
+                        Ok(())
+                    },
+                    cachem::Command::MDel => {
+                        let ids = <Vec<#key> as Parse>::read(req).await?;
+                        cachem::Del::mdel(self, ids).await;
+                        Ok(())
+                    },
+                    cachem::Command::Save => {
+                        cachem::Cache::snapshot(self).await
+                    },
+                    _ => Err(cachem::CachemError::UnknownCommand(cmd.into())),
+                }
+            }
+
+            async fn cnc_listener(&self) {}
+
+            #snapshot
+        }
+    })
+}
@@ -0,0 +1,50 @@
+use crate::attrs::CacheAttrs;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+/// Codegen for implementing the [cachem::Del] trait on a cache struct.
+///
+/// Removes the entries from the map given with the option `map`.
+///
+/// # Params
+///
+/// * `input` - Information about the cache struct
+///
+/// # Error
+///
+/// Returns an error if one of the options `map` or `key` is missing
+///
+/// # Returns
+///
+/// [proc_macro2::TokenStream] with the implementation of [cachem::Del]
+///
+pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
+    let attrs = CacheAttrs::parse(&input)?;
+    let name = &input.ident;
+    let map = attrs.map()?;
+    let key = attrs.key()?;
+
+    Ok(quote! {
+        #[async_trait::async_trait]
+        impl cachem::Del for #name {
+            type Id = #key;
+
+            async fn del(&self, id: #key) {
+                self
+                    .#map
+                    .write()
+                    .await
+                    .remove(&id);
+            }
+
+            async fn mdel(&self, ids: Vec<#key>) {
+                let mut entries = self.#map.write().await;
+                for id in ids {
+                    entries.remove(&id);
+                }
+            }
+        }
+    })
+}
@@ -1,32 +1,363 @@
 use proc_macro2::TokenStream;
-use syn::{Data, DeriveInput, Field};
+use quote::{format_ident, quote};
+use syn::{Attribute, Data, DeriveInput, Field, Fields, Meta, NestedMeta, Type};
+use syn::spanned::Spanned;
+
+/// Role of a field in the generated cache
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyAttr {
+    /// Field is the primary key, marked with `#[cachem(Primary)]`
+    Primary,
+    /// Field has a secondary index, marked with `#[cachem(Index)]`
+    Index,
+}
 
 /// Codegen for implementing the [cachem::Get2] trait.
 ///
+/// Generates a new struct `{Name}Cache` that stores all entries by their
+/// primary key and keeps a map from every indexed field to the primary key.
+/// Indexes are unique, every value of an indexed field points to a single
+/// entry.
+///
+/// Every indexed field gets the method `get_by_{field}`. [cachem::Get2] is
+/// implemented for the primary key and [cachem::Get2] and [cachem::Index]
+/// for every indexed field that does not share its type with the primary
+/// key or another indexed field, as a type can only have one implementation.
+///
+/// The cache also implements [cachem::Set2], [cachem::Del], [cachem::Key]
+/// and [cachem::Cache] for the primary key, so it can be added to a
+/// [cachem::Server]. All writes go through `insert` and `remove` and keep the
+/// indexes up to date. A SET stores the entry under the given id and
+/// overwrites the primary key field of the entry with it.
+///
 /// # Params
 ///
-/// * `input` - Information about the struct the trait should be implmented on
+/// * `input` - Information about the struct the trait should be implemented on
 ///
 /// # Error
 ///
-/// Returns an error when the struct has no primary key.
+/// Returns an error when the struct has no or multiple primary keys or when
+/// an attribute is unknown.
 ///
 /// # Returns
 ///
-/// New [proc_macro2::TokenStream] containung the implementation of the trait.
+/// New [proc_macro2::TokenStream] containing the implementation of the trait.
 ///
 pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
     let struct_fields = struct_fields(&input)?;
 
-    if !struct_fields.iter().any(has_primary_attr) {
+    if !input.generics.params.is_empty() {
+        return Err(crate::utils::error(
+                    input.generics.span(),
+                    "Generic structs are not supported".into()
+                )
+            )
+    }
+
+    let mut primary = Vec::new();
+    let mut indexes = Vec::new();
+    for field in struct_fields.iter() {
+        match key_attr(field)? {
+            Some(KeyAttr::Primary) => primary.push(field),
+            Some(KeyAttr::Index)   => indexes.push(field),
+            None                   => (),
+        }
+    }
+
+    if primary.is_empty() {
         return Err(crate::utils::error(
                     input.ident.span(),
                     "Struct has no primary key field".into()
                 )
             )
     }
+    if primary.len() > 1 {
+        return Err(crate::utils::error(
+                    primary[1].span(),
+                    "Struct has more than one primary key field".into()
+                )
+            )
+    }
+    let primary = primary[0];
+
+    // the traits are only implemented for types that are used by one key
+    let unique = |ty: &Type| {
+        indexes.iter().filter(|x| &x.ty == ty).count() == 1 && &primary.ty != ty
+    };
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let cache = format_ident!("{}Cache", name);
+
+    let pid = &primary.field_name();
+    let pty = &primary.ty;
+
+    // index of the map inside the tuple, 0 are the entries
+    let index_pos = (1..=indexes.len())
+        .map(syn::Index::from)
+        .collect::<Vec<_>>();
+    let index_names = indexes
+        .iter()
+        .map(|x| x.field_name())
+        .collect::<Vec<_>>();
+    let index_types = indexes
+        .iter()
+        .map(|x| &x.ty)
+        .collect::<Vec<_>>();
+    let index_getters = indexes
+        .iter()
+        .map(|x| format_ident!("get_by_{}", x.field_name()))
+        .collect::<Vec<_>>();
+    let index_docs = indexes
+        .iter()
+        .map(|x| format!("Gets the entry with the given `{}`", x.field_name()))
+        .collect::<Vec<_>>();
+
+    let trait_pos = indexes
+        .iter()
+        .zip(index_pos.iter())
+        .filter(|(x, _)| unique(&x.ty))
+        .map(|(_, x)| x)
+        .collect::<Vec<_>>();
+    let trait_types = indexes
+        .iter()
+        .filter(|x| unique(&x.ty))
+        .map(|x| &x.ty)
+        .collect::<Vec<_>>();
+
+    let cache_name = cache.to_string();
+    let doc = format!(
+        "Cache for [{}] with the primary key `{}`, generated by `#[derive(Get)]`",
+        name,
+        pid
+    );
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Default)]
+        #vis struct #cache {
+            /// Entries by their primary key, followed by one map from every
+            /// indexed field to the primary key
+            entries: tokio::sync::RwLock<(
+                std::collections::HashMap<#pty, #name>,
+                #(std::collections::HashMap<#index_types, #pty>,)*
+            )>,
+        }
+
+        impl #cache {
+            /// Inserts a new entry and updates all indexes. Indexes are
+            /// unique, if another entry has the same value in an indexed
+            /// field, the index points to the new entry afterwards.
+            ///
+            /// # Returns
+            ///
+            /// The previous entry with the same primary key
+            ///
+            pub async fn insert(&self, entry: #name) -> Option<#name> {
+                let mut entries = self.entries.write().await;
+                let previous = entries.0.remove(&entry.#pid);
+                if let Some(ref x) = previous {
+                    // the index may point to another entry by now
+                    #(
+                        if entries.#index_pos.get(&x.#index_names) == Some(&x.#pid) {
+                            entries.#index_pos.remove(&x.#index_names);
+                        }
+                    )*
+                }
+
+                #(entries.#index_pos.insert(entry.#index_names.clone(), entry.#pid.clone());)*
+                entries.0.insert(entry.#pid.clone(), entry);
+                previous
+            }
+
+            /// Removes an entry and its indexes
+            ///
+            /// # Returns
+            ///
+            /// The removed entry
+            ///
+            pub async fn remove(&self, id: &#pty) -> Option<#name> {
+                let mut entries = self.entries.write().await;
+                let previous = entries.0.remove(id);
+                if let Some(ref x) = previous {
+                    // the index may point to another entry by now
+                    #(
+                        if entries.#index_pos.get(&x.#index_names) == Some(&x.#pid) {
+                            entries.#index_pos.remove(&x.#index_names);
+                        }
+                    )*
+                }
+                previous
+            }
+
+            #(
+                #[doc = #index_docs]
+                pub async fn #index_getters(&self, id: &#index_types) -> Option<#name> {
+                    let entries = self.entries.read().await;
+                    entries.#index_pos
+                        .get(id)
+                        .and_then(|x| entries.0.get(x))
+                        .cloned()
+                }
+            )*
+        }
 
-    todo!()
+        #[async_trait::async_trait]
+        impl cachem::Get2<#pty, #name> for #cache {
+            async fn get(&self, id: #pty) -> Option<#name> {
+                self
+                    .entries
+                    .read()
+                    .await
+                    .0
+                    .get(&id)
+                    .cloned()
+            }
+        }
+
+        #(
+            #[async_trait::async_trait]
+            impl cachem::Get2<#trait_types, #name> for #cache {
+                async fn get(&self, id: #trait_types) -> Option<#name> {
+                    let entries = self.entries.read().await;
+                    entries.#trait_pos
+                        .get(&id)
+                        .and_then(|x| entries.0.get(x))
+                        .cloned()
+                }
+            }
+
+            #[async_trait::async_trait]
+            impl cachem::Index<#pty, #trait_types> for #cache {
+                async fn get(&self, id: #trait_types) -> Option<#pty> {
+                    self
+                        .entries
+                        .read()
+                        .await
+                        .#trait_pos
+                        .get(&id)
+                        .cloned()
+                }
+
+                async fn index_set(&self, pid: #pty, sid: #trait_types) {
+                    self
+                        .entries
+                        .write()
+                        .await
+                        .#trait_pos
+                        .insert(sid, pid);
+                }
+            }
+        )*
+
+        #[async_trait::async_trait]
+        impl cachem::Set2<#pty, #name> for #cache {
+            async fn set(&self, id: #pty, val: #name) {
+                let mut val = val;
+                val.#pid = id;
+                self.insert(val).await;
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl cachem::Del for #cache {
+            type Id = #pty;
+
+            async fn del(&self, id: #pty) {
+                self.remove(&id).await;
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl cachem::Key for #cache {
+            type Id = #pty;
+
+            async fn keys(&self) -> Vec<#pty> {
+                self
+                    .entries
+                    .read()
+                    .await
+                    .0
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl cachem::Cache for #cache {
+            fn name(&self) -> String {
+                #cache_name.into()
+            }
+
+            async fn handle(
+                &self,
+                cmd: cachem::Command,
+                req: &mut std::io::Cursor<Vec<u8>>,
+                res: &mut Vec<u8>,
+            ) -> Result<(), cachem::CachemError> {
+                use cachem::Parse;
+
+                match cmd {
+                    cachem::Command::Get => {
+                        let id = <#pty as Parse>::read(req).await?;
+                        cachem::Get2::<#pty, #name>::get(self, id).await.write(res).await
+                    },
+                    cachem::Command::MGet => {
+                        let ids = <Vec<#pty> as Parse>::read(req).await?;
+                        let entries = self.entries.read().await;
+                        ids
+                            .iter()
+                            .map(|x| entries.0.get(x).cloned())
+                            .collect::<Vec<_>>()
+                            .write(res)
+                            .await
+                    },
+                    cachem::Command::Keys => {
+                        cachem::Key::keys(self).await.write(res).await
+                    },
+                    cachem::Command::Exists => {
+                        let id = <#pty as Parse>::read(req).await?;
+                        self.entries.read().await.0.contains_key(&id).write(res).await
+                    },
+                    cachem::Command::MExists => {
+                        let ids = <Vec<#pty> as Parse>::read(req).await?;
+                        let entries = self.entries.read().await;
+                        ids
+                            .iter()
+                            .map(|x| entries.0.contains_key(x))
+                            .collect::<Vec<_>>()
+                            .write(res)
+                            .await
+                    },
+                    cachem::Command::Set => {
+                        let id = <#pty as Parse>::read(req).await?;
+                        let val = <#name as Parse>::read(req).await?;
+                        cachem::Set2::<#pty, #name>::set(self, id, val).await;
+                        Ok(())
+                    },
+                    cachem::Command::MSet => {
+                        let entries = <std::collections::HashMap<#pty, #name> as Parse>::read(req).await?;
+                        cachem::Set2::<#pty, #name>::mset(self, entries).await;
+                        Ok(())
+                    },
+                    cachem::Command::Del => {
+                        let id = <#pty as Parse>::read(req).await?;
+                        cachem::Del::del(self, id).await;
+                        Ok(())
+                    },
+                    cachem::Command::MDel => {
+                        let ids = <Vec<#pty> as Parse>::read(req).await?;
+                        cachem::Del::mdel(self, ids).await;
+                        Ok(())
+                    },
+                    _ => Err(cachem::CachemError::UnknownCommand(cmd.into())),
+                }
+            }
+
+            async fn cnc_listener(&self) {}
+        }
+    })
 }
 
 /// Extracts all fields from the struct.
@@ -45,7 +376,7 @@ pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
 ///
 fn struct_fields(input: &DeriveInput) -> Result<Vec<Field>, TokenStream> {
     match &input.data {
-        Data::Struct(x) => {
+        Data::Struct(x) if matches!(x.fields, Fields::Named(_)) => {
             let fields = x.fields
                 .iter()
                 .cloned()
@@ -60,18 +391,79 @@ fn struct_fields(input: &DeriveInput) -> Result<Vec<Field>, TokenStream> {
     }
 }
 
-/// Checks that the struct has a field marked as primary key
+/// Reads the `#[cachem(..)]` attribute of a field
+///
+/// The attributes `skip`, `default`, `since` and `with` belong to the `Parse` derive
+/// and are ignored.
 ///
 /// # Params
 ///
 /// Single syn::Field
 ///
+/// # Error
+///
+/// Returns an error if the attribute is unknown
+///
 /// # Returns
 ///
-/// `true`  - when the field is marked as primary
-/// `false` - when the field is not marked as primary
+/// `None` if the field has no attribute
 ///
-fn has_primary_attr(field: &Field) -> bool {
-    todo!();
-    !field.attrs.is_empty()
+fn key_attr(field: &Field) -> Result<Option<KeyAttr>, TokenStream> {
+    let mut key = None;
+    for attr in field.attrs.iter().filter(|x| is_cachem_attr(x)) {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(x)) => x,
+            _ => return Err(crate::utils::error(
+                        attr.span(),
+                        "Expected #[cachem(Primary)] or #[cachem(Index)]".into()
+                    )
+                ),
+        };
+
+        for nested in list.nested.iter() {
+            let attr = match nested {
+                NestedMeta::Meta(Meta::Path(x)) if x.is_ident("Primary") => KeyAttr::Primary,
+                NestedMeta::Meta(Meta::Path(x)) if x.is_ident("Index")   => KeyAttr::Index,
+                // attributes of the Parse derive
+                NestedMeta::Meta(Meta::Path(x)) if x.is_ident("skip") || x.is_ident("default") => continue,
+                NestedMeta::Meta(Meta::NameValue(x)) if ["since", "default", "with"].iter().any(|y| x.path.is_ident(y)) => continue,
+                _ => return Err(crate::utils::error(
+                            nested.span(),
+                            "Unknown attribute, expected Primary or Index".into()
+                        )
+                    ),
+            };
+
+            if key.is_some() && key != Some(attr) {
+                return Err(crate::utils::error(
+                            nested.span(),
+                            "A field cannot be primary key and index at the same time".into()
+                        )
+                    )
+            }
+            key = Some(attr);
+        }
+    }
+    Ok(key)
+}
+
+/// Checks if the attribute is a `#[cachem(..)]` attribute
+fn is_cachem_attr(attr: &Attribute) -> bool {
+    attr.path.is_ident("cachem")
+}
+
+/// Helper for getting the name of a named field
+trait FieldName {
+    /// # Returns
+    ///
+    /// Name of the field
+    ///
+    fn field_name(&self) -> syn::Ident;
+}
+
+impl FieldName for Field {
+    fn field_name(&self) -> syn::Ident {
+        // only structs with named fields are supported
+        self.ident.clone().unwrap()
+    }
 }
@@ -0,0 +1,53 @@
+use crate::attrs::CacheAttrs;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+/// Codegen for implementing the [cachem::Key] trait on a cache struct.
+///
+/// Lists and counts the keys of the map given with the option `map`.
+///
+/// # Params
+///
+/// * `input` - Information about the cache struct
+///
+/// # Error
+///
+/// Returns an error if one of the options `map` or `key` is missing
+///
+/// # Returns
+///
+/// [proc_macro2::TokenStream] with the implementation of [cachem::Key]
+///
+pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
+    let attrs = CacheAttrs::parse(&input)?;
+    let name = &input.ident;
+    let map = attrs.map()?;
+    let key = attrs.key()?;
+
+    Ok(quote! {
+        #[async_trait::async_trait]
+        impl cachem::Key for #name {
+            type Id = #key;
+
+            async fn keys(&self) -> Vec<#key> {
+                self
+                    .#map
+                    .read()
+                    .await
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            }
+
+            async fn count(&self) -> u64 {
+                self
+                    .#map
+                    .read()
+                    .await
+                    .len() as u64
+            }
+        }
+    })
+}
@@ -1,60 +1,205 @@
-#![feature(proc_macro_diagnostic)]
-
 //! This crate provides useful derives for different traits of the database.
 
+/// Parsing of the `#[cachem(..)]` attribute on cache structs
+mod attrs;
+/// Implementation of the [cachem::Cache] trait
+mod cache;
+/// Implementation of the [cachem::Del] trait
+mod del;
 /// Implementation of the [cachem::Get2] trait
 mod get;
+/// Implementation of the [cachem::Key] trait
+mod key;
 /// Implementation of the [cachem::Parse] trait
 mod parse;
+/// Implementation of the [cachem::Storage] trait
+mod save;
+/// Implementation of the [cachem::Set2] trait
+mod set;
 /// General purpose functions
 mod utils;
 
 use proc_macro::TokenStream;
-use quote::quote;
 use syn::{DeriveInput, parse_macro_input};
 
-/// Function for deriving the [cachem::Cachem] trait
+/// Function for deriving a cache that implements the [cachem::Get2] trait
+///
+/// Generates the struct `{Name}Cache`. The field marked with
+/// `#[cachem(Primary)]` is the primary key, every field marked with
+/// `#[cachem(Index)]` gets a unique secondary index and the method
+/// `get_by_{field}`. [cachem::Get2] is implemented for the primary key and
+/// for every indexed field whose type is not used by another key.
+/// The cache also implements [cachem::Set2], [cachem::Del], [cachem::Key]
+/// and [cachem::Cache], which keep the indexes up to date, so it can be
+/// added to a server.
 ///
 /// # Params
 ///
-/// * `input` - [proc_macro::TokenStream] of the struct the trait should be
-///             implemented on
+/// * `input` - [proc_macro::TokenStream] of the struct the cache should be
+///   generated for
 ///
 /// # Returns
 ///
-/// [proc_macro::TokenStream] that implements the trait cachem::Cachem
+/// [proc_macro::TokenStream] containing the cache and its implementations
 ///
 #[proc_macro_derive(Get, attributes(cachem))]
-pub fn derive_cachem(input: TokenStream) -> TokenStream {
+pub fn derive_get(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
-    let code_gen = get::code_gen(input);
-    let code_gen = if let Ok(x) = code_gen {
-        x
-    } else if let Err(x) = code_gen {
-        x
-    } else {
-        quote! {}
-    };
-
+    let code_gen = get::code_gen(input).unwrap_or_else(|x| x);
     TokenStream::from(code_gen)
 }
 
 /// Function for deriving the [cachem::Parse] trait
 ///
+/// Every enum variant is prefixed with a tag, which defaults to the position
+/// of the variant. Use `#[cachem(tag = N)]` on a variant to pin its tag.
+///
+/// Structs with `#[cachem(version = N)]` are written with their version and
+/// length, so that fields can be added later. With
+/// `#[cachem(version = N, legacy)]` the struct also gets `read_legacy`,
+/// which reads data that was written before the struct was versioned.
+/// Versioning an existing struct breaks data that was already written,
+/// [cachem::Parse::read] does not fall back to `read_legacy`, so that data
+/// has to be migrated by hand.
+///
 /// # Params
 ///
 /// * `input` - [proc_macro::TokenStream] of the struct the trait should be
-///             implemented on
+///   implemented on
 ///
 /// # Returns
 ///
 /// [proc_macro::TokenStream] that implements the trait [cachem::Parse]
 ///
-#[proc_macro_derive(Parse)]
+#[proc_macro_derive(Parse, attributes(cachem))]
 pub fn derive_parse(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let code_gen = parse::code_gen(input);
+    let code_gen = parse::code_gen(input).unwrap_or_else(|x| x);
+    TokenStream::from(code_gen)
+}
+
+/// Function for deriving the [cachem::Set2] trait on a cache struct
+///
+/// The struct needs the attribute
+/// `#[cachem(map = "entries", key = "u32", value = "MyEntry")]`, where `map`
+/// is a field of the type `tokio::sync::RwLock<HashMap<key, value>>`.
+///
+/// # Params
+///
+/// * `input` - [proc_macro::TokenStream] of the struct the trait should be
+///   implemented on
+///
+/// # Returns
+///
+/// [proc_macro::TokenStream] that implements the trait [cachem::Set2]
+///
+#[proc_macro_derive(Set, attributes(cachem))]
+pub fn derive_set(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let code_gen = set::code_gen(input).unwrap_or_else(|x| x);
     TokenStream::from(code_gen)
 }
 
+/// Function for deriving the [cachem::Del] trait on a cache struct
+///
+/// The struct needs the attribute `#[cachem(map = "entries", key = "u32")]`,
+/// see [derive_set].
+///
+/// # Params
+///
+/// * `input` - [proc_macro::TokenStream] of the struct the trait should be
+///   implemented on
+///
+/// # Returns
+///
+/// [proc_macro::TokenStream] that implements the trait [cachem::Del]
+///
+#[proc_macro_derive(Del, attributes(cachem))]
+pub fn derive_del(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let code_gen = del::code_gen(input).unwrap_or_else(|x| x);
+    TokenStream::from(code_gen)
+}
+
+/// Function for deriving the [cachem::Key] trait on a cache struct
+///
+/// The struct needs the attribute `#[cachem(map = "entries", key = "u32")]`,
+/// see [derive_set].
+///
+/// # Params
+///
+/// * `input` - [proc_macro::TokenStream] of the struct the trait should be
+///   implemented on
+///
+/// # Returns
+///
+/// [proc_macro::TokenStream] that implements the trait [cachem::Key]
+///
+#[proc_macro_derive(Key, attributes(cachem))]
+pub fn derive_key(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let code_gen = key::code_gen(input).unwrap_or_else(|x| x);
+    TokenStream::from(code_gen)
+}
+
+/// Function for deriving the [cachem::Storage] trait on a cache struct
+///
+/// Besides the attributes of [derive_set] the struct needs the attribute
+/// `#[cachem(file = "./my.cachem")]`. The optional attribute
+/// `#[cachem(name = "my_cache")]` sets the name that is stored in the file,
+/// it defaults to the name of the struct.
+///
+/// # Params
+///
+/// * `input` - [proc_macro::TokenStream] of the struct the trait should be
+///   implemented on
+///
+/// # Returns
+///
+/// [proc_macro::TokenStream] that implements the trait [cachem::Storage]
+///
+#[proc_macro_derive(Save, attributes(cachem))]
+pub fn derive_save(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let code_gen = save::code_gen(input).unwrap_or_else(|x| x);
+    TokenStream::from(code_gen)
+}
+
+/// Function for deriving the [cachem::Cache] trait on a cache struct
+///
+/// Takes the same attributes as [derive_save], `file` is optional. Commands
+/// are dispatched to the traits [cachem::Set2], [cachem::Del] and
+/// [cachem::Key], if `file` is set the trait [cachem::Storage] is used for
+/// saving the cache.
+///
+/// # Example
+///
+/// ``` ignore
+/// #[derive(Default, Cache, Set, Del, Key, Save)]
+/// #[cachem(map = "entries", key = "u32", value = "MyEntry", file = "./my.cachem")]
+/// struct MyCache {
+///     entries: RwLock<HashMap<u32, MyEntry>>,
+/// }
+/// ```
+///
+/// # Params
+///
+/// * `input` - [proc_macro::TokenStream] of the struct the trait should be
+///   implemented on
+///
+/// # Returns
+///
+/// [proc_macro::TokenStream] that implements the trait [cachem::Cache]
+///
+#[proc_macro_derive(Cache, attributes(cachem))]
+pub fn derive_cache(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let code_gen = cache::code_gen(input).unwrap_or_else(|x| x);
+    TokenStream::from(code_gen)
+}
@@ -1,28 +1,78 @@
 use proc_macro2::TokenStream;
-use quote::quote;
-use syn::{Data, DeriveInput, Fields, Ident, Type};
+use quote::{format_ident, quote};
+use syn::{Attribute, Data, DataEnum, DeriveInput, Field, Fields, GenericParam, Generics, Ident, Index, Lit, Meta, NestedMeta, Path, parse_quote};
 use syn::spanned::Spanned;
 
 /// Code generator for implementing the [cachem::Parse] trait
 ///
+/// Every field is read and written with `<#ty as cachem::Parse>`, so the
+/// type of a field can be any type that implements [cachem::Parse], for
+/// example `std::collections::HashMap<u32, u32>` or `Option<Vec<T>>`.
+///
+/// Structs with `#[cachem(version = N)]` are written as version, length and
+/// fields. When reading, fields that were added in a later version than the
+/// data, see [FieldAttrs], get their default value and unknown trailing
+/// fields of newer versions are skipped. With `legacy`, data written before
+/// the struct was versioned can be read with `read_legacy`, see
+/// [StructAttrs]. `read_legacy` is never called by [cachem::Parse::read],
+/// data without version has to be migrated by hand.
+///
 /// # Params
 ///
 /// * `input` - Input of the struct where the trait should be implemented only
 ///
+/// # Error
+///
+/// Returns an error if the type or one of its fields is not supported
+///
 /// # Returns
 ///
 /// [proc_macro2::TokenStream] with the implementation of the [cachem::Parse]
 /// trait.
 ///
-pub fn code_gen(input: DeriveInput) -> TokenStream {
+pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
+    let attrs    = struct_attrs(&input.attrs)?;
+    let version  = attrs.version;
     let name = input.ident;
-    let fn_read  = crate::parse::generate_fn_read(&name, &input.data);
-    let fn_write = crate::parse::generate_fn_write(&name, &input.data);
+    let fn_read  = crate::parse::generate_fn_read(&name, &input.data, version)?;
+    let fn_write = crate::parse::generate_fn_write(&name, &input.data, version)?;
 
-    let generics = input.generics;
+    let generics = add_trait_bounds(input.generics);
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    quote! {
+    let fn_read_legacy = match input.data {
+        Data::Struct(ref data) if attrs.legacy => {
+            let read = read_fields(&name, &data.fields, version, false)?;
+            quote! {
+                impl #impl_generics #name #ty_generics #where_clause {
+                    /// Reads data that was written before the struct was
+                    /// versioned, as version `0` without version and length.
+                    /// Fields with `since` or `default` get their default
+                    /// value.
+                    ///
+                    /// `Parse::read` always expects the version, snapshots
+                    /// and the server never call this function. Data without
+                    /// version has to be read with it and written again
+                    /// before it can be loaded with `Parse::read`.
+                    pub async fn read_legacy<B>(
+                        buf: &mut B
+                    ) -> Result<Self, cachem::CachemError>
+                    where
+                        B: tokio::io::AsyncBufRead + tokio::io::AsyncRead + Send + Unpin {
+
+                        #[allow(unused_variables)]
+                        let version = 0u8;
+                        #read
+                    }
+                }
+            }
+        },
+        _ => TokenStream::new(),
+    };
+
+    Ok(quote! {
+        #fn_read_legacy
+
         #[async_trait::async_trait]
         impl #impl_generics cachem::Parse for #name #ty_generics #where_clause {
             async fn read<B>(
@@ -45,7 +95,28 @@ pub fn code_gen(input: DeriveInput) -> TokenStream {
                 Ok(())
             }
         }
+    })
+}
+
+/// Adds the bound [cachem::Parse] to every type parameter
+///
+/// # Params
+///
+/// * `generics` - Generics of the struct
+///
+/// # Returns
+///
+/// Generics with the additional bounds
+///
+fn add_trait_bounds(mut generics: Generics) -> Generics {
+    for param in generics.params.iter_mut() {
+        if let GenericParam::Type(ref mut x) = *param {
+            x.bounds.push(parse_quote!(cachem::Parse));
+            x.bounds.push(parse_quote!(Send));
+            x.bounds.push(parse_quote!(Sync));
+        }
     }
+    generics
 }
 
 /// Generates the code for reading bytes to struct
@@ -54,139 +125,122 @@ pub fn code_gen(input: DeriveInput) -> TokenStream {
 ///
 /// * `struct_name` - Name of the struct
 /// * `data`        - Information about the struct
+/// * `version`     - Version of the struct, if it is versioned
+///
+/// # Error
+///
+/// Returns an error if the type or one of its fields is not supported
 ///
 /// # Returns
 ///
 /// [proc_macro2::TokenStream] with the implementation of the read function.
 ///
-fn generate_fn_read(struct_name: &Ident, data: &Data) -> TokenStream {
+fn generate_fn_read(
+    struct_name: &Ident,
+    data:        &Data,
+    version:     Option<u8>,
+) -> Result<TokenStream, TokenStream> {
     match *data {
         Data::Struct(ref data) => {
-            match data.fields {
-                // Structs with fields
-                Fields::Named(ref fields) => {
-                    let struct_fields = fields
-                        .named
-                        .iter()
-                        .map(|field| {
-
-                        let field_name = &field.ident;
-                        match &field.ty {
-                            Type::Path(_) => {
-                                let datatype = crate::utils::ident_from_type(&field.ty);
-                                quote! {
-                                    #field_name: #datatype::read(buf).await?
-                                }
-                            }
-                            _ => {
-                                crate::utils::error(
-                                    field.ty.span(),
-                                    "The given type is not supported.".into()
-                                )
-                            }
-                        }
-                    });
-                    quote! {
-                        Ok(#struct_name {
-                            #(#struct_fields),*
-                        })
-                    }
-                },
-                // Struct with only one entry
-                Fields::Unnamed(ref fields) => {
-                    let datatype = fields
-                        .unnamed
-                        .iter()
-                        .map(|f| {
-
-                        match &f.ty {
-                            Type::Path(x) => {
-                                let datatype = x.path.segments.first().unwrap().ident.clone();
-                                quote! {
-                                    Ok(Self(#datatype::read(buf).await?))
-                                }
-                            },
-                            _ => {
-                                crate::utils::error(
-                                    f.ty.span(),
-                                    "The given type is not supported.".into()
-                                )
-                            }
-                        }
-                    });
-                    quote! {
-                        #(#datatype)*
-                    }
-                },
+            let read = match data.fields {
                 // Structs without any fields
-                Fields::Unit => quote! {
-                    let _ = u8::read(buf).await?;
+                Fields::Unit => return unversioned(struct_name, version, quote! {
+                    let _ = <u8 as cachem::Parse>::read(buf).await?;
 
                     Ok(Self {})
-                }
+                }),
+                ref fields => read_fields(struct_name, fields, version, true)?,
+            };
+
+            if version.is_none() {
+                return Ok(read);
             }
+
+            Ok(quote! {
+                #[allow(unused_variables)]
+                let version = <u8 as cachem::Parse>::read(buf).await?;
+                let len = <u32 as cachem::Parse>::read(buf).await?;
+
+                // the length is not trusted, the buffer grows while reading
+                let mut body = Vec::new();
+                tokio::io::AsyncReadExt::read_to_end(
+                    &mut tokio::io::AsyncReadExt::take(&mut *buf, len as u64),
+                    &mut body
+                ).await?;
+                if body.len() != len as usize {
+                    return Err(cachem::CachemError::IoError(
+                        std::io::Error::from(std::io::ErrorKind::UnexpectedEof)
+                    ));
+                }
+
+                // everything after the known fields belongs to newer versions
+                // and is skipped
+                #[allow(unused_variables)]
+                let buf = &mut std::io::Cursor::new(body);
+                #read
+            })
         },
         Data::Enum(ref data) => {
-            let fields = data.variants.iter().enumerate().map(|(i, v)| {
-                let i = i as u8;
+            unversioned(struct_name, version, TokenStream::new())?;
+            no_field_attrs(data)?;
+            let tags = variant_tags(data)?;
+            let fields = data.variants.iter().zip(tags).map(|(v, i)| {
                 let field_name = &v.ident;
                 match v.fields {
-                    Fields::Unnamed(ref fields) => {
-                        let datatype = fields
-                            .unnamed
+                    Fields::Named(ref fields) => {
+                        let fields = fields
+                            .named
                             .iter()
                             .map(|f| {
 
-                            match &f.ty {
-                                Type::Path(x) => {
-                                    let datatype = crate::utils::get_datatype_enum(x);
-                                    quote! {
-                                        Self::#field_name(#datatype::read(buf).await?)
-                                    }
-                                },
-                                _ => {
-                                    crate::utils::error(
-                                        f.ty.span(),
-                                        "The given type is not supported.".into()
-                                    )
-                                }
+                            let field_name = &f.ident;
+                            let datatype = &f.ty;
+                            quote! {
+                                #field_name: <#datatype as cachem::Parse>::read(buf).await?
                             }
                         });
                         quote! {
-                            #i => #(#datatype)*
+                            #i => Self::#field_name { #(#fields),* }
+                        }
+                    },
+                    Fields::Unnamed(ref fields) => {
+                        let datatypes = fields
+                            .unnamed
+                            .iter()
+                            .map(|f| &f.ty);
+                        quote! {
+                            #i => Self::#field_name(#(<#datatypes as cachem::Parse>::read(buf).await?),*)
                         }
                     },
                     Fields::Unit => {
                         quote! {
                             #i => {
-                                cachem::EmptyMsg::read(buf).await?;
+                                <cachem::EmptyMsg as cachem::Parse>::read(buf).await?;
                                 Self::#field_name
                             }
                         }
                     }
-                    _ => {
-                        crate::utils::error(
-                            v.fields.span(),
-                            "Only unnamed and unit fields are supported.".into()
-                        )
-                    }
                 }
             });
 
-            quote! {
-                let index = u8::read(buf).await?;
+            let name = struct_name.to_string();
+            Ok(quote! {
+                let index = <u8 as cachem::Parse>::read(buf).await?;
                 let ret = match index {
-                    #(#fields),*,
-                    _ => panic!("Invalid enum field")
+                    #(#fields,)*
+                    _ => return Err(cachem::CachemError::InvalidDiscriminant {
+                        name: #name.into(),
+                        tag:  index,
+                    })
                 };
                 Ok(ret)
-            }
+            })
         },
-        _ => {
-            crate::utils::error(
+        Data::Union(_) => {
+            Err(crate::utils::error(
                 struct_name.span(),
-                "Only structs are supported.".into()
-            )
+                "Only structs and enums are supported.".into()
+            ))
         }
     }
 }
@@ -197,128 +251,626 @@ fn generate_fn_read(struct_name: &Ident, data: &Data) -> TokenStream {
 ///
 /// * `struct_name` - Name of the struct
 /// * `data`        - Information about the struct
+/// * `version`     - Version of the struct, if it is versioned
+///
+/// # Error
+///
+/// Returns an error if the type or one of its fields is not supported
 ///
 /// # Returns
 ///
 /// [proc_macro2::TokenStream] with the implementation of the write function.
 ///
-fn generate_fn_write(struct_name: &Ident, data: &Data) -> TokenStream {
+fn generate_fn_write(
+    struct_name: &Ident,
+    data:        &Data,
+    version:     Option<u8>,
+) -> Result<TokenStream, TokenStream> {
     match *data {
         Data::Struct(ref data) => {
-            match data.fields.clone() {
+            let write = match data.fields {
                 Fields::Named(ref fields) => {
                     let recurse = fields
                         .named
                         .iter()
-                        .map(|f| {
+                        .map(|f| Ok((f, field_attrs(f, version)?)))
+                        .collect::<Result<Vec<_>, TokenStream>>()?
+                        .into_iter()
+                        .filter(|(_, attrs)| !attrs.skip)
+                        .map(|(f, attrs)| {
 
                         let field_name = &f.ident;
-                        match &f.ty {
-                            Type::Path(_) => {
-                                quote! {
-                                    self.#field_name.write(buf).await?;
-                                }
-                            },
-                            _ => {
-                                crate::utils::error(
-                                    f.ty.span(),
-                                    "The given type is not supported.".into()
-                                )
-                            }
-                        }
+                        attrs.write(quote! { self.#field_name })
                     });
                     quote! {
                         #(#recurse)*
                     }
                 },
-                Fields::Unnamed(fields) => {
-                    let datatype = fields
+                Fields::Unnamed(ref fields) => {
+                    let writes = fields
                         .unnamed
                         .iter()
-                        .map(|f| {
-
-                        match &f.ty {
-                            Type::Path(_) => {
-                                quote! {
-                                    self.0.write(buf).await?;
-                                }
-                            },
-                            _ => {
-                                crate::utils::error(
-                                    f.ty.span(),
-                                    "The given type is not supported.".into()
-                                )
-                            }
-                        }
-                    });
+                        .map(|f| field_attrs(f, version))
+                        .collect::<Result<Vec<_>, TokenStream>>()?
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(_, attrs)| !attrs.skip)
+                        .map(|(i, attrs)| {
+                            let index = Index::from(i);
+                            attrs.write(quote! { self.#index })
+                        });
                     quote! {
-                        #(#datatype)*
+                        #(#writes)*
                     }
                 },
-                Fields::Unit => quote! {
-                    0u8.write(buf).await?;
+                Fields::Unit => return unversioned(struct_name, version, quote! {
+                    cachem::Parse::write(&0u8, buf).await?;
+                })
+            };
+
+            let version = match version {
+                Some(x) => x,
+                None    => return Ok(write),
+            };
+
+            Ok(quote! {
+                let mut body = Vec::new();
+                {
+                    #[allow(unused_variables)]
+                    let buf = &mut body;
+                    #write
                 }
-            }
+
+                cachem::Parse::write(&#version, buf).await?;
+                cachem::Parse::write(&(body.len() as u32), buf).await?;
+                tokio::io::AsyncWriteExt::write_all(buf, &body).await?;
+            })
         },
         Data::Enum(ref data) => {
-            let fields = data.variants.iter().enumerate().map(|(i, v)| {
-                let i = i as u8;
+            unversioned(struct_name, version, TokenStream::new())?;
+            no_field_attrs(data)?;
+            let tags = variant_tags(data)?;
+            let fields = data.variants.iter().zip(tags).map(|(v, i)| {
                 let field_name = &v.ident;
                 match v.fields {
-                    Fields::Unnamed(ref fields) => {
-                        let datatype = fields
-                            .unnamed
+                    Fields::Named(ref fields) => {
+                        let names = fields
+                            .named
                             .iter()
-                            .map(|f| {
-
-                            match &f.ty {
-                                Type::Path(_) => {
-                                    quote! {
-                                        Self::#field_name(x) => {
-                                            #i.write(buf).await?;
-                                            x.write(buf).await?;
-                                        }
-                                    }
-                                },
-                                _ => {
-                                    crate::utils::error(
-                                        f.ty.span(),
-                                        "The given type is not supported.".into()
-                                    )
-                                }
+                            .map(|f| &f.ident)
+                            .collect::<Vec<_>>();
+                        let bindings = (0..names.len())
+                            .map(|x| format_ident!("x{}", x))
+                            .collect::<Vec<_>>();
+                        quote! {
+                            Self::#field_name { #(#names: #bindings),* } => {
+                                cachem::Parse::write(&#i, buf).await?;
+                                #(cachem::Parse::write(#bindings, buf).await?;)*
                             }
-                        });
+                        }
+                    },
+                    Fields::Unnamed(ref fields) => {
+                        let bindings = (0..fields.unnamed.len())
+                            .map(|x| format_ident!("x{}", x))
+                            .collect::<Vec<_>>();
                         quote! {
-                            #(#datatype)*
+                            Self::#field_name(#(#bindings),*) => {
+                                cachem::Parse::write(&#i, buf).await?;
+                                #(cachem::Parse::write(#bindings, buf).await?;)*
+                            }
                         }
                     },
                     Fields::Unit => {
                         quote! {
                             Self::#field_name => {
-                                #i.write(buf).await?;
-                                cachem::EmptyMsg::default().write(buf).await?;
+                                cachem::Parse::write(&#i, buf).await?;
+                                cachem::Parse::write(&cachem::EmptyMsg::default(), buf).await?;
                             }
                         }
                     }
-                    _ => {
-                        crate::utils::error(
-                            v.fields.span(),
-                            "Only unnamed fields are supported.".into()
-                        )
-                    }
                 }
             });
 
-            quote! {
+            Ok(quote! {
                 match self {
                     #(#fields),*
                 };
+            })
+        },
+        Data::Union(_) => {
+            Err(crate::utils::error(
+                struct_name.span(),
+                "Only structs and enums are supported.".into()
+            ))
+        }
+    }
+}
+
+/// Gets the tag of every variant of the enum
+///
+/// The tag is written before the fields of a variant. It defaults to the
+/// position of the variant and can be set with `#[cachem(tag = N)]`, so that
+/// variants can be reordered without breaking stored data.
+///
+/// # Params
+///
+/// * `data` - Information about the enum
+///
+/// # Error
+///
+/// Returns an error if an attribute is invalid, a tag does not fit into a
+/// [u8] or two variants have the same tag
+///
+/// # Returns
+///
+/// Tag of every variant, in the order of the variants
+///
+fn variant_tags(data: &DataEnum) -> Result<Vec<u8>, TokenStream> {
+    let mut tags: Vec<(u8, &Ident)> = Vec::new();
+
+    for (i, variant) in data.variants.iter().enumerate() {
+        let mut tag = None;
+        for attr in variant.attrs.iter().filter(|x| x.path.is_ident("cachem")) {
+            let list = match attr.parse_meta() {
+                Ok(Meta::List(x)) => x,
+                _ => return Err(crate::utils::error(
+                            attr.span(),
+                            "Expected #[cachem(tag = N)]".into()
+                        )
+                    ),
+            };
+
+            for nested in list.nested.iter() {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(x)) if x.path.is_ident("tag") => {
+                        let value = match &x.lit {
+                            Lit::Int(x) => x,
+                            _ => return Err(crate::utils::error(
+                                        x.lit.span(),
+                                        "Expected a number".into()
+                                    )
+                                ),
+                        };
+                        let value = value
+                            .base10_parse::<u8>()
+                            .map_err(|_| crate::utils::error(
+                                value.span(),
+                                "The tag must be between 0 and 255".into()
+                            ))?;
+                        tag = Some(value);
+                    },
+                    _ => return Err(crate::utils::error(
+                                nested.span(),
+                                "Unknown attribute, expected tag = N".into()
+                            )
+                        ),
+                }
             }
+        }
+
+        let tag = match tag {
+            Some(x) => x,
+            None if i <= u8::MAX as usize => i as u8,
+            None => return Err(crate::utils::error(
+                        variant.ident.span(),
+                        "Enums with more than 256 variants are not supported".into()
+                    )
+                ),
+        };
+
+        if let Some((_, other)) = tags.iter().find(|(x, _)| *x == tag) {
+            return Err(crate::utils::error(
+                        variant.ident.span(),
+                        format!("The tag {} is already used by the variant `{}`", tag, other)
+                    )
+                )
+        }
+        tags.push((tag, &variant.ident));
+    }
+
+    Ok(tags.into_iter().map(|(x, _)| x).collect::<Vec<_>>())
+}
+
+/// Options of a single field, given with `#[cachem(..)]`
+///
+/// - `skip` - the field is not written and always gets its default value
+/// - `since = N` - the field was added in version `N` of the struct, data of
+///   older versions does not contain it, so it gets its default value
+/// - `default` - if the data ends before the field, the field gets its
+///   default value instead of failing
+/// - `default = "path"` - function that creates the default value, defaults
+///   to [Default::default]
+/// - `with = "module"` - the field is read with `module::read` and written
+///   with `module::write` instead of its [cachem::Parse] implementation,
+///   see `cachem::codec`
+///
+/// `since` and `default` require a versioned struct, because only then the
+/// version and the end of the data are known.
+#[derive(Default)]
+struct FieldAttrs {
+    /// The field is not written or read
+    skip:       bool,
+    /// The field may be missing at the end of the data
+    default:    bool,
+    /// Function that creates the default value
+    default_fn: Option<Path>,
+    /// Version the field was added in
+    since:      Option<u8>,
+    /// Module that reads and writes the field
+    with:       Option<Path>,
+}
+
+impl FieldAttrs {
+    /// # Returns
+    ///
+    /// Code that creates the default value of the field
+    ///
+    fn default_value(&self) -> TokenStream {
+        match self.default_fn.as_ref() {
+            Some(x) => quote! { #x() },
+            None    => quote! { Default::default() },
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Code that writes the given value
+    ///
+    fn write(&self, value: TokenStream) -> TokenStream {
+        match self.with.as_ref() {
+            Some(x) => quote! { #x::write(&#value, buf).await?; },
+            None    => quote! { cachem::Parse::write(&#value, buf).await?; },
+        }
+    }
+}
+
+/// Reads the `#[cachem(..)]` attributes of a field
+///
+/// `Primary` and `Index` belong to the `Get` derive and are ignored.
+///
+/// # Params
+///
+/// * `field`   - Field to read the attributes from
+/// * `version` - Version of the struct, if it is versioned
+///
+/// # Error
+///
+/// Returns an error if an attribute is unknown, invalid or requires a
+/// versioned struct
+///
+fn field_attrs(field: &Field, version: Option<u8>) -> Result<FieldAttrs, TokenStream> {
+    let mut attrs = FieldAttrs::default();
+
+    for attr in field.attrs.iter().filter(|x| x.path.is_ident("cachem")) {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(x)) => x,
+            _ => return Err(crate::utils::error(
+                        attr.span(),
+                        "Expected #[cachem(skip)], #[cachem(default)], #[cachem(since = N)] or #[cachem(with = \"module\")]".into()
+                    )
+                ),
+        };
+
+        for nested in list.nested.iter() {
+            match nested {
+                NestedMeta::Meta(Meta::Path(x)) if x.is_ident("Primary") || x.is_ident("Index") => (),
+                NestedMeta::Meta(Meta::Path(x)) if x.is_ident("skip") => attrs.skip = true,
+                NestedMeta::Meta(Meta::Path(x)) if x.is_ident("default") => attrs.default = true,
+                NestedMeta::Meta(Meta::NameValue(x)) if x.path.is_ident("default") => {
+                    let path = match &x.lit {
+                        Lit::Str(x) => x.parse::<Path>().ok(),
+                        _           => None,
+                    };
+                    let path = path.ok_or_else(|| crate::utils::error(
+                        x.lit.span(),
+                        "Expected the path of a function".into()
+                    ))?;
+                    attrs.default = true;
+                    attrs.default_fn = Some(path);
+                },
+                NestedMeta::Meta(Meta::NameValue(x)) if x.path.is_ident("with") => {
+                    let path = match &x.lit {
+                        Lit::Str(x) => x.parse::<Path>().ok(),
+                        _           => None,
+                    };
+                    let path = path.ok_or_else(|| crate::utils::error(
+                        x.lit.span(),
+                        "Expected the path of a module".into()
+                    ))?;
+                    attrs.with = Some(path);
+                },
+                NestedMeta::Meta(Meta::NameValue(x)) if x.path.is_ident("since") => {
+                    let since = match &x.lit {
+                        Lit::Int(x) => x.base10_parse::<u8>().ok(),
+                        _           => None,
+                    };
+                    let since = since.ok_or_else(|| crate::utils::error(
+                        x.lit.span(),
+                        "Expected a version between 0 and 255".into()
+                    ))?;
+
+                    match version {
+                        Some(x) if since > x => return Err(crate::utils::error(
+                                    nested.span(),
+                                    format!("The field is newer than the struct version {}", x)
+                                )
+                            ),
+                        Some(_) => (),
+                        None => return Err(crate::utils::error(
+                                    nested.span(),
+                                    "since requires #[cachem(version = N)] on the struct".into()
+                                )
+                            ),
+                    }
+                    attrs.since = Some(since);
+                },
+                _ => return Err(crate::utils::error(
+                            nested.span(),
+                            "Unknown attribute, expected skip, default, since = N or with = \"module\"".into()
+                        )
+                    ),
+            }
+        }
+
+        if attrs.skip && (attrs.since.is_some() || attrs.with.is_some()) {
+            return Err(crate::utils::error(
+                        attr.span(),
+                        "skip cannot be combined with since or with".into()
+                    )
+                )
+        }
+        if attrs.default && !attrs.skip && version.is_none() {
+            return Err(crate::utils::error(
+                        attr.span(),
+                        "default requires #[cachem(version = N)] on the struct".into()
+                    )
+                )
+        }
+    }
+
+    Ok(attrs)
+}
+
+/// Generates the code for reading a single field of a struct
+///
+/// # Params
+///
+/// * `field`   - Field that should be read
+/// * `version` - Version of the struct, if it is versioned
+/// * `framed`  - The end of the data is known, so fields with `default`
+///   can check if they are part of it, otherwise they get their default
+///   value
+///
+/// # Error
+///
+/// Returns an error if the attributes of the field are invalid
+///
+/// # Returns
+///
+/// Expression that evaluates to the value of the field
+///
+fn read_field(
+    field:   &Field,
+    version: Option<u8>,
+    framed:  bool,
+) -> Result<TokenStream, TokenStream> {
+    let attrs = field_attrs(field, version)?;
+    let datatype = &field.ty;
+    let read = match attrs.with.as_ref() {
+        Some(x) => quote! { #x::read(buf).await? },
+        None    => quote! { <#datatype as cachem::Parse>::read(buf).await? },
+    };
+    let default = attrs.default_value();
+
+    // without the end of the data it is unknown if the field is part of it
+    if attrs.skip || (attrs.default && !framed) {
+        return Ok(default);
+    }
+
+    let mut conditions = Vec::new();
+    if let Some(since) = attrs.since {
+        conditions.push(quote! { version >= #since });
+    }
+    if attrs.default && framed {
+        conditions.push(quote! { (buf.position() as usize) < buf.get_ref().len() });
+    }
+
+    if conditions.is_empty() {
+        Ok(read)
+    } else {
+        Ok(quote! {
+            if #(#conditions)&&* {
+                #read
+            } else {
+                #default
+            }
+        })
+    }
+}
+
+/// Generates the code for reading all fields of a struct with fields
+///
+/// # Params
+///
+/// * `struct_name` - Name of the struct
+/// * `fields`      - Fields of the struct
+/// * `version`     - Version of the struct, if it is versioned
+/// * `framed`      - The fields are read from the body of a versioned
+///   struct, so the end of the data is known
+///
+/// # Error
+///
+/// Returns an error if the attributes of a field are invalid
+///
+/// # Returns
+///
+/// Code that evaluates to the struct
+///
+fn read_fields(
+    struct_name: &Ident,
+    fields:      &Fields,
+    version:     Option<u8>,
+    framed:      bool,
+) -> Result<TokenStream, TokenStream> {
+    match fields {
+        Fields::Named(ref fields) => {
+            let struct_fields = fields
+                .named
+                .iter()
+                .map(|field| {
+
+                let field_name = &field.ident;
+                let read = read_field(field, version, framed)?;
+                Ok(quote! {
+                    #field_name: #read
+                })
+            })
+            .collect::<Result<Vec<_>, TokenStream>>()?;
+            Ok(quote! {
+                Ok(#struct_name {
+                    #(#struct_fields),*
+                })
+            })
         },
         _ => {
-            struct_name.span().unwrap().error("Only structs and enums are supported.").emit();
-            TokenStream::new()
+            let struct_fields = fields
+                .iter()
+                .map(|field| read_field(field, version, framed))
+                .collect::<Result<Vec<_>, TokenStream>>()?;
+            Ok(quote! {
+                Ok(Self(#(#struct_fields),*))
+            })
+        },
+    }
+}
+
+/// Options of the struct, given with `#[cachem(..)]`
+///
+/// - `version = N` - the struct is written as version, length and fields
+/// - `legacy` - generates `read_legacy`, which reads data that was written
+///   before the struct was versioned, fields with `since` or `default` are
+///   not part of that data. It is only called by hand, [cachem::Parse::read]
+///   does not fall back to it
+#[derive(Default)]
+struct StructAttrs {
+    /// Version of the struct, if it is versioned
+    version: Option<u8>,
+    /// Data without version and length can be read
+    legacy:  bool,
+}
+
+/// Reads the options of the struct, given with
+/// `#[cachem(version = N, legacy)]`
+///
+/// # Params
+///
+/// * `attrs` - Attributes of the struct
+///
+/// # Error
+///
+/// Returns an error if an attribute is unknown, the version does not fit
+/// into a [u8] or `legacy` is used without a version
+///
+fn struct_attrs(attrs: &[Attribute]) -> Result<StructAttrs, TokenStream> {
+    let mut struct_attrs = StructAttrs::default();
+
+    for attr in attrs.iter().filter(|x| x.path.is_ident("cachem")) {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(x)) => x,
+            _ => return Err(crate::utils::error(
+                        attr.span(),
+                        "Expected #[cachem(version = N)]".into()
+                    )
+                ),
+        };
+
+        for nested in list.nested.iter() {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(x)) if x.path.is_ident("version") => {
+                    let value = match &x.lit {
+                        Lit::Int(x) => x.base10_parse::<u8>().ok(),
+                        _           => None,
+                    };
+                    let value = value.ok_or_else(|| crate::utils::error(
+                        x.lit.span(),
+                        "Expected a version between 0 and 255".into()
+                    ))?;
+                    struct_attrs.version = Some(value);
+                },
+                NestedMeta::Meta(Meta::Path(x)) if x.is_ident("legacy") => {
+                    struct_attrs.legacy = true;
+                },
+                _ => return Err(crate::utils::error(
+                            nested.span(),
+                            "Unknown attribute, expected version = N or legacy".into()
+                        )
+                    ),
+            }
+        }
+
+        if struct_attrs.legacy && struct_attrs.version.is_none() {
+            return Err(crate::utils::error(
+                        attr.span(),
+                        "legacy requires #[cachem(version = N)] on the struct".into()
+                    )
+                )
         }
     }
+
+    Ok(struct_attrs)
 }
 
+/// Returns the given code, or an error if the type is versioned
+///
+/// # Params
+///
+/// * `name`    - Name of the type
+/// * `version` - Version of the type, if it is versioned
+/// * `code`    - Generated code
+///
+/// # Error
+///
+/// Returns an error if the type is versioned, only structs with fields can
+/// be versioned
+///
+fn unversioned(
+    name:    &Ident,
+    version: Option<u8>,
+    code:    TokenStream,
+) -> Result<TokenStream, TokenStream> {
+    if version.is_some() {
+        return Err(crate::utils::error(
+                    name.span(),
+                    "Only structs with fields can be versioned".into()
+                )
+            )
+    }
+    Ok(code)
+}
+
+/// Checks that no field of the enum has a `#[cachem(..)]` attribute
+///
+/// # Params
+///
+/// * `data` - Information about the enum
+///
+/// # Error
+///
+/// Returns an error if a field has an attribute
+///
+fn no_field_attrs(data: &DataEnum) -> Result<(), TokenStream> {
+    let attr = data.variants
+        .iter()
+        .flat_map(|x| x.fields.iter())
+        .flat_map(|x| x.attrs.iter())
+        .find(|x| x.path.is_ident("cachem"));
+
+    match attr {
+        Some(x) => Err(crate::utils::error(
+                    x.span(),
+                    "Field attributes are not supported on enum variants".into()
+                )
+            ),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,70 @@
+use crate::attrs::CacheAttrs;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+/// Codegen for implementing the [cachem::Storage] trait on a cache struct.
+///
+/// The map is stored as [cachem::SnapshotFile] in the file given with the
+/// option `file`.
+///
+/// # Params
+///
+/// * `input` - Information about the cache struct
+///
+/// # Error
+///
+/// Returns an error if one of the options `map`, `key`, `value` or `file`
+/// is missing
+///
+/// # Returns
+///
+/// [proc_macro2::TokenStream] with the implementation of [cachem::Storage]
+///
+pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
+    let attrs = CacheAttrs::parse(&input)?;
+    let name = &input.ident;
+    let map = attrs.map()?;
+    let key = attrs.key()?;
+    let value = attrs.value()?;
+    let file = attrs.file()?;
+    let cache_name = attrs.name();
+
+    Ok(quote! {
+        #[async_trait::async_trait]
+        impl cachem::Storage for #name {
+            fn file() -> &'static str {
+                #file
+            }
+
+            fn name() -> &'static str {
+                #cache_name
+            }
+
+            async fn load<B>(
+                &self,
+                buf: &mut B
+            ) -> Result<(), cachem::CachemError>
+            where
+                B: tokio::io::AsyncBufRead + tokio::io::AsyncRead + Send + Unpin {
+
+                let entries = <std::collections::HashMap<#key, #value> as cachem::Parse>::read(buf).await?;
+                *self.#map.write().await = entries;
+                Ok(())
+            }
+
+            async fn save<B>(
+                &self,
+                buf: &mut B
+            ) -> Result<u32, cachem::CachemError>
+            where
+                B: tokio::io::AsyncWrite + Send + Unpin {
+
+                let entries = self.#map.read().await;
+                cachem::Parse::write(&*entries, buf).await?;
+                Ok(entries.len() as u32)
+            }
+        }
+    })
+}
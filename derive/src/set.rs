@@ -0,0 +1,51 @@
+use crate::attrs::CacheAttrs;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+/// Codegen for implementing the [cachem::Set2] trait on a cache struct.
+///
+/// `set` inserts into the map given with the option `map`, `mset` extends
+/// it with all entries.
+///
+/// # Params
+///
+/// * `input` - Information about the cache struct
+///
+/// # Error
+///
+/// Returns an error if one of the options `map`, `key` or `value` is missing
+///
+/// # Returns
+///
+/// [proc_macro2::TokenStream] with the implementation of [cachem::Set2]
+///
+pub fn code_gen(input: DeriveInput) -> Result<TokenStream, TokenStream> {
+    let attrs = CacheAttrs::parse(&input)?;
+    let name = &input.ident;
+    let map = attrs.map()?;
+    let key = attrs.key()?;
+    let value = attrs.value()?;
+
+    Ok(quote! {
+        #[async_trait::async_trait]
+        impl cachem::Set2<#key, #value> for #name {
+            async fn set(&self, id: #key, val: #value) {
+                self
+                    .#map
+                    .write()
+                    .await
+                    .insert(id, val);
+            }
+
+            async fn mset(&self, entries: std::collections::HashMap<#key, #value>) {
+                self
+                    .#map
+                    .write()
+                    .await
+                    .extend(entries);
+            }
+        }
+    })
+}
@@ -1,7 +1,6 @@
 //! This file contains some general purpose functions
 
 use proc_macro2::{Span, TokenStream};
-use syn::{Ident, Type, TypePath};
 
 /// Generates a new [proc_macro2::TokenStream] error
 ///
@@ -12,61 +11,9 @@ use syn::{Ident, Type, TypePath};
 ///
 /// # Returns
 ///
-/// [proc_macro2::TokenStream] error
+/// [proc_macro2::TokenStream] containing a `compile_error!` that points to
+/// the given span
 ///
 pub fn error(span: Span, msg: String) -> TokenStream {
-    span
-        .unwrap()
-        .error(msg)
-        .emit();
-    TokenStream::new()
-}
-
-/// Extracts the datatype from a [syn::Type]
-///
-/// # Params
-///
-/// * `typ` - Type where the datatype should be extracted from
-///
-/// # Returns
-///
-/// Datatype of the given [syn::Type]
-///
-pub fn ident_from_type(
-    typ: &Type
-) -> Ident {
-    match typ {
-        Type::Path(x) => {
-            x
-                .path
-                .segments
-                .first()
-                .unwrap()
-                .ident
-                .clone()
-        },
-        _ => panic!("Invalid datatype, {:?}", &typ)
-    }
-}
-
-/// Extracts the datatype from the given [syn::TypePath]
-///
-/// # Params
-///
-/// * `type_path` - Path where the datatype should be extracted from
-///
-/// # Returns
-///
-/// Datatype from the [sync::TypePath]
-///
-pub fn get_datatype_enum(
-    type_path: &TypePath
-) -> Ident {
-    type_path
-        .path
-        .segments
-        .first()
-        .unwrap()
-        .ident
-        .clone()
+    syn::Error::new(span, msg).to_compile_error()
 }
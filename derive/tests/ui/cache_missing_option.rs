@@ -0,0 +1,11 @@
+use cachem_derive::Set;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+#[derive(Set)]
+#[cachem(map = "entries", key = "u32")]
+struct MissingValue {
+    entries: RwLock<HashMap<u32, u32>>,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use cachem_derive::Key;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+#[derive(Key)]
+#[cachem(map = "values", key = "u32")]
+struct UnknownField {
+    entries: RwLock<HashMap<u32, u32>>,
+}
+
+fn main() {}
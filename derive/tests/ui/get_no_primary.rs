@@ -0,0 +1,8 @@
+use cachem_derive::Get;
+
+#[derive(Get)]
+struct NoPrimary {
+    id: u32,
+}
+
+fn main() {}
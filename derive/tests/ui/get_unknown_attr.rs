@@ -0,0 +1,11 @@
+use cachem_derive::Get;
+
+#[derive(Get)]
+struct UnknownAttr {
+    #[cachem(Primary)]
+    id: u32,
+    #[cachem(Unique)]
+    name: u64,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use cachem_derive::Parse;
+
+#[derive(Parse)]
+enum DuplicateTag {
+    #[cachem(tag = 1)]
+    First,
+    Second,
+}
+
+fn main() {}
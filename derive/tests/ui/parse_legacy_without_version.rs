@@ -0,0 +1,9 @@
+use cachem_derive::Parse;
+
+#[derive(Parse)]
+#[cachem(legacy)]
+struct LegacyWithoutVersion {
+    id: u32,
+}
+
+fn main() {}
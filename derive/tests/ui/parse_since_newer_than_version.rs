@@ -0,0 +1,11 @@
+use cachem_derive::Parse;
+
+#[derive(Parse)]
+#[cachem(version = 1)]
+struct TooNew {
+    id: u32,
+    #[cachem(since = 2)]
+    name: String,
+}
+
+fn main() {}
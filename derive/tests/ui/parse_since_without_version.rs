@@ -0,0 +1,10 @@
+use cachem_derive::Parse;
+
+#[derive(Parse)]
+struct Unversioned {
+    id: u32,
+    #[cachem(since = 2)]
+    name: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cachem_derive::Parse;
+
+#[derive(Parse)]
+enum OutOfRange {
+    #[cachem(tag = 256)]
+    First,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cachem_derive::Parse;
+
+#[derive(Parse)]
+union Unsupported {
+    a: u32,
+    b: f32,
+}
+
+fn main() {}
@@ -1,11 +1,11 @@
 use async_trait::*;
 use cachem::ConnectionGuard;
-use cachem::{Command, Get2, Key, Set, Cache};
+use cachem::{CachemError, Command, Get2, Key, Set, Cache};
 use cachem::{Index, Parse};
 use std::collections::HashMap;
-use tokio::net::TcpStream;
+use std::io::Cursor;
 use tokio::sync::RwLock;
-use tokio::{io::BufStream, sync::watch::Receiver};
+use tokio::sync::watch::Receiver;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum CacheName {
@@ -26,13 +26,13 @@ impl From<u8> for CacheName {
     }
 }
 
-impl Into<u8> for CacheName {
-    fn into(self) -> u8 {
-        match self {
-            Self::A => 0,
-            Self::B => 1,
-            Self::CommandAndControl => 255,
-            Self::Invalid => 255
+impl From<CacheName> for u8 {
+    fn from(x: CacheName) -> Self {
+        match x {
+            CacheName::A => 0,
+            CacheName::B => 1,
+            CacheName::CommandAndControl => 255,
+            CacheName::Invalid => 255
         }
     }
 }
@@ -41,14 +41,19 @@ pub struct ExampleDbCli;
 
 #[derive(Clone, Copy, Debug, Parse)]
 pub struct ACacheVal {
+    /// First value of the entry
     field_a: u32,
+    /// Second value of the entry
     field_b: u64,
 }
 
 pub struct ACache {
+    /// Holds all entries
     cache: RwLock<HashMap<u32, ACacheVal>>,
+    /// Receives commands from the command and control cache
     cnc:   Receiver<Command>,
 
+    /// Maps a secondary id to the key of the entry
     index: RwLock<HashMap<u64, u32>>,
 }
 
@@ -74,32 +79,36 @@ impl Cache for ACache {
         "ACache".into()
     }
 
-    async fn handle(&self, cmd: Command, buf_socket: &mut BufStream<TcpStream>) {
+    async fn handle(
+        &self,
+        cmd: Command,
+        req: &mut Cursor<Vec<u8>>,
+        res: &mut Vec<u8>,
+    ) -> Result<(), CachemError> {
         match cmd {
             Command::Get => {
-                let val = u32::read(buf_socket).await.unwrap();
+                let val = u32::read(req).await?;
                 print!("id = {:?} -> ", val);
-                let res = <ACache as Get2<u32, ACacheVal>>::get(&self, val).await;
-                println!("res = {:?}", res);
+                let val = <ACache as Get2<u32, ACacheVal>>::get(&self, val).await;
+                println!("res = {:?}", val);
 
-                res.write(buf_socket).await.unwrap();
+                val.write(res).await?;
             },
             Command::Keys => {
-                let res = self.keys().await;
-                println!("res = {:?}", res);
-                for x in res {
-                    x.write(buf_socket).await.unwrap();
-                }
+                let val = self.keys().await;
+                println!("res = {:?}", val);
+                val.write(res).await?;
             },
             Command::Set => {
-                let idx = u32::read(buf_socket).await.unwrap();
-                let val = ACacheVal::read(buf_socket).await.unwrap();
+                let idx = u32::read(req).await?;
+                let val = ACacheVal::read(req).await?;
 
                 self.set(idx, val).await;
                 println!("res = OK");
             },
-            _ => panic!("Unknown Command")
+            _ => return Err(CachemError::UnknownCommand(cmd.into()))
         };
+        Ok(())
     }
 
     async fn cnc_listener(&self) {
@@ -162,19 +171,19 @@ impl Index<u32, u64> for ACache {
 
 #[async_trait]
 impl Key for ACache {
-    type Idx = u32;
+    type Id = u32;
 
-    async fn keys(&self) -> Vec<Self::Idx> {
-        self.cache.read().await.keys().map(|x| *x).collect()
+    async fn keys(&self) -> Vec<Self::Id> {
+        self.cache.read().await.keys().copied().collect()
     }
 }
 
 #[async_trait]
 impl Set for ACache {
-    type Idx = u32;
+    type Id = u32;
     type Val = ACacheVal;
 
-    async fn set(&self, idx: Self::Idx, val: Self::Val) {
+    async fn set(&self, idx: Self::Id, val: Self::Val) {
         self.cache.write().await.insert(idx, val);
     }
 }
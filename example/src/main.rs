@@ -1,6 +1,8 @@
+/// Structs that are only used to test the derives
+#[cfg(test)]
 mod sample_structs;
 
-use cachem::Server;
+use cachem::{MapCache, Server};
 use cachem_example::*;
 use std::sync::Arc;
 
@@ -8,10 +10,13 @@ use std::sync::Arc;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (cnc_rec, mut server) = Server::new("0.0.0.0:55555".into());
 
-    server.add(CacheName::A, Arc::new(Box::new(ACache::new(cnc_rec))));
+    server.add(CacheName::A, Arc::new(ACache::new(cnc_rec)));
+    server.add(CacheName::B, Arc::new(MapCache::<u32, ACacheVal>::default()));
 
     server.listen_cnc();
-    server.listen_tcp().await;
+    server.run_until(async {
+        let _ = tokio::signal::ctrl_c().await;
+    }).await?;
 
     Ok(())
 }
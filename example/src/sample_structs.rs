@@ -1,10 +1,14 @@
+// most structs are never constructed, they only check that the derives
+// compile
+#![allow(dead_code)]
+
 use cachem::*;
 use std::collections::HashMap;
 
 #[derive(Default, Parse)]
 struct Example1;
 
-#[derive(Debug, Default, Parse, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, Parse, PartialEq, Eq)]
 struct Example2(pub u32);
 
 #[derive(Debug, Default, Parse, PartialEq, Eq)]
@@ -57,14 +61,136 @@ struct Example13 {
     field: u16
 }
 
-#[derive(Get, Debug)]
+#[derive(Clone, Debug, Get, Parse, PartialEq, Eq)]
 struct Example14 {
     #[cachem(Primary)]
     primary: u32,
+    #[cachem(Index)]
     secondary: u64,
     non_index: u32,
 }
 
+#[derive(Default, Cache, Set, Del, Key, Save)]
+#[cachem(map = "entries", key = "u32", value = "Example2", file = "./example15.cachem")]
+struct Example15 {
+    entries: tokio::sync::RwLock<HashMap<u32, Example2>>,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example16 {
+    map: std::collections::HashMap<u32, u32>,
+    nested: crate::sample_structs::Example2,
+    optional: Option<Vec<u32>>,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example17(u32, u64, String);
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+enum Example18 {
+    Pair(u32, String),
+    Single(Option<u8>),
+    Empty,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example19<T> {
+    values: Option<Vec<T>>,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+enum Example20 {
+    #[cachem(tag = 5)]
+    Named { id: u32, name: String },
+    #[cachem(tag = 3)]
+    Unnamed(u32),
+    Empty,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+#[cachem(version = 1)]
+struct Example21V1 {
+    id: u32,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+#[cachem(version = 2)]
+struct Example21V2 {
+    id: u32,
+    #[cachem(since = 2)]
+    name: String,
+    #[cachem(skip)]
+    cached: u64,
+    #[cachem(default = "default_limit")]
+    limit: u32,
+}
+
+fn default_limit() -> u32 {
+    10u32
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example22 {
+    id: u32,
+    #[cachem(skip)]
+    cached: Vec<u32>,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example23 {
+    #[cachem(with = "cachem::codec::varint")]
+    big: u64,
+    #[cachem(with = "cachem::codec::zigzag")]
+    signed: i32,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example24(#[cachem(with = "cachem::codec::bytes")] Vec<u8>);
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example25 {
+    id:      VarU32,
+    offset:  VarI64,
+    entries: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Get, Parse, PartialEq, Eq)]
+struct Example26 {
+    #[cachem(Primary)]
+    id: u32,
+    #[cachem(Index)]
+    group: u32,
+    #[cachem(Index)]
+    name: String,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+struct Example27V0 {
+    id: u32,
+    values: Vec<u8>,
+}
+
+#[derive(Debug, Parse, PartialEq, Eq)]
+#[cachem(version = 1, legacy)]
+struct Example27V1 {
+    id: u32,
+    values: Vec<u8>,
+    #[cachem(since = 1)]
+    name: String,
+    #[cachem(default)]
+    limit: u32,
+}
+
+#[derive(Clone, Debug, Get, Parse, PartialEq, Eq)]
+struct Example28 {
+    #[cachem(Primary)]
+    id: u32,
+    #[cachem(Index)]
+    parent: u64,
+    #[cachem(Index)]
+    child: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -75,7 +201,7 @@ mod tests {
     #[tokio::test]
     async fn test_example_1_w() {
         let mut buf = BufStream::new(Cursor::new(Vec::new()));
-        let e = Example1::default();
+        let e = Example1;
         e.write(&mut buf).await.unwrap();
         buf.flush().await.unwrap();
 
@@ -125,4 +251,381 @@ mod tests {
         let expected = vec![0, 0, 0, 4, 210];
         assert_eq!(is, expected);
     }
+
+    #[tokio::test]
+    async fn test_example_14_get() {
+        let cache = Example14Cache::default();
+        let e = Example14 { primary: 1u32, secondary: 10u64, non_index: 0u32 };
+        assert_eq!(cache.insert(e.clone()).await, None);
+
+        assert_eq!(Get2::<u32, Example14>::get(&cache, 1u32).await, Some(e.clone()));
+        assert_eq!(Get2::<u64, Example14>::get(&cache, 10u64).await, Some(e));
+        assert_eq!(Get2::<u32, Example14>::get(&cache, 2u32).await, None);
+        assert_eq!(Index::<u32, u64>::get(&cache, 10u64).await, Some(1u32));
+    }
+
+    #[tokio::test]
+    async fn test_example_14_index_update() {
+        let cache = Example14Cache::default();
+        let e = Example14 { primary: 1u32, secondary: 10u64, non_index: 0u32 };
+        cache.insert(e.clone()).await;
+
+        let updated = Example14 { primary: 1u32, secondary: 20u64, non_index: 0u32 };
+        assert_eq!(cache.insert(updated.clone()).await, Some(e));
+        assert_eq!(Get2::<u64, Example14>::get(&cache, 10u64).await, None);
+        assert_eq!(Get2::<u64, Example14>::get(&cache, 20u64).await, Some(updated.clone()));
+
+        assert_eq!(cache.remove(&1u32).await, Some(updated));
+        assert_eq!(Get2::<u64, Example14>::get(&cache, 20u64).await, None);
+        assert_eq!(Get2::<u32, Example14>::mget(&cache, vec![1u32]).await, vec![None]);
+    }
+
+    #[tokio::test]
+    async fn test_example_14_shared_index() {
+        let cache = Example14Cache::default();
+        let a = Example14 { primary: 1u32, secondary: 10u64, non_index: 0u32 };
+        let b = Example14 { primary: 2u32, secondary: 10u64, non_index: 0u32 };
+        cache.insert(a.clone()).await;
+        cache.insert(b.clone()).await;
+
+        // the index points to the entry inserted last
+        assert_eq!(cache.get_by_secondary(&10u64).await, Some(b.clone()));
+
+        // removing the other entry keeps the index
+        assert_eq!(cache.remove(&1u32).await, Some(a));
+        assert_eq!(cache.get_by_secondary(&10u64).await, Some(b.clone()));
+
+        assert_eq!(cache.remove(&2u32).await, Some(b));
+        assert_eq!(cache.get_by_secondary(&10u64).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_example_14_handle() {
+        let cache = Example14Cache::default();
+        assert_eq!(Cache::name(&cache), "Example14Cache");
+
+        // the entry is stored under the id of the request
+        let mut req = Vec::new();
+        1u32.write(&mut req).await.unwrap();
+        Example14 { primary: 5u32, secondary: 10u64, non_index: 0u32 }.write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache.handle(Command::Set, &mut Cursor::new(req), &mut res).await.unwrap();
+
+        let e = Example14 { primary: 1u32, secondary: 10u64, non_index: 0u32 };
+        let mut req = Vec::new();
+        1u32.write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache.handle(Command::Get, &mut Cursor::new(req), &mut res).await.unwrap();
+        let is = Option::<Example14>::read(&mut Cursor::new(res)).await.unwrap();
+        assert_eq!(is, Some(e.clone()));
+        assert_eq!(cache.get_by_secondary(&10u64).await, Some(e));
+
+        let mut req = Vec::new();
+        vec![1u32, 5u32].write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache.handle(Command::MExists, &mut Cursor::new(req), &mut res).await.unwrap();
+        let is = Vec::<bool>::read(&mut Cursor::new(res)).await.unwrap();
+        assert_eq!(is, vec![true, false]);
+
+        // a delete also removes the index
+        let mut req = Vec::new();
+        1u32.write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache.handle(Command::Del, &mut Cursor::new(req), &mut res).await.unwrap();
+        assert_eq!(cache.get_by_secondary(&10u64).await, None);
+        assert_eq!(Key::count(&cache).await, 0u64);
+
+        let mut res = Vec::new();
+        assert!(cache.handle(Command::Ttl, &mut Cursor::new(Vec::new()), &mut res).await.is_err());
+
+        // the cache can be served
+        let (_, mut server) = Server::new("127.0.0.1:0".into());
+        server.add(0u8, std::sync::Arc::new(cache));
+    }
+
+    #[tokio::test]
+    async fn test_example_15_handle() {
+        let cache = Example15::default();
+        assert_eq!(Cache::name(&cache), "Example15");
+
+        let mut req = Vec::new();
+        1u32.write(&mut req).await.unwrap();
+        Example2(10u32).write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache.handle(Command::Set, &mut Cursor::new(req), &mut res).await.unwrap();
+
+        let mut req = Vec::new();
+        1u32.write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache.handle(Command::Get, &mut Cursor::new(req), &mut res).await.unwrap();
+        let is = Option::<Example2>::read(&mut Cursor::new(res)).await.unwrap();
+        assert_eq!(is, Some(Example2(10u32)));
+
+        let mut res = Vec::new();
+        cache.handle(Command::Keys, &mut Cursor::new(Vec::new()), &mut res).await.unwrap();
+        let is = Vec::<u32>::read(&mut Cursor::new(res)).await.unwrap();
+        assert_eq!(is, vec![1u32]);
+
+        let mut req = Vec::new();
+        vec![1u32].write(&mut req).await.unwrap();
+        let mut res = Vec::new();
+        cache.handle(Command::MDel, &mut Cursor::new(req), &mut res).await.unwrap();
+        assert_eq!(Key::count(&cache).await, 0u64);
+
+        let mut res = Vec::new();
+        assert!(cache.handle(Command::Ttl, &mut Cursor::new(Vec::new()), &mut res).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_example_15_storage() {
+        let cache = Example15::default();
+        Set2::set(&cache, 1u32, Example2(10u32)).await;
+        assert_eq!(<Example15 as Storage>::name(), "Example15");
+        assert_eq!(<Example15 as Storage>::file(), "./example15.cachem");
+
+        let mut buf = Vec::new();
+        assert_eq!(Storage::save(&cache, &mut buf).await.unwrap(), 1u32);
+
+        let loaded = Example15::default();
+        loaded.load(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(loaded.entries.read().await.get(&1u32), Some(&Example2(10u32)));
+    }
+
+    #[tokio::test]
+    async fn test_example_16_rw() {
+        let mut map = HashMap::new();
+        map.insert(1u32, 2u32);
+        let e = Example16 {
+            map,
+            nested: Example2(3u32),
+            optional: Some(vec![4u32]),
+        };
+
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+        let is = Example16::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, e);
+    }
+
+    #[tokio::test]
+    async fn test_example_17_w() {
+        let mut buf = Vec::new();
+        Example17(1u32, 2u64, "a".into()).write(&mut buf).await.unwrap();
+
+        let mut expected = Vec::new();
+        1u32.write(&mut expected).await.unwrap();
+        2u64.write(&mut expected).await.unwrap();
+        String::from("a").write(&mut expected).await.unwrap();
+        assert_eq!(buf, expected);
+
+        let is = Example17::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, Example17(1u32, 2u64, "a".into()));
+    }
+
+    #[tokio::test]
+    async fn test_example_18_rw() {
+        for e in [
+            Example18::Pair(1u32, "a".into()),
+            Example18::Single(Some(2u8)),
+            Example18::Empty,
+        ] {
+            let mut buf = Vec::new();
+            e.write(&mut buf).await.unwrap();
+            let is = Example18::read(&mut Cursor::new(buf)).await.unwrap();
+            assert_eq!(is, e);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_example_19_rw() {
+        let e = Example19 { values: Some(vec![Example2(1u32)]) };
+
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+        let is = Example19::<Example2>::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, e);
+    }
+
+    #[tokio::test]
+    async fn test_example_20_rw() {
+        let e = Example20::Named { id: 1u32, name: "a".into() };
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+        assert_eq!(buf[0], 5u8);
+        let is = Example20::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, e);
+
+        let mut buf = Vec::new();
+        Example20::Unnamed(1u32).write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![3, 0, 0, 0, 1]);
+
+        // variants without a tag keep their position
+        let mut buf = Vec::new();
+        Example20::Empty.write(&mut buf).await.unwrap();
+        assert_eq!(buf[0], 2u8);
+    }
+
+    #[tokio::test]
+    async fn test_example_20_invalid_tag() {
+        let is = Example20::read(&mut Cursor::new(vec![7u8])).await;
+        assert!(matches!(
+            is,
+            Err(CachemError::InvalidDiscriminant { tag: 7u8, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_example_21_old_data() {
+        let mut buf = Vec::new();
+        Example21V1 { id: 1u32 }.write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![1, 0, 0, 0, 4, 0, 0, 0, 1]);
+
+        let is = Example21V2::read(&mut Cursor::new(buf)).await.unwrap();
+        let expected = Example21V2 {
+            id: 1u32,
+            name: String::new(),
+            cached: 0u64,
+            limit: 10u32,
+        };
+        assert_eq!(is, expected);
+    }
+
+    #[tokio::test]
+    async fn test_example_21_new_data() {
+        let e = vec![
+            Example21V2 { id: 1u32, name: "a".into(), cached: 5u64, limit: 2u32 },
+            Example21V2 { id: 2u32, name: "b".into(), cached: 6u64, limit: 3u32 },
+        ];
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+
+        // old readers skip the fields they do not know
+        let is = Vec::<Example21V1>::read(&mut Cursor::new(buf.clone())).await.unwrap();
+        assert_eq!(is, vec![Example21V1 { id: 1u32 }, Example21V1 { id: 2u32 }]);
+
+        let is = Vec::<Example21V2>::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is[1], Example21V2 { id: 2u32, name: "b".into(), cached: 0u64, limit: 3u32 });
+    }
+
+    #[tokio::test]
+    async fn test_example_22_skip() {
+        let mut buf = Vec::new();
+        Example22 { id: 1u32, cached: vec![1u32] }.write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![0, 0, 0, 1]);
+
+        let is = Example22::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, Example22 { id: 1u32, cached: Vec::new() });
+    }
+
+    #[tokio::test]
+    async fn test_example_23_rw() {
+        let e = Example23 { big: 300u64, signed: -2i32 };
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![0xAC, 0x02, 3]);
+
+        let is = Example23::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, e);
+
+        let e = Example23 { big: u64::MAX, signed: i32::MIN };
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+        let is = Example23::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, e);
+    }
+
+    #[tokio::test]
+    async fn test_example_24_rw() {
+        let e = Example24(vec![1u8, 2u8, 3u8]);
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![3, 1, 2, 3]);
+
+        let is = Example24::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, e);
+
+        // the length is larger than the data
+        let is = Example24::read(&mut Cursor::new(vec![5, 1])).await;
+        assert!(is.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_example_25_rw() {
+        let e = Example25 {
+            id:      VarU32(1u32),
+            offset:  VarI64(-2i64),
+            entries: vec![5u8],
+        };
+
+        let mut buf = Vec::new();
+        e.write(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![1, 3, 0, 0, 0, 1, 5]);
+        let is = Example25::read(&mut Cursor::new(buf)).await.unwrap();
+        assert_eq!(is, e);
+
+        let mut buf = Vec::new();
+        LengthEncoding::Compact.encode(&e, &mut buf).await.unwrap();
+        assert_eq!(buf, vec![1, 3, 1, 5]);
+        let is: Example25 = LengthEncoding::Compact.decode(&buf).await.unwrap();
+        assert_eq!(is, e);
+    }
+
+    #[tokio::test]
+    async fn test_example_26_same_type() {
+        let cache = Example26Cache::default();
+        let e = Example26 { id: 1u32, group: 5u32, name: "a".into() };
+        cache.insert(e.clone()).await;
+
+        assert_eq!(Get2::<u32, Example26>::get(&cache, 1u32).await, Some(e.clone()));
+        assert_eq!(Get2::<String, Example26>::get(&cache, "a".into()).await, Some(e.clone()));
+        assert_eq!(cache.get_by_group(&5u32).await, Some(e.clone()));
+        assert_eq!(cache.get_by_group(&1u32).await, None);
+        assert_eq!(cache.get_by_name(&"a".into()).await, Some(e));
+
+        cache.remove(&1u32).await;
+        assert_eq!(cache.get_by_group(&5u32).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_example_27_legacy() {
+        let mut buf = Vec::new();
+        Example27V0 { id: 1u32, values: vec![2u8] }.write(&mut buf).await.unwrap();
+        Example27V0 { id: 3u32, values: Vec::new() }.write(&mut buf).await.unwrap();
+
+        // unframed data is read field by field as version 0
+        let mut buf = Cursor::new(buf);
+        let is = Example27V1::read_legacy(&mut buf).await.unwrap();
+        assert_eq!(is, Example27V1 { id: 1u32, values: vec![2u8], name: String::new(), limit: 0u32 });
+        let is = Example27V1::read_legacy(&mut buf).await.unwrap();
+        assert_eq!(is, Example27V1 { id: 3u32, values: Vec::new(), name: String::new(), limit: 0u32 });
+    }
+
+    #[tokio::test]
+    async fn test_example_27_untrusted_length() {
+        // version 1 with a length of 4 GiB, but only 4 bytes of data
+        let buf = vec![1, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 1];
+        let is = Example27V1::read(&mut Cursor::new(buf)).await;
+        assert!(matches!(is, Err(CachemError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_example_28_shared_type() {
+        // both indexes are u64, so they are only available by their field
+        let cache = Example28Cache::default();
+        let a = Example28 { id: 1u32, parent: 10u64, child: 20u64 };
+        let b = Example28 { id: 2u32, parent: 20u64, child: 30u64 };
+        cache.insert(a.clone()).await;
+        cache.insert(b.clone()).await;
+
+        assert_eq!(cache.get_by_parent(&10u64).await, Some(a.clone()));
+        assert_eq!(cache.get_by_parent(&20u64).await, Some(b.clone()));
+        assert_eq!(cache.get_by_child(&20u64).await, Some(a.clone()));
+        assert_eq!(cache.get_by_child(&30u64).await, Some(b.clone()));
+        assert_eq!(cache.get_by_child(&10u64).await, None);
+        assert_eq!(Get2::<u32, Example28>::get(&cache, 2u32).await, Some(b));
+
+        cache.remove(&2u32).await;
+        assert_eq!(cache.get_by_parent(&20u64).await, None);
+        assert_eq!(cache.get_by_child(&20u64).await, Some(a));
+    }
 }